@@ -0,0 +1,493 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use rpodlib::{iPod, ExtractOptions, ReplayGain};
+
+/// Manage the music on an iPod.
+#[derive(Parser)]
+#[command(name = "rpod", version)]
+struct Cli {
+    /// Where the iPod is mounted, found automatically when only one is connected
+    #[arg(short, long, global = true)]
+    mount: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the model, FWID, checksum scheme and free space
+    Info,
+    /// List the tracks, playlists or albums
+    Ls { what: Listing },
+    /// Copy audio files to the device
+    Add {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Which ReplayGain value becomes the Sound Check adjustment
+        #[arg(long, value_enum, default_value_t)]
+        replay_gain: Gain,
+    },
+    /// Remove tracks and their files
+    Rm {
+        #[arg(required = true)]
+        track_ids: Vec<u32>,
+    },
+    /// Create and edit playlists
+    #[command(subcommand)]
+    Playlist(PlaylistCommand),
+    /// Check the database checksums
+    Verify,
+    /// Look for problems with the database and the music files
+    Fsck {
+        /// Fix what can be fixed and save the database
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Print the records of the database with their offsets and fields
+    Dump {
+        /// An iTunesDB file to print instead of the one on the device
+        file: Option<PathBuf>,
+        /// Print the database as JSON instead
+        #[arg(long, conflicts_with = "file")]
+        json: bool,
+    },
+    /// Print the fields that differ between two iTunesDB files
+    Diff { a: PathBuf, b: PathBuf },
+    /// Copy the tracks off the device into an organized folder
+    Extract {
+        dest: PathBuf,
+        /// Only the tracks of this playlist
+        #[arg(long)]
+        playlist: Option<String>,
+        /// Path of each file, eg. "{artist}/{album}/{track} {title}"
+        #[arg(long)]
+        template: Option<String>,
+        /// Copy files without tags as they are
+        #[arg(long)]
+        no_retag: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum PlaylistCommand {
+    /// Create an empty playlist
+    Create { name: String },
+    /// Add tracks to a playlist, given by name or id
+    Add {
+        playlist: String,
+        #[arg(required = true)]
+        track_ids: Vec<u32>,
+    },
+    /// Remove tracks from a playlist, or the playlist when no tracks are given
+    Rm {
+        playlist: String,
+        track_ids: Vec<u32>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Listing {
+    Tracks,
+    Playlists,
+    Albums,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Gain {
+    #[default]
+    Track,
+    Album,
+}
+
+impl From<Gain> for ReplayGain {
+    fn from(gain: Gain) -> Self {
+        match gain {
+            Gain::Track => ReplayGain::Track,
+            Gain::Album => ReplayGain::Album,
+        }
+    }
+}
+
+fn find_mount(mount: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    if let Some(mount) = mount {
+        return Ok(mount);
+    }
+
+    let mut devices = rpodlib::discover()?;
+    match devices.len() {
+        0 => bail!("no iPod found, pass --mount"),
+        1 => Ok(devices.remove(0).path),
+        _ => bail!("more than one iPod found, pass --mount"),
+    }
+}
+
+/// The id of the playlist with the given name, or the given id in hex.
+fn find_playlist(ipod: &iPod, playlist: &str) -> anyhow::Result<u64> {
+    let playlists = ipod.playlists()?;
+    if let Some(found) = playlists
+        .iter()
+        .find(|found| found.name.as_deref() == Some(playlist))
+    {
+        return Ok(found.id);
+    }
+
+    match u64::from_str_radix(playlist, 16) {
+        Ok(id) if playlists.iter().any(|found| found.id == id) => Ok(id),
+        _ => bail!("no playlist called \"{playlist}\""),
+    }
+}
+
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: path is NUL terminated and stat is plain data statvfs fills in
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    match unsafe { libc::statvfs(path.as_ptr(), &mut stat) } {
+        0 => Some(stat.f_bavail as u64 * stat.f_frsize as u64),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn duration(ms: u32) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn info(ipod: &iPod) -> anyhow::Result<()> {
+    let model = match ipod.model() {
+        Some(model) => {
+            let mut text = format!("{:?} {}G", model.model, model.generation);
+            if let Some(capacity_mb) = model.capacity_mb {
+                text.push_str(&format!(", {} GB", capacity_mb / 1000));
+            }
+            if let Some(color) = model.color {
+                text.push_str(&format!(", {color:?}"));
+            }
+            text
+        }
+        None => "unknown".to_string(),
+    };
+    let verification = ipod.verify_database()?;
+    let free = match free_space(ipod.path()) {
+        Some(bytes) => format!("{:.1} GB", bytes as f64 / 1e9),
+        None => "unknown".to_string(),
+    };
+
+    println!("mount\t{}", ipod.path().display());
+    println!("model\t{model}");
+    println!("fwid\t{}", ipod.fwid().unwrap_or("unknown"));
+    println!(
+        "checksum\t{:?} ({})",
+        verification.scheme,
+        match verification.is_valid() {
+            true => "valid",
+            false => "invalid",
+        }
+    );
+    println!("tracks\t{}", ipod.tracks()?.len());
+    println!("free\t{free}");
+
+    Ok(())
+}
+
+fn list(ipod: &iPod, what: Listing) -> anyhow::Result<()> {
+    match what {
+        Listing::Tracks => {
+            for track in ipod.tracks()? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    track.id,
+                    track.artist.unwrap_or_default(),
+                    track.album.unwrap_or_default(),
+                    track.title.unwrap_or_default(),
+                    duration(track.duration_ms)
+                );
+            }
+        }
+        Listing::Playlists => {
+            for playlist in ipod.playlists()? {
+                println!(
+                    "{:016X}\t{}\t{}{}",
+                    playlist.id,
+                    playlist.track_ids.len(),
+                    playlist.name.unwrap_or_default(),
+                    if playlist.is_master { " (master)" } else { "" }
+                );
+            }
+        }
+        Listing::Albums => {
+            for album in ipod.albums()? {
+                println!(
+                    "{}\t{}\t{}",
+                    album.artist,
+                    album.album,
+                    album.track_ids.len()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn playlist(ipod: &mut iPod, command: PlaylistCommand) -> anyhow::Result<()> {
+    match command {
+        PlaylistCommand::Create { name } => {
+            let mut transaction = ipod.transaction();
+            let playlist_id = transaction.create_playlist(&name)?;
+            transaction.commit()?;
+            println!("{playlist_id:016X}");
+        }
+        PlaylistCommand::Add {
+            playlist,
+            track_ids,
+        } => {
+            let playlist_id = find_playlist(ipod, &playlist)?;
+            let mut transaction = ipod.transaction();
+            for track_id in track_ids {
+                transaction.add_to_playlist(playlist_id, track_id)?;
+            }
+            transaction.commit()?;
+        }
+        PlaylistCommand::Rm {
+            playlist,
+            track_ids,
+        } => {
+            let playlist_id = find_playlist(ipod, &playlist)?;
+            let mut transaction = ipod.transaction();
+            if track_ids.is_empty() {
+                transaction.delete_playlist(playlist_id)?;
+            }
+            for track_id in track_ids {
+                transaction.remove_from_playlist(playlist_id, track_id)?;
+            }
+            transaction.commit()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints `text`. Piping into head closes stdout early, that isn't an error.
+fn print(text: &str) -> io::Result<()> {
+    match write!(io::stdout(), "{text}") {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn run(cli: Cli) -> anyhow::Result<ExitCode> {
+    // Commands on files rather than a device
+    match &cli.command {
+        Command::Dump {
+            file: Some(file), ..
+        } => {
+            print(&rpodlib::dump_itunesdb(&read(file)?)?)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Command::Diff { a, b } => {
+            let diff = rpodlib::diff_itunesdb(&read(a)?, &read(b)?)?;
+            print(&diff)?;
+            return Ok(match diff.is_empty() {
+                true => ExitCode::SUCCESS,
+                false => ExitCode::FAILURE,
+            });
+        }
+        _ => {}
+    }
+
+    let mut ipod = iPod::open(find_mount(cli.mount)?)?;
+
+    match cli.command {
+        Command::Info => info(&ipod)?,
+        Command::Ls { what } => list(&ipod, what)?,
+        Command::Add { files, replay_gain } => {
+            let mut transaction = ipod.transaction();
+            let mut added = Vec::new();
+            for file in &files {
+                added.push((transaction.add_track(file, replay_gain.into())?, file));
+            }
+            transaction.commit()?;
+
+            for (track_id, file) in added {
+                println!("{track_id}\t{}", file.display());
+            }
+        }
+        Command::Rm { track_ids } => {
+            let mut transaction = ipod.transaction();
+            for track_id in track_ids {
+                transaction.remove_track(track_id)?;
+            }
+            transaction.commit()?;
+        }
+        Command::Playlist(command) => playlist(&mut ipod, command)?,
+        Command::Verify => {
+            let verification = ipod.verify_database()?;
+            println!("scheme\t{:?}", verification.scheme);
+            println!("hash58\t{:?}", verification.hash58);
+            println!("hash72\t{:?}", verification.hash72);
+            println!("hashAB\t{:?}", verification.hash_ab);
+
+            if !verification.is_valid() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Fsck { repair } => {
+            let problems = match repair {
+                true => ipod.repair()?,
+                false => ipod.check(),
+            };
+            for problem in &problems {
+                println!("{problem}");
+            }
+
+            if !problems.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Dump { json: true, .. } => print(&format!("{}\n", ipod.export_json()?))?,
+        Command::Dump { .. } => print(&ipod.dump_database()?)?,
+        Command::Diff { .. } => unreachable!("handled without a device"),
+        Command::Extract {
+            dest,
+            playlist,
+            template,
+            no_retag,
+        } => {
+            let mut options = ExtractOptions {
+                retag: !no_retag,
+                ..Default::default()
+            };
+            if let Some(template) = template {
+                options.template = template;
+            }
+
+            let report = match playlist {
+                Some(name) => ipod.extract_playlist(&name, &dest, &options)?,
+                None => ipod.extract(&dest, &options)?,
+            };
+            for path in report.copied.iter().chain(&report.playlists) {
+                println!("{}", path.display());
+            }
+            for track_id in report.missing {
+                eprintln!("the file of track {track_id} is missing");
+            }
+            for path in report.not_retagged {
+                eprintln!("{} has no tags and couldn't be retagged", path.display());
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("rpod: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, process::ExitCode};
+
+    use clap::{CommandFactory, Parser};
+    use rpodlib::iPod;
+
+    use super::Cli;
+
+    /// A mount with the sample database on it, set up like the library's tests do.
+    fn device() -> tempfile::TempDir {
+        let mount = tempfile::tempdir().unwrap();
+        fs::create_dir_all(mount.path().join("iPod_Control/iTunes")).unwrap();
+        fs::create_dir_all(mount.path().join("iPod_Control/Device")).unwrap();
+
+        // The sample's playlist entries have their track id where the timestamp goes
+        let mut itunesdb = include_bytes!("../db/itunesdb/sample/iTunesDB").to_vec();
+        let mut at = 0;
+        while let Some(found) = itunesdb[at..].windows(4).position(|magic| magic == b"mhip") {
+            at += found;
+            itunesdb.copy_within(at + 0x1C..at + 0x20, at + 0x18);
+            itunesdb[at + 0x1C..at + 0x20].fill(0);
+            at += 4;
+        }
+
+        fs::write(mount.path().join("iPod_Control/iTunes/iTunesDB"), itunesdb).unwrap();
+        fs::write(
+            mount.path().join("iPod_Control/Device/SysInfoExtended"),
+            include_bytes!("../db/itunesdb/sample/ExtendedSysInfoXml"),
+        )
+        .unwrap();
+        mount
+    }
+
+    fn rpod(mount: &Path, args: &[&str]) -> ExitCode {
+        let mount = mount.to_str().unwrap();
+        let cli = Cli::try_parse_from(["rpod", "--mount", mount].iter().chain(args)).unwrap();
+        super::run(cli).unwrap()
+    }
+
+    #[test]
+    fn cli() {
+        super::Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn add_list_and_remove() {
+        let mount = device();
+        let source = tempfile::tempdir().unwrap();
+
+        // 100 frames of silence, MPEG-1 layer III at 128 kbps and 44.1 kHz
+        let file = source.path().join("silence.mp3");
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x40];
+        frame.resize(417, 0);
+        fs::write(&file, frame.repeat(100)).unwrap();
+
+        let tracks = || iPod::open(mount.path()).unwrap().tracks().unwrap();
+        let before = tracks();
+
+        assert_eq!(
+            rpod(mount.path(), &["add", file.to_str().unwrap()]),
+            ExitCode::SUCCESS
+        );
+        let added = tracks()
+            .into_iter()
+            .find(|track| !before.contains(track))
+            .expect("no track was added");
+        let location = added.location.as_deref().unwrap().replace(':', "/");
+        let copied = mount.path().join(location.trim_start_matches('/'));
+        assert!(copied.exists());
+
+        assert_eq!(rpod(mount.path(), &["ls", "tracks"]), ExitCode::SUCCESS);
+
+        assert_eq!(
+            rpod(mount.path(), &["rm", &added.id.to_string()]),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(tracks(), before);
+        assert!(!copied.exists());
+    }
+}
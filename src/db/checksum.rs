@@ -0,0 +1,388 @@
+use std::ops::Range;
+
+use anyhow::{bail, ensure};
+
+use super::{
+    hash58,
+    hash72::{self, HashInfo},
+};
+
+const DATABASE_ID: Range<usize> = 0x18..0x20;
+const HASHING_SCHEME: Range<usize> = 0x22..0x24;
+const PADDING_0X32: Range<usize> = 0x32..0x46;
+const HASH58: Range<usize> = 0x58..0x6C;
+const HASH72: Range<usize> = 0x72..0xA0;
+const HASHAB: Range<usize> = 0xAB..0xE4;
+
+/// Which checksum the device checks the iTunesDB with, from `hashing_scheme` in the mhbd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashingScheme {
+    /// Older devices don't check the database at all
+    None,
+    /// SHA1 HMAC keyed by the FWID (classic, nano 3G and earlier with newer firmware)
+    Hash58,
+    /// AES signature keyed by the device's HashInfo (nano 5G, iPhone OS devices)
+    Hash72,
+    /// Proprietary scheme of the nano 6G/7G
+    HashAB,
+    Unknown(u16),
+}
+
+impl HashingScheme {
+    pub(crate) fn from_u16(scheme: u16) -> Self {
+        match scheme {
+            0 => HashingScheme::None,
+            1 => HashingScheme::Hash58,
+            2 => HashingScheme::Hash72,
+            3 => HashingScheme::HashAB,
+            scheme => HashingScheme::Unknown(scheme),
+        }
+    }
+
+    pub(crate) fn as_u16(&self) -> u16 {
+        match self {
+            HashingScheme::None => 0,
+            HashingScheme::Hash58 => 1,
+            HashingScheme::Hash72 => 2,
+            HashingScheme::HashAB => 3,
+            HashingScheme::Unknown(scheme) => *scheme,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashStatus {
+    Valid,
+    /// The field is all zeros
+    Missing,
+    /// The stored hash doesn't match the one computed from the database
+    Mismatch,
+    /// The expected hash can't be computed, eg. hash72 without a HashInfo
+    Unverifiable,
+}
+
+/// Computes one kind of iTunesDB checksum. hash58 and hash72 are built in, other
+/// schemes (hashAB on the nano 6G/7G) can be provided by registering a signer with
+/// `iPod::register_signer`.
+pub trait DatabaseSigner {
+    /// The hashing scheme this signer computes.
+    fn scheme(&self) -> HashingScheme;
+
+    /// Computes the checksum of `itunesdb`, whose checksum fields and database id are
+    /// already zeroed. `fwid` is the device's FireWire GUID as 16 hex digits. The
+    /// result must be exactly as long as the scheme's field: 20 bytes for hash58,
+    /// 46 for hash72 and 57 for hashAB.
+    fn sign(&self, fwid: &str, itunesdb: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+pub(crate) struct Hash58Signer;
+
+impl DatabaseSigner for Hash58Signer {
+    fn scheme(&self) -> HashingScheme {
+        HashingScheme::Hash58
+    }
+
+    fn sign(&self, fwid: &str, itunesdb: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(hash58::generate_hash58(fwid, itunesdb)
+            .map_err(anyhow::Error::msg)?
+            .to_vec())
+    }
+}
+
+pub(crate) struct Hash72Signer(pub HashInfo);
+
+impl DatabaseSigner for Hash72Signer {
+    fn scheme(&self) -> HashingScheme {
+        HashingScheme::Hash72
+    }
+
+    fn sign(&self, _fwid: &str, itunesdb: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(hash72::generate_hash72(&self.0, itunesdb).to_vec())
+    }
+}
+
+/// State of every checksum stored in an iTunesDB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseVerification {
+    pub scheme: HashingScheme,
+    pub hash58: HashStatus,
+    pub hash72: HashStatus,
+    pub hash_ab: HashStatus,
+}
+
+impl DatabaseVerification {
+    /// Whether the hash the device checks is present and correct.
+    pub fn is_valid(&self) -> bool {
+        match self.scheme {
+            HashingScheme::None => true,
+            HashingScheme::Hash58 => self.hash58 == HashStatus::Valid,
+            HashingScheme::Hash72 => self.hash72 == HashStatus::Valid,
+            HashingScheme::HashAB => self.hash_ab == HashStatus::Valid,
+            HashingScheme::Unknown(_) => false,
+        }
+    }
+}
+
+pub(crate) fn hashing_scheme(buf: &[u8]) -> anyhow::Result<HashingScheme> {
+    ensure!(buf.len() >= 0xF4, "database is too short");
+
+    Ok(HashingScheme::from_u16(u16::from_le_bytes([
+        buf[HASHING_SCHEME.start],
+        buf[HASHING_SCHEME.start + 1],
+    ])))
+}
+
+/// Where the checksum of a hashing scheme is stored.
+fn field(scheme: HashingScheme) -> Option<Range<usize>> {
+    match scheme {
+        HashingScheme::Hash58 => Some(HASH58),
+        HashingScheme::Hash72 => Some(HASH72),
+        HashingScheme::HashAB => Some(HASHAB),
+        HashingScheme::None | HashingScheme::Unknown(_) => None,
+    }
+}
+
+/// The bytes hash58 is computed over: the database with the database id, the
+/// 20 bytes at 0x32 and the hash itself zeroed.
+pub(crate) fn hash58_input(buf: &[u8]) -> Vec<u8> {
+    let mut buf = buf.to_vec();
+
+    buf[DATABASE_ID].fill(0);
+    buf[PADDING_0X32].fill(0);
+    buf[HASH58].fill(0);
+    buf
+}
+
+/// The bytes hash72 is computed over: like hash58, with hash72 zeroed as well.
+pub(crate) fn hash72_input(buf: &[u8]) -> Vec<u8> {
+    let mut buf = hash58_input(buf);
+
+    buf[HASH72].fill(0);
+    buf
+}
+
+/// The bytes hashAB is computed over: like hash72, with hashAB zeroed as well.
+pub(crate) fn hash_ab_input(buf: &[u8]) -> Vec<u8> {
+    let mut buf = hash72_input(buf);
+
+    buf[HASHAB].fill(0);
+    buf
+}
+
+fn input(scheme: HashingScheme, buf: &[u8]) -> Vec<u8> {
+    match scheme {
+        HashingScheme::Hash72 => hash72_input(buf),
+        HashingScheme::HashAB => hash_ab_input(buf),
+        _ => hash58_input(buf),
+    }
+}
+
+/// Runs the signer registered for `scheme`, `None` if there isn't one. Fails for
+/// schemes keyed by the FWID when it isn't known.
+fn expected(
+    scheme: HashingScheme,
+    buf: &[u8],
+    fwid: Option<&str>,
+    signers: &[Box<dyn DatabaseSigner>],
+) -> Option<anyhow::Result<Vec<u8>>> {
+    let signer = signers.iter().find(|signer| signer.scheme() == scheme)?;
+    let field = field(scheme)?;
+
+    // hash72 is keyed by the HashInfo instead
+    let fwid = match fwid {
+        Some(fwid) => fwid,
+        None if scheme == HashingScheme::Hash72 => "",
+        None => {
+            return Some(Err(anyhow::anyhow!(
+                "{scheme:?} needs the FireWire GUID of the iPod, which couldn't be found"
+            )))
+        }
+    };
+
+    Some(signer.sign(fwid, &input(scheme, buf)).and_then(|hash| {
+        ensure!(
+            hash.len() == field.len(),
+            "{scheme:?} signer returned {} bytes, expected {}",
+            hash.len(),
+            field.len()
+        );
+        Ok(hash)
+    }))
+}
+
+/// Fills in the checksum required by the database's hashing scheme using the
+/// matching signer.
+pub(crate) fn sign(
+    buf: &mut [u8],
+    fwid: Option<&str>,
+    signers: &[Box<dyn DatabaseSigner>],
+) -> anyhow::Result<()> {
+    let scheme = hashing_scheme(buf)?;
+
+    let Some(field) = field(scheme) else {
+        ensure!(
+            scheme == HashingScheme::None,
+            "unsupported hashing scheme {scheme:?}"
+        );
+        return Ok(());
+    };
+
+    match expected(scheme, buf, fwid, signers) {
+        Some(hash) => buf[field].copy_from_slice(&hash?),
+        None => bail!("database requires {scheme:?} but no signer is registered for it"),
+    }
+
+    Ok(())
+}
+
+/// Recovers the device's HashInfo from a database signed with hash72, `None` if the
+/// database uses another hashing scheme.
+pub(crate) fn extract_hash_info(buf: &[u8], fwid: &str) -> anyhow::Result<Option<HashInfo>> {
+    if hashing_scheme(buf)? != HashingScheme::Hash72 {
+        return Ok(None);
+    }
+
+    let hash72: [u8; 46] = buf[HASH72].try_into().unwrap();
+    let hash_info =
+        HashInfo::from_database(fwid, &hash72_input(buf), &hash72).map_err(anyhow::Error::msg)?;
+
+    Ok(Some(hash_info))
+}
+
+fn compare(stored: &[u8], expected: Option<Vec<u8>>) -> HashStatus {
+    if stored.iter().all(|byte| *byte == 0) {
+        return HashStatus::Missing;
+    }
+
+    match expected {
+        Some(expected) if expected == stored => HashStatus::Valid,
+        Some(_) => HashStatus::Mismatch,
+        None => HashStatus::Unverifiable,
+    }
+}
+
+/// Recomputes every checksum the database carries that one of `signers` can compute,
+/// and compares it to the stored one.
+pub(crate) fn verify(
+    buf: &[u8],
+    fwid: Option<&str>,
+    signers: &[Box<dyn DatabaseSigner>],
+) -> anyhow::Result<DatabaseVerification> {
+    let scheme = hashing_scheme(buf)?;
+
+    let status = |scheme: HashingScheme| {
+        let expected = expected(scheme, buf, fwid, signers).and_then(Result::ok);
+        compare(&buf[field(scheme).unwrap()], expected)
+    };
+
+    Ok(DatabaseVerification {
+        scheme,
+        hash58: status(HashingScheme::Hash58),
+        hash72: status(HashingScheme::Hash72),
+        hash_ab: status(HashingScheme::HashAB),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::{DatabaseSigner, Hash58Signer, HashStatus, HashingScheme};
+
+    const FWID: &str = "000A270013E10993";
+
+    fn signers() -> Vec<Box<dyn DatabaseSigner>> {
+        vec![Box::new(Hash58Signer)]
+    }
+
+    /// Stand in for a native hashAB implementation
+    struct FakeHashAB;
+
+    impl DatabaseSigner for FakeHashAB {
+        fn scheme(&self) -> HashingScheme {
+            HashingScheme::HashAB
+        }
+
+        fn sign(&self, fwid: &str, itunesdb: &[u8]) -> anyhow::Result<Vec<u8>> {
+            let mut hash = Sha1::new_with_prefix(fwid)
+                .chain_update(itunesdb)
+                .finalize()
+                .to_vec();
+            hash.resize(57, 0xAB);
+            Ok(hash)
+        }
+    }
+
+    #[test]
+    fn sign_with_registered_signer() {
+        let mut buf = include_bytes!("./itunesdb/sample/iTunesDB").to_vec();
+        buf[super::HASHING_SCHEME].copy_from_slice(&3u16.to_le_bytes());
+
+        assert!(super::sign(&mut buf, Some(FWID), &signers()).is_err());
+
+        let mut signers = signers();
+        signers.push(Box::new(FakeHashAB));
+        super::sign(&mut buf, Some(FWID), &signers).unwrap();
+
+        let verification = super::verify(&buf, Some(FWID), &signers).unwrap();
+        assert_eq!(verification.scheme, HashingScheme::HashAB);
+        assert_eq!(verification.hash_ab, HashStatus::Valid);
+        assert!(verification.is_valid());
+
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+        assert_eq!(
+            super::verify(&buf, Some(FWID), &signers).unwrap().hash_ab,
+            HashStatus::Mismatch
+        );
+    }
+
+    #[test]
+    fn signer_with_wrong_length_is_rejected() {
+        struct Short;
+
+        impl DatabaseSigner for Short {
+            fn scheme(&self) -> HashingScheme {
+                HashingScheme::Hash58
+            }
+
+            fn sign(&self, _fwid: &str, _itunesdb: &[u8]) -> anyhow::Result<Vec<u8>> {
+                Ok(vec![0; 4])
+            }
+        }
+
+        let mut buf = include_bytes!("./itunesdb/sample/iTunesDB").to_vec();
+        let signers: Vec<Box<dyn DatabaseSigner>> = vec![Box::new(Short)];
+
+        assert!(super::sign(&mut buf, Some(FWID), &signers).is_err());
+    }
+
+    #[test]
+    fn verify_hash58() {
+        let mut buf = include_bytes!("./itunesdb/sample/iTunesDB").to_vec();
+
+        let verification = super::verify(&buf, Some(FWID), &signers()).unwrap();
+        assert_eq!(verification.scheme, HashingScheme::Hash58);
+        assert_eq!(verification.hash58, HashStatus::Valid);
+        assert_eq!(verification.hash72, HashStatus::Missing);
+        assert!(verification.is_valid());
+
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+        let verification = super::verify(&buf, Some(FWID), &signers()).unwrap();
+        assert_eq!(verification.hash58, HashStatus::Mismatch);
+        assert!(!verification.is_valid());
+
+        buf[super::HASH58].fill(0);
+        let verification = super::verify(&buf, Some(FWID), &signers()).unwrap();
+        assert_eq!(verification.hash58, HashStatus::Missing);
+    }
+
+    #[test]
+    fn verify_without_fwid() {
+        let buf = include_bytes!("./itunesdb/sample/iTunesDB");
+
+        let verification = super::verify(buf, None, &signers()).unwrap();
+        assert_eq!(verification.hash58, HashStatus::Unverifiable);
+    }
+}
@@ -26,7 +26,7 @@ fn generate_hash(fwid: &[u8; 8], itunesdb: &[u8]) -> [u8; 20] {
         *b ^= 0x36;
     }
     let mut sha = Sha1::new();
-    sha.update(&key);
+    sha.update(key);
     sha.update(itunesdb);
     let hash1 = sha.finalize_reset(); // 20 bytes
 
@@ -34,8 +34,8 @@ fn generate_hash(fwid: &[u8; 8], itunesdb: &[u8]) -> [u8; 20] {
     for b in &mut key {
         *b ^= 0x36 ^ 0x5C; // flip 0x36 → 0x5c
     }
-    sha.update(&key);
-    sha.update(&hash1);
+    sha.update(key);
+    sha.update(hash1);
     let result = sha.finalize();
 
     let mut out = [0u8; 20];
@@ -56,7 +56,7 @@ fn generate_key(fwid: &[u8; 8], key_out: &mut [u8; 64]) {
         let lo = (lcm & 0xFF) as u8;
 
         let base = i * 4;
-        y[base + 0] = TABLE1[hi as usize].wrapping_mul(0xB5).wrapping_sub(3);
+        y[base] = TABLE1[hi as usize].wrapping_mul(0xB5).wrapping_sub(3);
         y[base + 1] = TABLE2[hi as usize].wrapping_mul(0xB7).wrapping_add(0x49);
         y[base + 2] = TABLE1[lo as usize].wrapping_mul(0xB5).wrapping_sub(3);
         y[base + 3] = TABLE2[lo as usize].wrapping_mul(0xB7).wrapping_add(0x49);
@@ -69,8 +69,8 @@ fn generate_key(fwid: &[u8; 8], key_out: &mut [u8; 64]) {
 
     // ---- SHA1(fixed ‖ y) ----------------------------------------------------------
     let mut sha = Sha1::new();
-    sha.update(&FIXED);
-    sha.update(&y);
+    sha.update(FIXED);
+    sha.update(y);
     let digest = sha.finalize();
 
     // Copy the 20-byte digest to the beginning of the 64-byte key buffer.
@@ -0,0 +1,179 @@
+#![allow(unused)]
+
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    Aes128,
+};
+use sha1::{Digest, Sha1};
+
+pub(crate) const HASH_INFO_LEN: usize = 54;
+
+const HASH_INFO_HEADER: &[u8; 6] = b"HASHv0";
+
+/// Fixed key the iPod firmware decrypts hash72 signatures with
+const AES_KEY: [u8; 16] = [
+    0x61, 0x8C, 0xA1, 0x0D, 0xC7, 0xF5, 0x7F, 0xD3, 0xB4, 0x72, 0x3E, 0x08, 0x15, 0x74, 0x63, 0xD7,
+];
+
+/// Contents of iPod_Control/Device/HashInfo. The IV and random bytes are specific to
+/// each device and can only be learned from a database iTunes has signed for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HashInfo {
+    pub uuid: [u8; 20], // device FWID, zero padded
+    pub rndpart: [u8; 12],
+    pub iv: [u8; 16],
+}
+
+impl HashInfo {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < HASH_INFO_LEN || &bytes[..6] != HASH_INFO_HEADER {
+            return Err("not a HashInfo file");
+        }
+
+        Ok(HashInfo {
+            uuid: bytes[6..26].try_into().unwrap(),
+            rndpart: bytes[26..38].try_into().unwrap(),
+            iv: bytes[38..54].try_into().unwrap(),
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; HASH_INFO_LEN] {
+        let mut bytes = [0u8; HASH_INFO_LEN];
+
+        bytes[..6].copy_from_slice(HASH_INFO_HEADER);
+        bytes[6..26].copy_from_slice(&self.uuid);
+        bytes[26..38].copy_from_slice(&self.rndpart);
+        bytes[38..54].copy_from_slice(&self.iv);
+        bytes
+    }
+
+    /// Recovers the HashInfo from a database carrying a valid hash72. `itunesdb` must
+    /// have its hash fields zeroed the same way as for `generate_hash72`.
+    pub(crate) fn from_database(
+        fwid_hex: &str,
+        itunesdb: &[u8],
+        hash72: &[u8; 46],
+    ) -> Result<Self, &'static str> {
+        if hash72[..2] != [0x01, 0x00] {
+            return Err("database has no hash72 signature");
+        }
+
+        let rndpart: [u8; 12] = hash72[2..14].try_into().unwrap();
+        let plaintext = plaintext(itunesdb, &rndpart);
+
+        // CBC: first ciphertext block = E(P0 ^ IV), so IV = D(C0) ^ P0
+        let cipher = Aes128::new(GenericArray::from_slice(&AES_KEY));
+        let mut block = GenericArray::clone_from_slice(&hash72[14..30]);
+        cipher.decrypt_block(&mut block);
+
+        let mut iv = [0u8; 16];
+        for (i, byte) in iv.iter_mut().enumerate() {
+            *byte = block[i] ^ plaintext[i];
+        }
+
+        Ok(HashInfo {
+            uuid: fwid_to_uuid(fwid_hex)?,
+            rndpart,
+            iv,
+        })
+    }
+}
+
+fn fwid_to_uuid(fwid_hex: &str) -> Result<[u8; 20], &'static str> {
+    if fwid_hex.len() != 16 {
+        return Err("FWID must be 16 chars");
+    }
+
+    let mut uuid = [0u8; 20];
+    for (j, chunk) in fwid_hex.as_bytes().chunks(2).enumerate() {
+        uuid[j] = u8::from_str_radix(std::str::from_utf8(chunk).unwrap(), 16)
+            .map_err(|_| "FWID contains non-hex digits")?;
+    }
+    Ok(uuid)
+}
+
+/// SHA1 of the database followed by the random bytes, the two blocks that get encrypted.
+fn plaintext(itunesdb: &[u8], rndpart: &[u8; 12]) -> [u8; 32] {
+    let mut plaintext = [0u8; 32];
+
+    plaintext[..20].copy_from_slice(&Sha1::digest(itunesdb));
+    plaintext[20..].copy_from_slice(rndpart);
+    plaintext
+}
+
+pub(crate) fn generate_hash72(hash_info: &HashInfo, itunesdb: &[u8]) -> [u8; 46] {
+    let plaintext = plaintext(itunesdb, &hash_info.rndpart);
+    let cipher = Aes128::new(GenericArray::from_slice(&AES_KEY));
+
+    let mut out = [0u8; 46];
+    out[0] = 0x01;
+    out[1] = 0x00;
+    out[2..14].copy_from_slice(&hash_info.rndpart);
+
+    // --- AES-128-CBC over the two blocks ------------------------------------------
+    let mut chain = hash_info.iv;
+    for (i, chunk) in plaintext.chunks(16).enumerate() {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        for (byte, prev) in block.iter_mut().zip(chain) {
+            *byte ^= prev;
+        }
+        cipher.encrypt_block(&mut block);
+
+        chain.copy_from_slice(&block);
+        out[14 + i * 16..30 + i * 16].copy_from_slice(&block);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashInfo;
+
+    #[test]
+    fn hash_info_bytes_round_trip() {
+        let info = HashInfo {
+            uuid: [0x11; 20],
+            rndpart: [0x22; 12],
+            iv: [0x33; 16],
+        };
+
+        assert_eq!(HashInfo::from_bytes(&info.to_bytes()), Ok(info));
+        assert!(HashInfo::from_bytes(b"HASHv1").is_err());
+    }
+
+    /// Signature of `b"mhbd test database"`, worked out with `openssl enc -aes-128-cbc
+    /// -nopad` over its SHA1 and the random bytes rather than with this module.
+    const SIGNED: [u8; 46] = [
+        0x01, 0x00, 0x72, 0x61, 0x6E, 0x64, 0x6F, 0x6D, 0x20, 0x62, 0x79, 0x74, 0x65, 0x73, 0xE9,
+        0xAC, 0xC3, 0x5B, 0xFB, 0xE1, 0xAB, 0x0B, 0x48, 0x75, 0x70, 0xB7, 0x26, 0x6B, 0x06, 0x2F,
+        0xC2, 0x0F, 0x91, 0xA9, 0x9A, 0x64, 0xAB, 0xF0, 0x7B, 0x08, 0x37, 0x2F, 0x8B, 0xAD, 0x14,
+        0x22,
+    ];
+
+    #[test]
+    fn generate_hash72() {
+        let info = HashInfo {
+            uuid: [0; 20],
+            rndpart: *b"random bytes",
+            iv: *b"per device iv!!!",
+        };
+
+        assert_eq!(super::generate_hash72(&info, b"mhbd test database"), SIGNED);
+    }
+
+    #[test]
+    fn from_database() {
+        let info =
+            HashInfo::from_database("000A270013E10993", b"mhbd test database", &SIGNED).unwrap();
+
+        assert_eq!(&info.rndpart, b"random bytes");
+        assert_eq!(&info.iv, b"per device iv!!!");
+        assert_eq!(
+            info.uuid[..8],
+            [0x00, 0x0A, 0x27, 0x00, 0x13, 0xE1, 0x09, 0x93]
+        );
+        assert_eq!(super::generate_hash72(&info, b"mhbd test database"), SIGNED);
+        assert!(HashInfo::from_database("000A270013E10993", b"", &[0; 46]).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use super::{
+    Album, Data, DataContainer, List, ListContainer, Master, Record, RecordList, Track, Utf16String,
+};
+
+/// Albums are grouped by album name and album artist, falling back to the track artist
+/// for tracks without an album artist (the way iTunes groups them).
+pub(crate) fn track_album_key(track: &Track) -> (String, String) {
+    let album = track.album().unwrap_or_default();
+    let artist = track
+        .album_artist()
+        .filter(|artist| !artist.is_empty())
+        .or_else(|| track.artist())
+        .unwrap_or_default();
+
+    (album, artist)
+}
+
+impl Album {
+    fn new(
+        album_id: u32,
+        persistent_id: u64,
+        key: &(String, String),
+        artist_sort: Option<String>,
+    ) -> Self {
+        let mut children = vec![
+            Record::mhod(DataContainer::new(Data::AlbumInAlbumList(
+                Utf16String::new(&key.0),
+            ))),
+            Record::mhod(DataContainer::new(Data::ArtistInAlbumList(
+                Utf16String::new(&key.1),
+            ))),
+        ];
+
+        if let Some(artist_sort) = artist_sort.filter(|artist_sort| !artist_sort.is_empty()) {
+            children.push(Record::mhod(DataContainer::new(
+                Data::ArtistSortInAlbumList(Utf16String::new(&artist_sort)),
+            )));
+        }
+
+        Album {
+            len: 0,
+            album_id,
+            unk_0x14: persistent_id,
+            unk_0x1C: 2,
+            unk_0x20: 0,
+            children,
+        }
+    }
+
+    fn key(&self) -> (String, String) {
+        let mut key = (String::new(), String::new());
+
+        for child in &self.children {
+            match child {
+                Record::mhod(DataContainer {
+                    data: Data::AlbumInAlbumList(string),
+                    ..
+                }) => key.0 = string.as_string(),
+                Record::mhod(DataContainer {
+                    data: Data::ArtistInAlbumList(string),
+                    ..
+                }) => key.1 = string.as_string(),
+                _ => {}
+            }
+        }
+        key
+    }
+}
+
+/// The 16-bit `album_id` stored in the tracks of album `album_id`, or 0 when it
+/// doesn't fit.
+fn track_album_id(album_id: u32) -> u16 {
+    u16::try_from(album_id).unwrap_or(0)
+}
+
+/// Regenerates the album list (mhsd type 4) from the track list and points the
+/// `album_id` of every track at its mhia, see [`track_album_id`]. Albums that already
+/// existed keep their 64-bit id so it stays stable across saves. A database without an
+/// album list gets one.
+pub(crate) fn rebuild_album_list(master: &mut Master) {
+    let mut known_ids = HashMap::new();
+
+    if let Some(list) = master.list(0x04) {
+        for child in &list.children {
+            if let Record::mhia(album) = child {
+                known_ids.insert(album.key(), album.unk_0x14);
+            }
+        }
+    }
+
+    let mut albums = Vec::<Album>::new();
+    let mut album_index = HashMap::<(String, String), usize>::new();
+
+    if let Some(list) = master.list_mut(0x01) {
+        for child in &mut list.children {
+            let Record::mhit(track) = child else {
+                continue;
+            };
+
+            let key = track_album_key(track);
+            let index = *album_index.entry(key.clone()).or_insert_with(|| {
+                let album_id = albums.len() as u32 + 1;
+                let persistent_id = known_ids.get(&key).copied().unwrap_or_else(rand::random);
+
+                let artist_sort = track.album_artist_sort().or_else(|| track.artist_sort());
+
+                albums.push(Album::new(album_id, persistent_id, &key, artist_sort));
+                albums.len() - 1
+            });
+
+            track.album_id = track_album_id(albums[index].album_id);
+        }
+    }
+
+    let children = albums.into_iter().map(Record::mhia).collect();
+
+    match master.list_mut(0x04) {
+        Some(list) => list.children = children,
+        None => master.children.push(Record::mhsd(ListContainer {
+            len: 0,
+            list: List::Albums(RecordList { children }),
+        })),
+    }
+}
+
+/// Whether the album list holds exactly one album per album of the track list and every
+/// track points at its album, ie. whether `rebuild_album_list` would change anything
+/// but ids.
+pub(crate) fn album_list_is_current(master: &Master) -> bool {
+    let Some(list) = master.list(0x04) else {
+        return false;
+    };
+
+    let albums: Vec<(u32, (String, String))> = list
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            Record::mhia(album) => Some((album.album_id, album.key())),
+            _ => None,
+        })
+        .collect();
+    let album_ids: HashMap<&(String, String), u32> = albums
+        .iter()
+        .map(|(album_id, key)| (key, *album_id))
+        .collect();
+
+    let mut used = HashMap::new();
+    for track in master.tracks() {
+        let key = track_album_key(track);
+        match album_ids.get(&key) {
+            Some(&album_id) if track.album_id == track_album_id(album_id) => {}
+            _ => return false,
+        }
+        used.insert(key, ());
+    }
+
+    // Albums sharing a key or left without tracks are stale too
+    used.len() == albums.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::itunesdb::{io, List, Master, Record, Track},
+        HashingScheme,
+    };
+
+    #[test]
+    fn rebuild_album_list() {
+        let mut root = io::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap();
+        let Record::mhbd(master) = &mut root else {
+            panic!("root is not a master record");
+        };
+
+        super::rebuild_album_list(master);
+
+        let written = io::write_to_buffer(&root);
+        let Record::mhbd(master) = io::read_from_buffer(&written).unwrap() else {
+            panic!("root is not a master record");
+        };
+
+        let albums = &master.list(0x04).expect("no album list").children;
+        let tracks = &master.list(0x01).expect("no track list").children;
+
+        for track in tracks {
+            let Record::mhit(track) = track else {
+                continue;
+            };
+
+            let album = albums
+                .iter()
+                .find_map(|album| match album {
+                    Record::mhia(album) if album.album_id == track.album_id as u32 => Some(album),
+                    _ => None,
+                })
+                .expect("track points at a missing album");
+
+            assert_eq!(album.key(), super::track_album_key(track));
+        }
+
+        let mut keys: Vec<_> = albums
+            .iter()
+            .filter_map(|album| match album {
+                Record::mhia(album) => Some(album.key()),
+                _ => None,
+            })
+            .collect();
+        let count = keys.len();
+        keys.sort();
+        keys.dedup();
+
+        assert_eq!(keys.len(), count);
+    }
+
+    #[test]
+    fn more_albums_than_track_album_ids() {
+        let mut master = Master::new(HashingScheme::None, "iPod");
+        let tracks = &mut master.list_mut(0x01).unwrap().children;
+        for unique_id in 1..=65_537 {
+            let mut track = Track::new(unique_id);
+            track.set_string(3, &format!("Album {unique_id}"));
+            tracks.push(Record::mhit(track));
+        }
+
+        super::rebuild_album_list(&mut master);
+
+        let album_ids: Vec<u16> = master.tracks().map(|track| track.album_id).collect();
+        assert_eq!(album_ids[0], 1);
+        assert_eq!(album_ids[65_534], 65_535);
+        assert_eq!(album_ids[65_535..], [0, 0]);
+        assert_eq!(master.list(0x04).unwrap().children.len(), 65_537);
+        assert!(super::album_list_is_current(&master));
+    }
+}
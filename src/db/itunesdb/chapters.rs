@@ -0,0 +1,268 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::{bail, ensure};
+
+use super::{ChapterData, Data, Track};
+use crate::media::mp4::{self, be_u16, be_u32};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Chapter {
+    pub start_ms: u32,
+    pub title: String,
+}
+
+/// Chapters of a track, in the order they are played.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Chapters {
+    pub chapters: Vec<Chapter>,
+}
+
+fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut atom = (8 + body.len() as u32).to_be_bytes().to_vec();
+    atom.extend(kind);
+    atom.extend(body);
+    atom
+}
+
+fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+impl Chapters {
+    /// Reads the chapter list of an M4B/M4A file, from its chapter track or Nero chapters.
+    pub(crate) fn from_m4b(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        Ok(Chapters {
+            chapters: mp4::read_chapters(&mut reader)?
+                .into_iter()
+                .map(|(start_ms, title)| Chapter { start_ms, title })
+                .collect(),
+        })
+    }
+
+    /// Parses the `sean` atom: a `chap` atom per chapter, each with a `name` child,
+    /// followed by a `hedr` atom.
+    pub(crate) fn from_atoms(bytes: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            bytes.get(4..8) == Some(b"sean"),
+            "chapter data has no sean atom"
+        );
+
+        let sean_len = (be_u32(bytes, 0)? as usize).min(bytes.len());
+        let mut at = 20;
+        let mut chapters = Vec::new();
+
+        while at + 8 <= sean_len {
+            let len = be_u32(bytes, at)? as usize;
+            ensure!(
+                len >= 8 && at + len <= sean_len,
+                "chapter atom overruns sean"
+            );
+
+            if &bytes[at + 4..at + 8] == b"chap" {
+                let start_ms = be_u32(bytes, at + 8)?;
+                let name = at + 20;
+
+                ensure!(
+                    bytes.get(name + 4..name + 8) == Some(b"name"),
+                    "chapter has no name"
+                );
+                let chars = be_u16(bytes, name + 20)? as usize;
+                let Some(title) = bytes.get(name + 22..name + 22 + chars * 2) else {
+                    bail!("chapter name overruns its atom");
+                };
+
+                let units: Vec<u16> = title
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+
+                chapters.push(Chapter {
+                    start_ms,
+                    title: String::from_utf16_lossy(&units),
+                });
+            }
+            at += len;
+        }
+
+        Ok(Chapters { chapters })
+    }
+
+    pub(crate) fn to_atoms(&self) -> Vec<u8> {
+        let mut body = words(&[1, self.chapters.len() as u32 + 1, 0]);
+
+        for chapter in &self.chapters {
+            let title: Vec<u16> = chapter.title.encode_utf16().collect();
+
+            let mut name = words(&[1, 0, 0]);
+            name.extend((title.len() as u16).to_be_bytes());
+            name.extend(title.iter().flat_map(|unit| unit.to_be_bytes()));
+
+            let mut chap = words(&[chapter.start_ms, 1, 0]);
+            chap.extend(atom(b"name", &name));
+
+            body.extend(atom(b"chap", &chap));
+        }
+        body.extend(atom(b"hedr", &words(&[1, 0, 0, 0, 1])));
+
+        atom(b"sean", &body)
+    }
+}
+
+impl Track {
+    pub(crate) fn chapters(&self) -> Option<&Chapters> {
+        self.data().find_map(|data| match data {
+            Data::ChapterData(data) => Some(&data.chapters),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn set_chapters(&mut self, chapters: Chapters) {
+        self.set_data(Data::ChapterData(ChapterData {
+            unk_0x00: 0,
+            unk_0x04: 0,
+            unk_0x08: 0,
+            chapters,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{atom, words, Chapter, Chapters};
+    use crate::db::itunesdb::{io, Data, DataContainer, Record};
+
+    fn chapters() -> Chapters {
+        Chapters {
+            chapters: vec![
+                Chapter {
+                    start_ms: 0,
+                    title: "Prologue".into(),
+                },
+                Chapter {
+                    start_ms: 61_500,
+                    title: "Chapitre Deux — Été".into(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn chapter_data_round_trip() {
+        let mut bytes = b"mhod".to_vec();
+        let atoms = chapters().to_atoms();
+        bytes.extend(24u32.to_le_bytes());
+        bytes.extend((24 + 12 + atoms.len() as u32).to_le_bytes());
+        bytes.extend(17u32.to_le_bytes());
+        bytes.extend([0; 8]);
+        bytes.extend([0; 12]);
+        bytes.extend(&atoms);
+
+        let record = io::read_from_buffer(&bytes).unwrap();
+        let Record::mhod(DataContainer {
+            data: Data::ChapterData(data),
+            ..
+        }) = &record
+        else {
+            panic!("chapter data was not parsed");
+        };
+
+        assert_eq!(data.chapters, chapters());
+        assert_eq!(io::write_to_buffer(&record), bytes);
+    }
+
+    fn full_atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut full = vec![0; 4];
+        full.extend(body);
+        atom(kind, &full)
+    }
+
+    fn write_fixture(moov: Vec<u8>, mdat: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&atom(b"ftyp", b"M4B \0\0\0\0")).unwrap();
+        file.write_all(&moov).unwrap();
+        file.write_all(&atom(b"mdat", mdat)).unwrap();
+        file
+    }
+
+    #[test]
+    fn from_m4b_nero_chapters() {
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, 2];
+        for (start, title) in [(0u64, "Prologue"), (615_000_000, "Chapitre Deux — Été")] {
+            chpl.extend(start.to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend(title.as_bytes());
+        }
+
+        let moov = atom(b"moov", &atom(b"udta", &atom(b"chpl", &chpl)));
+        let file = write_fixture(moov, &[]);
+
+        assert_eq!(Chapters::from_m4b(file.path()).unwrap(), chapters());
+    }
+
+    #[test]
+    fn from_m4b_chapter_track() {
+        let titles = ["Prologue", "Chapitre Deux — Été"];
+        let samples: Vec<Vec<u8>> = titles
+            .iter()
+            .map(|title| {
+                let mut sample = (title.len() as u16).to_be_bytes().to_vec();
+                sample.extend(title.as_bytes());
+                sample
+            })
+            .collect();
+
+        // ftyp (16 bytes) comes first, the mdat body offset is only known once moov is built
+        let build_moov = |mdat_offset: u32| {
+            let audio = atom(
+                b"trak",
+                &[
+                    full_atom(b"tkhd", &[words(&[0, 0, 1]), vec![0; 72]].concat()),
+                    atom(b"tref", &atom(b"chap", &words(&[2]))),
+                ]
+                .concat(),
+            );
+
+            // Timescale of 10, so the first chapter lasts 615 units = 61.5s
+            let stbl = [
+                full_atom(b"stts", &words(&[2, 1, 615, 1, 1000])),
+                full_atom(
+                    b"stsz",
+                    &words(&[0, 2, samples[0].len() as u32, samples[1].len() as u32]),
+                ),
+                full_atom(b"stsc", &words(&[1, 1, 2, 1])),
+                full_atom(b"stco", &words(&[1, mdat_offset])),
+            ]
+            .concat();
+
+            let text = atom(
+                b"trak",
+                &[
+                    full_atom(b"tkhd", &[words(&[0, 0, 2]), vec![0; 72]].concat()),
+                    atom(
+                        b"mdia",
+                        &[
+                            full_atom(b"mdhd", &words(&[0, 0, 10, 1615, 0])),
+                            atom(b"minf", &atom(b"stbl", &stbl)),
+                        ]
+                        .concat(),
+                    ),
+                ]
+                .concat(),
+            );
+
+            atom(b"moov", &[audio, text].concat())
+        };
+
+        let moov_len = build_moov(0).len() as u32;
+        let moov = build_moov(16 + moov_len + 8);
+        let file = write_fixture(moov, &samples.concat());
+
+        assert_eq!(Chapters::from_m4b(file.path()).unwrap(), chapters());
+    }
+}
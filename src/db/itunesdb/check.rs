@@ -0,0 +1,486 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+
+use super::{albums, io, Master, Playlist, Record, Track};
+use crate::{files, media::soundcheck::ReplayGain};
+
+/// Something wrong with the database or the files on the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// More than one track has this unique id
+    DuplicateTrackId { track_id: u32 },
+    /// More than one track has this persistent id
+    DuplicatePersistentId { persistent_id: u64 },
+    /// A playlist entry points at a track that doesn't exist
+    MissingTrack { playlist_id: u64, track_id: u32 },
+    /// The file of a track isn't on the device
+    MissingFile { track_id: u32, path: PathBuf },
+    /// A file in the music folders no track points at
+    OrphanedFile { path: PathBuf },
+    /// No playlist is marked as the master playlist
+    NoMasterPlaylist,
+    /// The master playlist doesn't list a track
+    NotInMasterPlaylist { track_id: u32 },
+    /// A list holds records of another kind, what a wrong child count in its header
+    /// parses as
+    WrongChildCount { list_type: u32, stray: usize },
+    /// The length stored in a record header doesn't match its contents
+    WrongLength {
+        magic: &'static str,
+        stored: u32,
+        actual: u32,
+    },
+    /// The album list doesn't match the albums of the tracks
+    StaleAlbumList,
+}
+
+impl Problem {
+    /// Whether writing the database as is would leave it broken. Lengths and the
+    /// album list are regenerated on every write.
+    pub(crate) fn blocks_writing(&self) -> bool {
+        !matches!(
+            self,
+            Problem::WrongLength { .. } | Problem::StaleAlbumList | Problem::OrphanedFile { .. }
+        )
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::DuplicateTrackId { track_id } => {
+                write!(f, "track id {track_id} is used more than once")
+            }
+            Problem::DuplicatePersistentId { persistent_id } => {
+                write!(
+                    f,
+                    "persistent id {persistent_id:016X} is used more than once"
+                )
+            }
+            Problem::MissingTrack {
+                playlist_id,
+                track_id,
+            } => write!(
+                f,
+                "playlist {playlist_id:016X} lists track {track_id}, which doesn't exist"
+            ),
+            Problem::MissingFile { track_id, path } => {
+                write!(
+                    f,
+                    "the file of track {track_id}, {}, is missing",
+                    path.display()
+                )
+            }
+            Problem::OrphanedFile { path } => {
+                write!(f, "no track points at {}", path.display())
+            }
+            Problem::NoMasterPlaylist => write!(f, "there is no master playlist"),
+            Problem::NotInMasterPlaylist { track_id } => {
+                write!(f, "track {track_id} is missing from the master playlist")
+            }
+            Problem::WrongChildCount { list_type, stray } => write!(
+                f,
+                "list {list_type} holds {stray} records of the wrong kind, its child count is off"
+            ),
+            Problem::WrongLength {
+                magic,
+                stored,
+                actual,
+            } => write!(f, "{magic} says it is {stored} bytes long but is {actual}"),
+            Problem::StaleAlbumList => write!(f, "the album list doesn't match the tracks"),
+        }
+    }
+}
+
+// The record kind each list holds
+fn list_record_matches(list_type: u32, record: &Record) -> bool {
+    match list_type {
+        0x01 => matches!(record, Record::mhit(_)),
+        0x02 | 0x03 | 0x05 => matches!(record, Record::mhyp(_)),
+        0x04 => matches!(record, Record::mhia(_)),
+        _ => true,
+    }
+}
+
+/// Problems with the database itself, leaving out the files on the device.
+pub(crate) fn check_database(root: &Record) -> Vec<Problem> {
+    let Record::mhbd(master) = root else {
+        return Vec::new();
+    };
+    let mut problems = Vec::new();
+
+    for (magic, stored, actual) in io::wrong_lengths(root) {
+        problems.push(Problem::WrongLength {
+            magic,
+            stored,
+            actual,
+        });
+    }
+
+    for list_type in 0x01..=0x05 {
+        let Some(list) = master.list(list_type) else {
+            continue;
+        };
+        let stray = list
+            .children
+            .iter()
+            .filter(|child| !list_record_matches(list_type, child))
+            .count();
+        if stray != 0 {
+            problems.push(Problem::WrongChildCount { list_type, stray });
+        }
+    }
+
+    let mut track_ids = HashSet::new();
+    let mut persistent_ids = HashSet::new();
+    for track in master.tracks() {
+        if !track_ids.insert(track.unique_id) {
+            problems.push(Problem::DuplicateTrackId {
+                track_id: track.unique_id,
+            });
+        }
+        if !persistent_ids.insert(track.persistent_id) {
+            problems.push(Problem::DuplicatePersistentId {
+                persistent_id: track.persistent_id,
+            });
+        }
+    }
+
+    let mut has_master = false;
+    let mut reported = HashSet::new();
+    for playlist in master.playlists() {
+        for track_id in playlist.track_ids() {
+            if !track_ids.contains(&track_id) && reported.insert((playlist.persistent_id, track_id))
+            {
+                problems.push(Problem::MissingTrack {
+                    playlist_id: playlist.persistent_id,
+                    track_id,
+                });
+            }
+        }
+
+        // The podcast list repeats the master playlist, the first one is checked
+        if playlist.is_master() && !has_master {
+            has_master = true;
+            let listed: HashSet<u32> = playlist.track_ids().collect();
+            for track in master.tracks() {
+                if !listed.contains(&track.unique_id) {
+                    problems.push(Problem::NotInMasterPlaylist {
+                        track_id: track.unique_id,
+                    });
+                }
+            }
+        }
+    }
+    if !has_master {
+        problems.push(Problem::NoMasterPlaylist);
+    }
+
+    if !albums::album_list_is_current(master) {
+        problems.push(Problem::StaleAlbumList);
+    }
+
+    problems
+}
+
+// FAT is case insensitive, locations and file names don't always agree on case
+fn path_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Problems with the database and the files on the device mounted at `mount`.
+pub(crate) fn check(root: &Record, mount: &Path) -> Vec<Problem> {
+    let mut problems = check_database(root);
+    let Record::mhbd(master) = root else {
+        return problems;
+    };
+
+    let mut referenced = HashSet::new();
+    for track in master.tracks() {
+        let Some(path) = track.location_path(mount) else {
+            continue;
+        };
+
+        referenced.insert(path_key(&path));
+        if !path.is_file() {
+            problems.push(Problem::MissingFile {
+                track_id: track.unique_id,
+                path,
+            });
+        }
+    }
+
+    for path in files::music_files(mount) {
+        if !referenced.contains(&path_key(&path)) {
+            problems.push(Problem::OrphanedFile { path });
+        }
+    }
+
+    problems
+}
+
+/// Fixes the problems `check` found. Tracks whose file is missing are removed, orphaned
+/// files that can be read are added back as tracks and the rest is fixed in place.
+/// Returns the problems that couldn't be fixed.
+pub(crate) fn repair(
+    root: &mut Record,
+    mount: &Path,
+    problems: &[Problem],
+    replay_gain: ReplayGain,
+) -> Vec<Problem> {
+    let Record::mhbd(master) = root else {
+        return problems.to_vec();
+    };
+    let mut unfixed = Vec::new();
+
+    for problem in problems {
+        match problem {
+            Problem::WrongChildCount { list_type, .. } => {
+                let Some(list) = master.list_mut(*list_type) else {
+                    continue;
+                };
+
+                // Lists swallowed by a list claiming too many children go back to the
+                // master, anything else out of place is dropped
+                let (kept, stray): (Vec<Record>, Vec<Record>) = list
+                    .children
+                    .drain(..)
+                    .partition(|child| list_record_matches(*list_type, child));
+                list.children = kept;
+                master.children.extend(
+                    stray
+                        .into_iter()
+                        .filter(|child| matches!(child, Record::mhsd(_))),
+                );
+            }
+            Problem::DuplicateTrackId { track_id } => {
+                // The first track keeps the id and the playlist entries pointing at it
+                let mut next_id = master.next_track_id();
+                let mut seen = false;
+                if let Some(list) = master.list_mut(0x01) {
+                    for child in list.children.iter_mut() {
+                        match child {
+                            Record::mhit(track) if track.unique_id == *track_id && seen => {
+                                track.unique_id = next_id;
+                                next_id += 1;
+                            }
+                            Record::mhit(track) if track.unique_id == *track_id => seen = true,
+                            _ => {}
+                        }
+                    }
+                }
+                add_to_master_playlists(master);
+            }
+            Problem::DuplicatePersistentId { persistent_id } => {
+                let mut seen = false;
+                if let Some(list) = master.list_mut(0x01) {
+                    for child in list.children.iter_mut() {
+                        match child {
+                            Record::mhit(track)
+                                if track.persistent_id == *persistent_id && seen =>
+                            {
+                                track.persistent_id = rand::random();
+                            }
+                            Record::mhit(track) if track.persistent_id == *persistent_id => {
+                                seen = true
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Problem::MissingTrack {
+                playlist_id,
+                track_id,
+            } => {
+                for playlist in master.playlist_mut(*playlist_id) {
+                    playlist.remove_track(*track_id);
+                }
+            }
+            Problem::MissingFile { track_id, .. } => {
+                master.remove_track(*track_id);
+            }
+            Problem::OrphanedFile { path } => {
+                let unique_id = master.next_track_id();
+                let location = files::location(mount, path);
+
+                match (Track::from_file(unique_id, path, replay_gain), location) {
+                    (Ok(mut track), Some(location)) => {
+                        track.set_string(2, &location);
+                        master.add_track(track);
+                    }
+                    _ => unfixed.push(problem.clone()),
+                }
+            }
+            Problem::NoMasterPlaylist => {
+                let mut playlist = Playlist::new_master("iPod");
+                for track in master.tracks() {
+                    playlist.push_track(track.unique_id);
+                }
+                master.add_playlist(playlist);
+            }
+            Problem::NotInMasterPlaylist { .. } => add_to_master_playlists(master),
+            Problem::StaleAlbumList => albums::rebuild_album_list(master),
+            // Fixed when the database is written
+            Problem::WrongLength { .. } => {}
+        }
+    }
+
+    unfixed
+}
+
+/// Adds the tracks the master playlists don't list yet.
+fn add_to_master_playlists(master: &mut Master) {
+    let track_ids: Vec<u32> = master.tracks().map(|track| track.unique_id).collect();
+
+    for list_type in [0x02, 0x03] {
+        let Some(list) = master.list_mut(list_type) else {
+            continue;
+        };
+
+        for child in list.children.iter_mut() {
+            let Record::mhyp(playlist) = child else {
+                continue;
+            };
+            if !playlist.is_master() {
+                continue;
+            }
+
+            let listed: HashSet<u32> = playlist.track_ids().collect();
+            for track_id in &track_ids {
+                if !listed.contains(track_id) {
+                    playlist.push_track(*track_id);
+                }
+            }
+        }
+    }
+}
+
+/// Checks the invariants the device relies on before a database is written, see
+/// `Problem::blocks_writing`.
+pub(crate) fn validate(root: &Record) -> anyhow::Result<()> {
+    match check_database(root)
+        .into_iter()
+        .find(Problem::blocks_writing)
+    {
+        Some(problem) => bail!("{problem}"),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Problem;
+    use crate::{
+        db::itunesdb::{io, Master, Record},
+        iPod,
+        media::mp3,
+        tests::device,
+    };
+
+    fn master(ipod: &mut iPod) -> &mut Master {
+        match &mut ipod.itunesdb {
+            Record::mhbd(master) => master,
+            _ => panic!("root is not a master record"),
+        }
+    }
+
+    /// The sample device, saved once so it has an album list.
+    fn healthy_device() -> tempfile::TempDir {
+        let mount = device();
+        iPod::open(mount.path()).unwrap().save().unwrap();
+        mount
+    }
+
+    #[test]
+    fn check_database() {
+        let mount = healthy_device();
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        assert_eq!(super::check_database(&ipod.itunesdb), vec![]);
+
+        master(&mut ipod).track_mut(103).unwrap().unique_id = 102;
+        let mut buf = io::write_to_buffer(&ipod.itunesdb);
+        buf[8] ^= 1; // mhbd total length
+
+        let root = io::read_from_buffer(&buf).unwrap();
+        let problems = super::check_database(&root);
+        assert!(matches!(
+            problems[0],
+            Problem::WrongLength { magic: "mhbd", .. }
+        ));
+        assert!(problems.contains(&Problem::DuplicateTrackId { track_id: 102 }));
+        assert!(super::validate(&root).is_err());
+    }
+
+    #[test]
+    fn check_and_repair() {
+        let mount = healthy_device();
+        let music = mount.path().join("iPod_Control/Music");
+        for folder in ["F00", "F01", "F02"] {
+            fs::create_dir_all(music.join(folder)).unwrap();
+        }
+        fs::write(music.join("F00/AAAA.mp3"), mp3::tests::tagged_mp3()).unwrap();
+        fs::write(music.join("F01/BBBB.mp3"), mp3::tests::tagged_mp3()).unwrap();
+        fs::write(music.join("F02/DDDD.mp3"), mp3::tests::tagged_mp3()).unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let master = master(&mut ipod);
+        let playlist_id = master
+            .playlists()
+            .find(|playlist| !playlist.is_master())
+            .unwrap()
+            .persistent_id();
+        for playlist in master.playlist_mut(playlist_id) {
+            playlist.push_track(999);
+        }
+        let master_id = master
+            .playlists()
+            .find(|playlist| playlist.is_master())
+            .unwrap()
+            .persistent_id();
+        for playlist in master.playlist_mut(master_id) {
+            playlist.remove_track(102);
+        }
+        ipod.save().unwrap();
+        let mut ipod = iPod::open(mount.path()).unwrap();
+
+        let problems = ipod.check();
+        assert_eq!(
+            problems,
+            vec![
+                Problem::NotInMasterPlaylist { track_id: 102 },
+                Problem::MissingTrack {
+                    playlist_id,
+                    track_id: 999
+                },
+                Problem::MissingFile {
+                    track_id: 103,
+                    path: music.join("F02/CCCC.mp3")
+                },
+                Problem::OrphanedFile {
+                    path: music.join("F02/DDDD.mp3")
+                },
+            ]
+        );
+
+        assert!(ipod.repair().unwrap().is_empty());
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        assert_eq!(ipod.check(), vec![]);
+
+        let master = match &ipod.itunesdb {
+            Record::mhbd(master) => master,
+            _ => panic!("root is not a master record"),
+        };
+        let titles: Vec<_> = master.tracks().map(|track| track.title()).collect();
+        assert_eq!(titles.len(), 3);
+        assert_eq!(titles[2].as_deref(), Some("Ace of Spades"));
+    }
+}
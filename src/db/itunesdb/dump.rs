@@ -0,0 +1,569 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use anyhow::{bail, ensure};
+
+use super::Data;
+
+// Header fields after the magic, as (name, size), in the order of the structs in
+// mod.rs. Headers longer than the table end in unnamed bytes, shorter ones (older
+// databases) stop early.
+#[rustfmt::skip]
+const MHBD: &[(&str, usize)] = &[
+    ("header_len", 4), ("len", 4), ("unk_0x0C", 4), ("version", 4), ("child_count", 4),
+    ("database_id", 8), ("unk_0x20", 2), ("hashing_scheme", 2), ("unk_0x24", 8),
+    ("unk_0x2C", 4), ("unk_0x30", 2), ("padding_0x32", 20), ("lang", 2),
+    ("persistent_id", 8), ("unk_0x50", 4), ("unk_0x54", 4), ("hash_0x58", 20),
+    ("timezone_offset", 4), ("unk_0x70", 2), ("hash_0x72", 46), ("unk_0xA0", 4),
+    ("audio_lang", 2), ("subtitle_lang", 2),
+];
+
+#[rustfmt::skip]
+const MHSD: &[(&str, usize)] = &[("header_len", 4), ("len", 4), ("list_type", 4)];
+
+// mhlt, mhlp and mhla
+#[rustfmt::skip]
+const LIST: &[(&str, usize)] = &[("header_len", 4), ("child_count", 4)];
+
+#[rustfmt::skip]
+const MHIT: &[(&str, usize)] = &[
+    ("header_len", 4), ("len", 4), ("child_count", 4), ("unique_id", 4), ("visible", 4),
+    ("file_type", 4), ("vbr_flag", 1), ("mp3_flag", 1), ("compilation_flag", 1),
+    ("rating", 1), ("hfs_time_last_modified", 4), ("file_size_bytes_u32", 4),
+    ("duration_ms", 4), ("album_index", 4), ("album_track_count", 4), ("release_year", 4),
+    ("bitrate", 4), ("sample_rate", 4), ("playback_volume_adj", 4), ("start_offset_ms", 4),
+    ("stop_offset_ms", 4), ("soundcheck", 4), ("play_count_1", 4), ("play_count_2", 4),
+    ("hfs_time_last_played", 4), ("album_disc_index", 4), ("album_disc_count", 4),
+    ("drm_user_id", 4), ("hfs_time_date_added", 4), ("bookmark_ms", 4),
+    ("persistent_id", 8), ("unchecked_flag", 1), ("last_rating", 1), ("bpm", 2),
+    ("artwork_count", 2), ("audio_format_tag", 2), ("artwork_size_bytes", 4),
+    ("unk_0x84", 4), ("IEEE_f32_sample_rate", 4), ("hfs_time_release_date", 4),
+    ("unk_0x90", 2), ("unk_0x92", 2), ("unk_0x94", 4), ("unk_0x98", 4), ("skip_count", 4),
+    ("hfs_time_last_skipped", 4), ("has_artwork", 1), ("skip_on_shuffle_flag", 1),
+    ("remember_playback_position_flag", 1), ("podcast_flag", 1), ("unk_0xA8", 8),
+    ("has_lyrics_flag", 1), ("is_movie_flag", 1), ("podcast_unplayed", 1), ("unk_0xB3", 1),
+    ("unk_0xB4", 4), ("samples_before_start_gapless", 4), ("samples_count_gapless", 8),
+    ("unk_0xC4", 4), ("samples_before_end_gapless", 4), ("mp3_encoded", 4),
+    ("media_type", 4), ("season_number", 4), ("episode_number", 4), ("unk_0xDC", 4),
+    ("padding_0xE0", 24), ("gapless_data", 4), ("unk_0xFC", 4),
+    ("is_gapless_track_flag", 2), ("is_gapless_album_flag", 2), ("padding_0x0104", 28),
+    ("unk_0x0120", 4), ("unk_0x0124", 8), ("file_size_bytes_u64", 8), ("unk_0x0134", 6),
+    ("album_id", 2), ("padding_0x013A", 36), ("mhii_link", 8), ("unk_0x0168", 8),
+    ("padding_0x0170", 112), ("unk_0x01E0", 4), ("padding_0x01E4", 16), ("unk_0x01F4", 4),
+    ("padding_0x01F8", 20), ("unk_0x020C", 4), ("", 28), ("unk_0x22C", 4),
+];
+
+#[rustfmt::skip]
+const MHIA: &[(&str, usize)] = &[
+    ("header_len", 4), ("len", 4), ("child_count", 4), ("album_id", 4), ("unk_0x14", 8),
+    ("unk_0x1C", 4), ("unk_0x20", 8),
+];
+
+#[rustfmt::skip]
+const MHYP: &[(&str, usize)] = &[
+    ("header_len", 4), ("len", 4), ("child_count", 4), ("entry_count", 4),
+    ("is_master_flag", 1), ("flag_0x15", 1), ("flag_0x16", 1), ("flag_0x17", 1),
+    ("hfs_timestamp_0x18", 4), ("persistent_id", 8), ("unk_0x24", 4),
+    ("string_obj_count", 2), ("is_podcast_playlist_flag", 2), ("sort_order", 4),
+    ("padding_0x30", 40), ("hfs_timestamp_0x58", 4),
+];
+
+#[rustfmt::skip]
+const MHIP: &[(&str, usize)] = &[
+    ("header_len", 4), ("len", 4), ("child_count", 4), ("podcast_group_flag", 2),
+    ("unk_0x18", 2), ("group_id", 4), ("track_id", 4), ("hfs_timestamp_0x28", 4),
+    ("podcast_group_id", 4), ("padding_0x32", 12), ("unk_0x48", 4), ("padding_0x52", 8),
+    ("unk_0x60", 8),
+];
+
+#[rustfmt::skip]
+const MHOD: &[(&str, usize)] = &[("header_len", 4), ("len", 4), ("data_type", 4)];
+
+#[rustfmt::skip]
+const STRING: &[(&str, usize)] = &[
+    ("encoding", 4), ("len", 4), ("unk_0x08", 4), ("unk_0x0C", 4),
+];
+
+#[rustfmt::skip]
+const LIBRARY_INDEX: &[(&str, usize)] = &[("index_type", 4), ("count", 4), ("", 40)];
+
+#[rustfmt::skip]
+const JUMP_TABLE: &[(&str, usize)] = &[("index_type", 4), ("count", 4), ("", 8)];
+
+#[rustfmt::skip]
+const CHAPTER_DATA: &[(&str, usize)] = &[("unk_0x00", 4), ("unk_0x04", 4), ("unk_0x08", 4)];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Int(u64, usize), // value and size in bytes
+    Bytes(Vec<u8>),
+    Text(String),
+    Ints(Vec<u32>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value, size) => write!(f, "{value} (0x{value:0width$X})", width = size * 2),
+            Value::Bytes(bytes) if bytes.iter().all(|&byte| byte == 0) => {
+                write!(f, "{} zero bytes", bytes.len())
+            }
+            Value::Bytes(bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+                write!(f, "{}", hex.join(" "))
+            }
+            Value::Text(text) => write!(f, "{text:?}"),
+            Value::Ints(values) => {
+                let values: Vec<String> = values.iter().map(u32::to_string).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    offset: usize, // from the start of the record
+    name: String,
+    value: Value,
+}
+
+impl Field {
+    /// Fields nobody knows the meaning of yet.
+    fn is_unknown(&self) -> bool {
+        self.name.starts_with("unk_")
+            || self.name.starts_with("padding_")
+            || self.name.starts_with("unnamed_")
+    }
+
+    /// Whether `other` is this field in another version of the record. Names alone
+    /// aren't unique, string mhods have a len in the header and one in the body.
+    fn same_place(&self, other: &Field) -> bool {
+        self.offset == other.offset && self.name == other.name
+    }
+}
+
+/// A record as laid out in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node {
+    offset: usize,
+    magic: String,
+    fields: Vec<Field>, // header_len and len (or child_count) first
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn field(&self, name: &str) -> Option<u64> {
+        self.fields.iter().find_map(|field| match field.value {
+            Value::Int(value, _) if field.name == name => Some(value),
+            _ => None,
+        })
+    }
+
+    /// What tells the record apart from its siblings, for matching them up in diffs.
+    fn id(&self) -> Option<u64> {
+        match self.magic.as_str() {
+            "mhsd" => self.field("list_type"),
+            "mhit" => self.field("unique_id"),
+            "mhia" => self.field("album_id"),
+            "mhyp" => self.field("persistent_id"),
+            "mhip" => self.field("track_id"),
+            "mhod" => self.field("data_type"),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> anyhow::Result<&[u8]> {
+        match self.buf.get(offset..offset + len) {
+            Some(bytes) => Ok(bytes),
+            None => bail!("record at 0x{offset:X} runs past the end of the file"),
+        }
+    }
+
+    fn u32(&self, offset: usize) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.bytes(offset, 4)?.try_into().unwrap(),
+        ))
+    }
+
+    fn value(&self, offset: usize, size: usize) -> anyhow::Result<Value> {
+        let bytes = self.bytes(offset, size)?;
+        Ok(match size {
+            1 | 2 | 4 | 8 => {
+                let mut le = [0; 8];
+                le[..size].copy_from_slice(bytes);
+                Value::Int(u64::from_le_bytes(le), size)
+            }
+            _ => Value::Bytes(bytes.to_vec()),
+        })
+    }
+
+    /// The fields of `table` starting at `start`, cut off at `end`. Bytes left before
+    /// `end` become one unnamed field.
+    fn fields(
+        &self,
+        record: usize,
+        start: usize,
+        end: usize,
+        table: &[(&str, usize)],
+    ) -> anyhow::Result<Vec<Field>> {
+        let mut fields = Vec::new();
+        let mut at = start;
+
+        for &(name, size) in table {
+            if at + size > end {
+                break;
+            }
+            let name = match name {
+                "" => format!("unnamed_0x{:X}", at - record),
+                name => name.to_string(),
+            };
+            fields.push(Field {
+                offset: at - record,
+                name,
+                value: self.value(at, size)?,
+            });
+            at += size;
+        }
+
+        if at < end {
+            fields.push(Field {
+                offset: at - record,
+                name: format!("unnamed_0x{:X}", at - record),
+                value: Value::Bytes(self.bytes(at, end - at)?.to_vec()),
+            });
+        }
+
+        Ok(fields)
+    }
+
+    /// Parses the record at `offset`, returns it and its size.
+    fn record(&self, offset: usize) -> anyhow::Result<(Node, usize)> {
+        let magic = String::from_utf8_lossy(self.bytes(offset, 4)?).into_owned();
+        let header_len = self.u32(offset + 4)? as usize;
+        let second = self.u32(offset + 8)? as usize; // len, or child_count for lists
+        ensure!(
+            header_len >= 12,
+            "record at 0x{offset:X} has a header shorter than 12 bytes"
+        );
+
+        let table = match magic.as_str() {
+            "mhbd" => MHBD,
+            "mhsd" => MHSD,
+            "mhlt" | "mhlp" | "mhla" => LIST,
+            "mhit" => MHIT,
+            "mhia" => MHIA,
+            "mhyp" => MHYP,
+            "mhip" => MHIP,
+            "mhod" => MHOD,
+            _ => &[("header_len", 4), ("len", 4)],
+        };
+        let header_end = match magic.as_str() {
+            "mhod" => offset + header_len.min(second),
+            _ => offset + header_len,
+        };
+
+        let mut node = Node {
+            offset,
+            magic: magic.clone(),
+            fields: self.fields(offset, offset + 4, header_end, table)?,
+            children: Vec::new(),
+        };
+
+        let child_count = match magic.as_str() {
+            "mhsd" => 0,
+            "mhyp" => {
+                node.field("child_count").unwrap_or(0) + node.field("entry_count").unwrap_or(0)
+            }
+            _ => node.field("child_count").unwrap_or(0),
+        };
+        let mut at = header_end;
+        for _ in 0..child_count {
+            let (child, size) = self.record(at)?;
+            node.children.push(child);
+            at += size;
+        }
+
+        let size = match magic.as_str() {
+            // Lists have no total length, they end after their last child
+            "mhlt" | "mhlp" | "mhla" => at - offset,
+            "mhsd" if (1..=5).contains(&node.field("list_type").unwrap_or(0)) => {
+                let (list, _) = self.record(header_end)?;
+                node.children.push(list);
+                second
+            }
+            "mhod" => {
+                let data_type = node.field("data_type").unwrap_or(0) as u32;
+                node.fields.extend(self.mhod_body(
+                    offset,
+                    header_end,
+                    offset + second,
+                    data_type,
+                )?);
+                second
+            }
+            "mhbd" | "mhit" | "mhia" | "mhyp" | "mhip" => second,
+            _ => {
+                if header_end < offset + second {
+                    node.fields
+                        .extend(self.fields(offset, header_end, offset + second, &[])?);
+                }
+                second
+            }
+        };
+
+        Ok((node, size))
+    }
+
+    fn mhod_body(
+        &self,
+        record: usize,
+        start: usize,
+        end: usize,
+        data_type: u32,
+    ) -> anyhow::Result<Vec<Field>> {
+        let text_field = |at: usize, text: String| Field {
+            offset: at - record,
+            name: "text".to_string(),
+            value: Value::Text(text),
+        };
+
+        if Data::from_text(data_type, "").is_some() && start + 16 <= end {
+            let mut fields = self.fields(record, start, start + 16, STRING)?;
+            let len = self.u32(start + 4)? as usize;
+            let bytes = self.bytes(start + 16, len.min(end - start - 16))?;
+            let text = match self.u32(start)? {
+                2 => String::from_utf8_lossy(bytes).into_owned(),
+                _ => {
+                    let units: Vec<u16> = bytes
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                        .collect();
+                    String::from_utf16_lossy(&units)
+                }
+            };
+            fields.push(text_field(start + 16, text));
+            return Ok(fields);
+        }
+
+        Ok(match data_type {
+            15 | 16 => vec![text_field(
+                start,
+                String::from_utf8_lossy(self.bytes(start, end - start)?).into_owned(),
+            )],
+            17 => self.fields(record, start, end, CHAPTER_DATA)?,
+            52 | 53 if start + 8 <= end => {
+                let table = if data_type == 52 {
+                    LIBRARY_INDEX
+                } else {
+                    JUMP_TABLE
+                };
+                let header: usize = table.iter().map(|(_, size)| size).sum();
+                let mut fields = self.fields(record, start, start + header, table)?;
+
+                let values: Vec<u32> = (start + header..end)
+                    .step_by(4)
+                    .filter_map(|at| self.u32(at).ok())
+                    .collect();
+                fields.push(Field {
+                    offset: header + start - record,
+                    name: match data_type {
+                        52 => "indices".to_string(),
+                        // letter and padding, start, count
+                        _ => "entries".to_string(),
+                    },
+                    value: Value::Ints(values),
+                });
+                fields
+            }
+            _ => self.fields(record, start, end, &[])?,
+        })
+    }
+}
+
+fn parse(buf: &[u8]) -> anyhow::Result<Node> {
+    let (root, _) = Reader { buf }.record(0)?;
+    Ok(root)
+}
+
+fn write_node(node: &Node, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let header: Vec<String> = node
+        .fields
+        .iter()
+        .take(2)
+        .map(|field| format!("{}={}", field.name, field.value))
+        .collect();
+    let _ = writeln!(
+        out,
+        "{indent}0x{:08X} {} {}",
+        node.offset,
+        node.magic,
+        header.join(" ")
+    );
+
+    for field in node.fields.iter().skip(2) {
+        let _ = writeln!(
+            out,
+            "{indent}  +0x{:03X} {} = {}{}",
+            field.offset,
+            field.name,
+            field.value,
+            if field.is_unknown() {
+                "  <- unknown"
+            } else {
+                ""
+            }
+        );
+    }
+
+    for child in &node.children {
+        write_node(child, depth + 1, out);
+    }
+}
+
+/// Every record of the iTunesDB in `itunesdb` with its offset and fields, one per line.
+/// Fields whose meaning isn't known are marked, to help figuring them out.
+pub fn dump_itunesdb(itunesdb: &[u8]) -> anyhow::Result<String> {
+    let mut out = String::new();
+    write_node(&parse(itunesdb)?, 0, &mut out);
+    Ok(out)
+}
+
+/// Labels for the children of a record, unique among them: the magic, the id of the
+/// record if it has one and a count for repeats.
+fn child_keys(node: &Node) -> Vec<String> {
+    let mut seen = HashMap::new();
+
+    node.children
+        .iter()
+        .map(|child| {
+            let key = match child.id() {
+                Some(id) => format!("{}[{id}]", child.magic),
+                None => child.magic.clone(),
+            };
+            let count = seen.entry(key.clone()).or_insert(0);
+            *count += 1;
+            match count {
+                1 => key,
+                count => format!("{key}#{count}"),
+            }
+        })
+        .collect()
+}
+
+fn diff_nodes(path: &str, a: &Node, b: &Node, out: &mut String) {
+    for field in &a.fields {
+        match b.fields.iter().find(|other| other.same_place(field)) {
+            Some(other) if other.value == field.value => {}
+            Some(other) => {
+                let _ = writeln!(
+                    out,
+                    "{path} {}: {} -> {}",
+                    field.name, field.value, other.value
+                );
+            }
+            None => {
+                let _ = writeln!(out, "{path} {}: {} -> missing", field.name, field.value);
+            }
+        }
+    }
+    for field in &b.fields {
+        if !a.fields.iter().any(|other| other.same_place(field)) {
+            let _ = writeln!(out, "{path} {}: missing -> {}", field.name, field.value);
+        }
+    }
+
+    let a_keys = child_keys(a);
+    let b_keys = child_keys(b);
+    for (key, child) in a_keys.iter().zip(&a.children) {
+        let child_path = format!("{path}/{key}");
+        match b_keys.iter().position(|other| other == key) {
+            Some(position) => diff_nodes(&child_path, child, &b.children[position], out),
+            None => {
+                let _ = writeln!(out, "{child_path}: only in the first");
+            }
+        }
+    }
+    for key in &b_keys {
+        if !a_keys.contains(key) {
+            let _ = writeln!(out, "{path}/{key}: only in the second");
+        }
+    }
+}
+
+/// The fields that differ between two iTunesDB files, one per line as the path of the
+/// record, the field and both values. Records are matched by their ids, so a track
+/// added in the middle doesn't make every following track differ.
+pub fn diff_itunesdb(a: &[u8], b: &[u8]) -> anyhow::Result<String> {
+    let (a, b) = (parse(a)?, parse(b)?);
+
+    let mut out = String::new();
+    diff_nodes(&a.magic, &a, &b, &mut out);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{io, Record};
+
+    const SAMPLE: &[u8] = include_bytes!("./sample/iTunesDB");
+
+    #[test]
+    fn tables_match_header_lengths() {
+        let len = |table: &[(&str, usize)]| -> usize { table.iter().map(|(_, size)| size).sum() };
+
+        // Tables start after the magic and leave out the padding before the children
+        assert_eq!(4 + len(super::MHBD) + 76, 244);
+        assert_eq!(4 + len(super::MHIT) + 64, 624);
+        assert_eq!(4 + len(super::MHIA) + 48, 88);
+        assert_eq!(4 + len(super::MHYP) + 92, 184);
+        assert_eq!(4 + len(super::MHIP) + 8, 76);
+    }
+
+    #[test]
+    fn dump() {
+        let dump = super::dump_itunesdb(SAMPLE).unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert!(lines[0].starts_with("0x00000000 mhbd header_len=244 (0x000000F4) len="));
+        assert!(lines.contains(&"  +0x010 version = 25 (0x00000019)"));
+        assert!(dump.contains("mhit header_len=624"));
+        assert!(dump.contains("+0x010 unique_id = 101 (0x00000065)"));
+        assert!(dump.contains("+0x028 text = \"Song One\""));
+        assert!(dump.contains("+0x084 unk_0x84 = 0 (0x00000000)  <- unknown"));
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(super::diff_itunesdb(SAMPLE, SAMPLE).unwrap(), "");
+
+        let mut root = io::read_from_buffer(SAMPLE).unwrap();
+        let Record::mhbd(master) = &mut root else {
+            panic!("root is not an mhbd record");
+        };
+        let track = master.track_mut(102).unwrap();
+        track.set_string(1, "Renamed");
+        track.rating = 60;
+        master.remove_track(103);
+
+        let changed = io::write_to_buffer(&root);
+        let diff = super::diff_itunesdb(SAMPLE, &changed).unwrap();
+        let lines: Vec<&str> = diff.lines().collect();
+
+        assert!(lines.contains(&"mhbd/mhsd[1]/mhlt/mhit[102] rating: 0 (0x00) -> 60 (0x3C)"));
+        assert!(lines
+            .contains(&"mhbd/mhsd[1]/mhlt/mhit[102]/mhod[1] text: \"Song Two\" -> \"Renamed\""));
+        assert!(lines.contains(&"mhbd/mhsd[1]/mhlt/mhit[103]: only in the first"));
+        // Track 101 didn't change
+        assert!(!diff.contains("mhit[101]"));
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use super::{Master, Record, Track};
+use crate::media::fingerprint;
+
+/// What makes two tracks the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateStrategy {
+    /// Both point at the same file on the device
+    Location,
+    /// Same file size and duration
+    SizeAndDuration,
+    /// Same artist, title and album, ignoring case, spacing and punctuation
+    Metadata,
+    /// Same audio stream, read from the files on the device
+    AudioHash,
+}
+
+/// Tracks found to be copies of each other, by unique id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub keeper: u32,          // the copy to keep: most played, then best bitrate
+    pub duplicates: Vec<u32>, // the copies to merge into the keeper and remove
+}
+
+fn normalize(text: Option<String>) -> String {
+    text.unwrap_or_default()
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The key tracks are grouped by, `None` for tracks the strategy can't compare.
+fn duplicate_key(track: &Track, strategy: DuplicateStrategy, mount: &Path) -> Option<Vec<u8>> {
+    match strategy {
+        DuplicateStrategy::Location => track
+            .location()
+            .map(|location| location.to_lowercase().into_bytes()),
+        DuplicateStrategy::SizeAndDuration => {
+            let size = track
+                .file_size_bytes_u64
+                .max(track.file_size_bytes_u32 as u64);
+            (size != 0)
+                .then(|| [size.to_le_bytes(), (track.duration_ms as u64).to_le_bytes()].concat())
+        }
+        DuplicateStrategy::Metadata => {
+            let key = [track.artist(), track.title(), track.album()].map(normalize);
+            (!key[1].is_empty()).then(|| key.join("\0").into_bytes())
+        }
+        DuplicateStrategy::AudioHash => {
+            let path = track.location_path(mount)?;
+            fingerprint::audio_hash(path).ok().map(|hash| hash.to_vec())
+        }
+    }
+}
+
+/// Groups the tracks of the track list that `strategy` considers the same. `mount` is
+/// where the device is mounted, only used to read files for `AudioHash`.
+pub(crate) fn find_duplicates(
+    master: &Master,
+    strategy: DuplicateStrategy,
+    mount: &Path,
+) -> Vec<DuplicateGroup> {
+    let Some(tracks) = master.list(0x01) else {
+        return Vec::new();
+    };
+
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    let mut groups: HashMap<Vec<u8>, Vec<&Track>> = HashMap::new();
+
+    for track in &tracks.children {
+        let Record::mhit(track) = track else {
+            continue;
+        };
+        let Some(key) = duplicate_key(track, strategy, mount) else {
+            continue;
+        };
+
+        if !groups.contains_key(&key) {
+            keys.push(key.clone());
+        }
+        groups.entry(key).or_default().push(track);
+    }
+
+    // Keys in track list order, so the result doesn't depend on hashing
+    keys.into_iter()
+        .filter_map(|key| {
+            let mut tracks = groups.remove(&key)?;
+            if tracks.len() < 2 {
+                return None;
+            }
+
+            tracks.sort_by_key(|track| {
+                (
+                    std::cmp::Reverse(track.play_count_1),
+                    std::cmp::Reverse(track.bitrate),
+                    track.unique_id,
+                )
+            });
+
+            Some(DuplicateGroup {
+                keeper: tracks[0].unique_id,
+                duplicates: tracks[1..].iter().map(|track| track.unique_id).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Merges the play counts, skips and ratings of every duplicate into its keeper, points
+/// playlist entries at the keeper and removes the duplicates from the track list.
+/// Returns the removed tracks, whose files the caller may want to delete.
+pub(crate) fn remove_duplicates(master: &mut Master, groups: &[DuplicateGroup]) -> Vec<Track> {
+    let keeper_of: HashMap<u32, u32> = groups
+        .iter()
+        .flat_map(|group| group.duplicates.iter().map(|id| (*id, group.keeper)))
+        .collect();
+
+    let Some(tracks) = master.list_mut(0x01) else {
+        return Vec::new();
+    };
+
+    let (removed, kept): (Vec<Record>, Vec<Record>) = tracks.children.drain(..).partition(
+        |track| matches!(track, Record::mhit(track) if keeper_of.contains_key(&track.unique_id)),
+    );
+    tracks.children = kept;
+
+    let removed: Vec<Track> = removed
+        .into_iter()
+        .filter_map(|record| match record {
+            Record::mhit(track) => Some(track),
+            _ => None,
+        })
+        .collect();
+
+    for keeper in tracks.children.iter_mut() {
+        let Record::mhit(keeper) = keeper else {
+            continue;
+        };
+
+        for duplicate in removed
+            .iter()
+            .filter(|duplicate| keeper_of.get(&duplicate.unique_id) == Some(&keeper.unique_id))
+        {
+            keeper.play_count_1 = keeper.play_count_1.saturating_add(duplicate.play_count_1);
+            keeper.play_count_2 = keeper.play_count_2.saturating_add(duplicate.play_count_2);
+            keeper.skip_count = keeper.skip_count.saturating_add(duplicate.skip_count);
+            keeper.rating = keeper.rating.max(duplicate.rating);
+            keeper.hfs_time_last_played = keeper
+                .hfs_time_last_played
+                .max(duplicate.hfs_time_last_played);
+            keeper.hfs_time_last_skipped = keeper
+                .hfs_time_last_skipped
+                .max(duplicate.hfs_time_last_skipped);
+            keeper.hfs_time_date_added = keeper
+                .hfs_time_date_added
+                .min(duplicate.hfs_time_date_added);
+        }
+    }
+
+    // Playlists, podcasts and smart playlists all hold mhips
+    for list_type in [0x02, 0x03, 0x05] {
+        let Some(playlists) = master.list_mut(list_type) else {
+            continue;
+        };
+
+        for playlist in playlists.children.iter_mut() {
+            let Record::mhyp(playlist) = playlist else {
+                continue;
+            };
+
+            // A duplicate whose keeper is already in the playlist is dropped, otherwise
+            // the master playlist would list the keeper twice
+            let mut present: HashSet<u32> = playlist
+                .entries
+                .iter()
+                .filter_map(|entry| match entry {
+                    Record::mhip(entry) if !keeper_of.contains_key(&entry.track_id) => {
+                        Some(entry.track_id)
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            playlist.entries.retain_mut(|entry| {
+                let Record::mhip(entry) = entry else {
+                    return true;
+                };
+                let Some(keeper) = keeper_of.get(&entry.track_id) else {
+                    return true;
+                };
+
+                entry.track_id = *keeper;
+                present.insert(*keeper)
+            });
+        }
+    }
+
+    removed
+}
+
+/// Files of `removed` tracks that no track left in the track list points at.
+pub(crate) fn orphaned_files(master: &Master, removed: &[Track], mount: &Path) -> Vec<PathBuf> {
+    let kept: HashSet<PathBuf> = master
+        .list(0x01)
+        .map(|tracks| {
+            tracks
+                .children
+                .iter()
+                .filter_map(|track| match track {
+                    Record::mhit(track) => track.location_path(mount),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut files: Vec<PathBuf> = removed
+        .iter()
+        .filter_map(|track| track.location_path(mount))
+        .filter(|path| !kept.contains(path))
+        .collect();
+    files.dedup();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{DuplicateGroup, DuplicateStrategy};
+    use crate::db::itunesdb::{io, Master, Record};
+
+    fn master() -> Master {
+        match io::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap() {
+            Record::mhbd(master) => master,
+            _ => panic!("root is not a master record"),
+        }
+    }
+
+    fn first_track_id(master: &Master) -> u32 {
+        master
+            .list(0x01)
+            .unwrap()
+            .children
+            .iter()
+            .find_map(|track| match track {
+                Record::mhit(track) => Some(track.unique_id),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// Copies the first track under a new id, played once and skipped as often as a
+    /// count goes, and adds it to every playlist the original is in.
+    fn add_copy(master: &mut Master, unique_id: u32) -> u32 {
+        let original_id = first_track_id(master);
+
+        let tracks = master.list_mut(0x01).unwrap();
+        let mut copy = tracks
+            .children
+            .iter()
+            .find_map(|track| match track {
+                Record::mhit(track) if track.unique_id == original_id => Some(track.clone()),
+                _ => None,
+            })
+            .unwrap();
+        copy.unique_id = unique_id;
+        copy.play_count_1 = copy.play_count_1.saturating_sub(1);
+        copy.rating = 100;
+        copy.skip_count = u32::MAX;
+        tracks.children.push(Record::mhit(copy));
+
+        for playlist in master.list_mut(0x02).unwrap().children.iter_mut() {
+            let Record::mhyp(playlist) = playlist else {
+                continue;
+            };
+            let entry = playlist.entries.iter().find_map(|entry| match entry {
+                Record::mhip(entry) if entry.track_id == original_id => Some(entry.clone()),
+                _ => None,
+            });
+            if let Some(mut entry) = entry {
+                entry.track_id = unique_id;
+                playlist.entries.push(Record::mhip(entry));
+            }
+        }
+
+        original_id
+    }
+
+    fn references(master: &Master, unique_id: u32) -> usize {
+        master
+            .list(0x02)
+            .unwrap()
+            .children
+            .iter()
+            .map(|playlist| match playlist {
+                Record::mhyp(playlist) => playlist
+                    .entries
+                    .iter()
+                    .filter(
+                        |entry| matches!(entry, Record::mhip(entry) if entry.track_id == unique_id),
+                    )
+                    .count(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn find_and_remove_duplicates() {
+        let mut master = master();
+        let original_references = references(&master, first_track_id(&master));
+        let original_id = add_copy(&mut master, 0xFFFF_0000);
+        let track_count = master.list(0x01).unwrap().children.len();
+
+        for strategy in [
+            DuplicateStrategy::Location,
+            DuplicateStrategy::SizeAndDuration,
+            DuplicateStrategy::Metadata,
+        ] {
+            let groups = super::find_duplicates(&master, strategy, Path::new("/nonexistent"));
+            assert!(
+                groups.contains(&DuplicateGroup {
+                    keeper: original_id,
+                    duplicates: vec![0xFFFF_0000]
+                }),
+                "{strategy:?}"
+            );
+        }
+
+        let groups = vec![DuplicateGroup {
+            keeper: original_id,
+            duplicates: vec![0xFFFF_0000],
+        }];
+        let removed = super::remove_duplicates(&mut master, &groups);
+
+        assert_eq!(removed.len(), 1);
+        // The copy shares the original's file, which must not be deleted
+        assert!(super::orphaned_files(&master, &removed, Path::new("/mnt")).is_empty());
+        assert_eq!(master.list(0x01).unwrap().children.len(), track_count - 1);
+        assert_eq!(references(&master, 0xFFFF_0000), 0);
+        assert_eq!(references(&master, original_id), original_references);
+
+        let keeper = master
+            .list(0x01)
+            .unwrap()
+            .children
+            .iter()
+            .find_map(|track| match track {
+                Record::mhit(track) if track.unique_id == original_id => Some(track),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(keeper.rating, 100);
+        assert_eq!(keeper.skip_count, u32::MAX);
+    }
+}
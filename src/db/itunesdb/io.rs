@@ -2,11 +2,14 @@
 
 use std::io::Cursor;
 
-use anyhow::ensure;
+use anyhow::bail;
 use binrw::{binrw, BinRead, BinWrite};
 
-use super::{List, Record};
-use crate::util::ByteCounter;
+use super::{albums, library_index, podcasts, List, Record};
+use crate::{
+    db::checksum::{self, DatabaseSigner},
+    util::ByteCounter,
+};
 
 fn get_record_size(record: &Record) -> u32 {
     let mut counter = ByteCounter::new();
@@ -27,16 +30,10 @@ fn update_len(record: &mut Record) {
             master.len = get_record_size(&Record::mhbd(master.clone()));
         }
         Record::mhsd(list_container) => {
-            let list = match &mut list_container.list {
-                List::Tracks(list)
-                | List::Playlists(list)
-                | List::Podcasts(list)
-                | List::Albums(list)
-                | List::InclSmartPlaylists(list) => list,
-            };
-
-            for child in &mut list.children {
-                update_len(child);
+            if let Some(list) = list_container.list.records_mut() {
+                for child in &mut list.children {
+                    update_len(child);
+                }
             }
             list_container.len = get_record_size(&Record::mhsd(list_container.clone()));
         }
@@ -69,8 +66,55 @@ fn update_len(record: &mut Record) {
             playlist_entry.len = get_record_size(&Record::mhip(playlist_entry.clone()));
         }
         Record::mhod(data_container) => {
+            data_container.data_type = data_container.data.as_u32();
             data_container.len = get_record_size(&Record::mhod(data_container.clone()));
         }
+        Record::Unknown(_) => {}
+    }
+}
+
+/// Records whose stored total length doesn't match their contents, as (magic, stored,
+/// actual). Writing the record fixes them.
+pub(crate) fn wrong_lengths(record: &Record) -> Vec<(&'static str, u32, u32)> {
+    let mut found = Vec::new();
+    collect_wrong_lengths(record, &mut found);
+    found
+}
+
+fn collect_wrong_lengths(record: &Record, found: &mut Vec<(&'static str, u32, u32)>) {
+    let (magic, len, children): (_, _, Vec<&Record>) = match record {
+        Record::mhbd(master) => ("mhbd", master.len, master.children.iter().collect()),
+        Record::mhsd(list_container) => (
+            "mhsd",
+            list_container.len,
+            match list_container.list.records() {
+                Some(list) => list.children.iter().collect(),
+                None => Vec::new(),
+            },
+        ),
+        Record::mhit(track) => ("mhit", track.len, track.children.iter().collect()),
+        Record::mhia(album) => ("mhia", album.len, album.children.iter().collect()),
+        Record::mhyp(playlist) => (
+            "mhyp",
+            playlist.len,
+            playlist.children.iter().chain(&playlist.entries).collect(),
+        ),
+        Record::mhip(playlist_entry) => (
+            "mhip",
+            playlist_entry.len,
+            playlist_entry.children.iter().collect(),
+        ),
+        // mhod lengths are what the parser reads them by, unknown records are kept as is
+        Record::mhod(_) | Record::Unknown(_) => return,
+    };
+
+    let actual = get_record_size(record);
+    if len != actual {
+        found.push((magic, len, actual));
+    }
+
+    for child in children {
+        collect_wrong_lengths(child, found);
     }
 }
 
@@ -87,10 +131,32 @@ pub(crate) fn write_to_buffer(record: &Record) -> Vec<u8> {
     buf.into_inner()
 }
 
-pub(crate) fn read_from_buffer(buf: &[u8]) -> Record {
+pub(crate) fn read_from_buffer(buf: &[u8]) -> anyhow::Result<Record> {
     let mut cursor = Cursor::new(buf);
 
-    Record::read(&mut cursor).expect("binrw failed to read from buffer")
+    Ok(Record::read(&mut cursor)?)
+}
+
+/// Serializes the database the way it is stored on the device: the album list, the
+/// Podcasts playlist and library indices are regenerated from the tracks and the
+/// checksum required by `hashing_scheme` is filled in.
+pub(crate) fn write_database(
+    record: &mut Record,
+    fwid: Option<&str>,
+    signers: &[Box<dyn DatabaseSigner>],
+) -> anyhow::Result<Vec<u8>> {
+    let Record::mhbd(master) = record else {
+        bail!("database root is not an mhbd record");
+    };
+
+    albums::rebuild_album_list(master);
+    podcasts::rebuild_podcasts(master);
+    library_index::rebuild_library_indices(master);
+
+    let mut buf = write_to_buffer(record);
+    checksum::sign(&mut buf, fwid, signers)?;
+
+    Ok(buf)
 }
 
 #[cfg(test)]
@@ -99,7 +165,12 @@ mod tests {
 
     use binrw::BinRead;
 
-    use crate::db::hash58;
+    use crate::db::{
+        checksum::{self, Hash58Signer, Hash72Signer},
+        hash58,
+        hash72::{self, HashInfo},
+        itunesdb::{Data, DataContainer, ListContainer, RuleOperand, UnknownRecord},
+    };
 
     use super::{List, Record};
 
@@ -135,7 +206,7 @@ mod tests {
             .try_into()
             .unwrap();
 
-        let master = super::read_from_buffer(on_disk);
+        let master = super::read_from_buffer(on_disk).expect("failed to read database");
         let mut written = super::write_to_buffer(&master).to_vec();
         let written_copy = written.clone();
 
@@ -144,12 +215,261 @@ mod tests {
 
         let new_hash = hash58::generate_hash58(FWID, &written).expect("failed to hash database");
 
-        crate::util::print_byte_diffs(&on_disk_copy, &written_copy);
+        // The database is written back field for field as it was read
+        let diff = super::super::dump::diff_itunesdb(&on_disk_copy, &written_copy).unwrap();
+        assert!(diff.is_empty(), "{diff}");
 
         assert_eq!(stored_hash, new_hash);
     }
 
     #[test]
+    fn write_database_signs_hash58() {
+        const FWID: &str = "000A270013E10993";
+
+        let mut root = super::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap();
+        let written = super::write_database(&mut root, Some(FWID), &[Box::new(Hash58Signer)])
+            .expect("failed to write database");
+
+        let expected = hash58::generate_hash58(FWID, &checksum::hash58_input(&written)).unwrap();
+
+        assert_eq!(written[0x58..0x6C], expected);
+    }
+
+    #[test]
+    fn write_database_signs_hash72() {
+        const FWID: &str = "000A270013E10993";
+
+        let hash_info = HashInfo {
+            uuid: [0; 20],
+            rndpart: *b"random bytes",
+            iv: *b"per device iv!!!",
+        };
+
+        let mut root = super::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap();
+        let Record::mhbd(master) = &mut root else {
+            panic!("root is not a master record");
+        };
+        master.hashing_scheme = 2;
+
+        let written = super::write_database(
+            &mut root,
+            Some(FWID),
+            &[Box::new(Hash72Signer(hash_info.clone()))],
+        )
+        .expect("failed to write database");
+
+        let expected = hash72::generate_hash72(&hash_info, &checksum::hash72_input(&written));
+        assert_eq!(written[0x72..0xA0], expected);
+
+        let extracted = checksum::extract_hash_info(&written, FWID)
+            .expect("failed to extract HashInfo")
+            .expect("database is not signed with hash72");
+
+        assert_eq!(extracted.rndpart, hash_info.rndpart);
+        assert_eq!(extracted.iv, hash_info.iv);
+        assert_eq!(
+            extracted.uuid[..8],
+            [0x00, 0x0A, 0x27, 0x00, 0x13, 0xE1, 0x09, 0x93]
+        );
+
+        // A database signed with the extracted HashInfo carries the same hash
+        let mut root = super::read_from_buffer(&written).unwrap();
+        let resigned =
+            super::write_database(&mut root, Some(FWID), &[Box::new(Hash72Signer(extracted))])
+                .unwrap();
+        assert_eq!(resigned[0x72..0xA0], written[0x72..0xA0]);
+    }
+
+    #[test]
+    fn string_mhods_round_trip() {
+        const TYPES: [u32; 22] = [
+            1, 3, 4, 12, 18, 19, 20, 21, 22, 23, 24, 25, 27, 28, 29, 30, 31, 200, 201, 202, 203,
+            204,
+        ];
+        let text = "Ünïcødé ✓";
+
+        for data_type in TYPES {
+            let string: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+            let mut bytes = b"mhod".to_vec();
+            bytes.extend(24u32.to_le_bytes());
+            bytes.extend((24 + 16 + string.len() as u32).to_le_bytes());
+            bytes.extend(data_type.to_le_bytes());
+            bytes.extend([0; 8]);
+            bytes.extend(1u32.to_le_bytes());
+            bytes.extend((string.len() as u32).to_le_bytes());
+            bytes.extend(0x1234u32.to_le_bytes());
+            bytes.extend(0u32.to_le_bytes());
+            bytes.extend(&string);
+
+            let record = super::read_from_buffer(&bytes).expect("failed to read mhod");
+            let Record::mhod(container) = &record else {
+                panic!("not an mhod");
+            };
+
+            assert_eq!(container.data.as_u32(), data_type);
+            assert_eq!(container.data.text().as_deref(), Some(text));
+            assert_eq!(super::write_to_buffer(&record), bytes, "mhod {data_type}");
+        }
+    }
+
+    fn raw_mhod(data_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut bytes = b"mhod".to_vec();
+        bytes.extend(24u32.to_le_bytes());
+        bytes.extend((24 + body.len() as u32).to_le_bytes());
+        bytes.extend(data_type.to_le_bytes());
+        bytes.extend([0; 8]);
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn unknown_mhod_round_trip() {
+        let bytes = raw_mhod(999, &[1, 2, 3, 4, 5]);
+
+        let record = super::read_from_buffer(&bytes).expect("failed to read mhod");
+        let Record::mhod(DataContainer {
+            data:
+                Data::Unknown {
+                    data_type,
+                    bytes: body,
+                },
+            ..
+        }) = &record
+        else {
+            panic!("mhod 999 was not kept as unknown data");
+        };
+
+        assert_eq!(*data_type, 999);
+        assert_eq!(body, &[1, 2, 3, 4, 5]);
+        assert_eq!(super::write_to_buffer(&record), bytes);
+    }
+
+    #[test]
+    fn smart_playlist_mhods_round_trip() {
+        let mut body = vec![1, 1, 1, 3, 2, 0, 0, 0];
+        body.extend(25u32.to_le_bytes());
+        body.extend([1, 0]);
+        body.resize(72, 0);
+
+        let bytes = raw_mhod(50, &body);
+        let record = super::read_from_buffer(&bytes).expect("failed to read mhod");
+        let Record::mhod(DataContainer {
+            data: Data::SmartPlaylistData(data),
+            ..
+        }) = &record
+        else {
+            panic!("mhod 50 was not decoded");
+        };
+        assert_eq!(data.limit_type, 3);
+        assert_eq!(data.limit_value, 25);
+        assert_eq!(super::write_to_buffer(&record), bytes);
+
+        // Artist contains "Ünï", then a play count greater than 10
+        let artist: Vec<u8> = "Ünï".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut body = b"SLst".to_vec();
+        body.extend(0x0001_0000u32.to_be_bytes());
+        body.extend(2u32.to_be_bytes());
+        body.extend(1u32.to_be_bytes());
+        body.extend([0; 120]);
+        body.extend(4u32.to_be_bytes());
+        body.extend(0x0100_0002u32.to_be_bytes());
+        body.extend([0; 44]);
+        body.extend((artist.len() as u32).to_be_bytes());
+        body.extend(&artist);
+        body.extend(0x16u32.to_be_bytes());
+        body.extend(0x10u32.to_be_bytes());
+        body.extend([0; 44]);
+        body.extend(0x44u32.to_be_bytes());
+        for value in [10u64, 0, 1, 10, 0, 1] {
+            body.extend(value.to_be_bytes());
+        }
+        body.extend([0; 20]);
+
+        let bytes = raw_mhod(51, &body);
+        let record = super::read_from_buffer(&bytes).expect("failed to read mhod");
+        let Record::mhod(DataContainer {
+            data: Data::SmartPlaylistRules(rules),
+            ..
+        }) = &record
+        else {
+            panic!("mhod 51 was not decoded");
+        };
+        assert_eq!(rules.match_any, 1);
+        assert_eq!(rules.rules.len(), 2);
+        assert_eq!(
+            rules.rules[0].operand,
+            RuleOperand::Text {
+                text: "Ünï".to_string()
+            }
+        );
+        assert!(matches!(
+            rules.rules[1].operand,
+            RuleOperand::Value { from_value: 10, .. }
+        ));
+        assert_eq!(super::write_to_buffer(&record), bytes);
+    }
+
+    #[test]
+    fn utf8_string_mhod_round_trip() {
+        let mut body = Vec::new();
+        body.extend(2u32.to_le_bytes());
+        body.extend(3u32.to_le_bytes());
+        body.extend([0; 8]);
+        body.extend(b"abc");
+
+        let bytes = raw_mhod(1, &body);
+        let record = super::read_from_buffer(&bytes).expect("failed to read mhod");
+        let Record::mhod(container) = &record else {
+            panic!("not an mhod");
+        };
+
+        assert_eq!(container.data.text().as_deref(), Some("abc"));
+        assert_eq!(super::write_to_buffer(&record), bytes);
+    }
+
+    #[test]
+    fn unknown_lists_and_records_round_trip() {
+        let mut root = super::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap();
+        let Record::mhbd(master) = &mut root else {
+            panic!("root is not a master record");
+        };
+
+        master.children.push(Record::mhsd(ListContainer {
+            len: 0,
+            list: List::Unknown {
+                list_type: 8,
+                bytes: b"mhli\x5c\0\0\0\0\0\0\0".to_vec(),
+            },
+        }));
+        master.children.push(Record::Unknown(UnknownRecord {
+            magic: *b"mhzz",
+            header_len: 12,
+            len: 16,
+            bytes: vec![1, 2, 3, 4],
+        }));
+
+        let written = super::write_to_buffer(&root);
+        let Record::mhbd(master) = super::read_from_buffer(&written).unwrap() else {
+            panic!("root is not a master record");
+        };
+
+        assert!(master.children.iter().any(|child| matches!(
+            child,
+            Record::mhsd(ListContainer {
+                list: List::Unknown { list_type: 8, .. },
+                ..
+            })
+        )));
+        assert!(master
+            .children
+            .iter()
+            .any(|child| matches!(child, Record::Unknown(record) if &record.magic == b"mhzz")));
+        assert_eq!(super::write_to_buffer(&Record::mhbd(master)), written);
+    }
+
+    #[test]
+    #[allow(clippy::single_match)]
     fn parse_itdb() {
         let bytes = include_bytes!("./sample/iTunesDB");
         let mut cursor = Cursor::new(&bytes[..]);
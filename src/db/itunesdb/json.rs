@@ -0,0 +1,137 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{io, Record, Utf16String};
+
+/// Byte arrays and blobs as hex strings, serde only handles arrays up to 32 elements
+/// and a list of numbers per padding field is unreadable.
+pub(crate) mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<T: AsRef<[u8]>, S: Serializer>(
+        bytes: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub(crate) fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let bytes = hex::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)?;
+        let len = bytes.len();
+        T::try_from(bytes).map_err(|_| D::Error::custom(format!("unexpected length {len}")))
+    }
+}
+
+/// Podcast URLs as strings.
+pub(crate) mod utf8 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(bytes))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        Ok(String::deserialize(deserializer)?.into_bytes())
+    }
+}
+
+/// String mhods as their text rather than the encoded bytes.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Text {
+    text: String,
+    encoding: u32,
+    #[serde(default)]
+    unk_0x08: u32,
+    #[serde(default)]
+    unk_0x0C: u32,
+}
+
+impl From<Utf16String> for Text {
+    fn from(string: Utf16String) -> Self {
+        Text {
+            text: string.as_string(),
+            encoding: string.encoding,
+            unk_0x08: string.unk_0x08,
+            unk_0x0C: string.unk_0x0C,
+        }
+    }
+}
+
+impl From<Text> for Utf16String {
+    fn from(text: Text) -> Self {
+        let bytes = match text.encoding {
+            2 => text.text.into_bytes(),
+            _ => text
+                .text
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+        };
+
+        Utf16String {
+            encoding: text.encoding,
+            unk_0x08: text.unk_0x08,
+            unk_0x0C: text.unk_0x0C,
+            bytes,
+        }
+    }
+}
+
+/// The iTunesDB in `itunesdb` as JSON, one object per record keyed by its magic.
+/// Lengths and counts are left out since they follow from the contents.
+pub fn export_json(itunesdb: &[u8]) -> anyhow::Result<String> {
+    let root = io::read_from_buffer(itunesdb).context("failed to parse the iTunesDB")?;
+    to_json(&root)
+}
+
+/// Builds an iTunesDB from JSON in the format of [`export_json`]. The checksum isn't
+/// filled in, use [`crate::iPod::import_json`] to write it to a device.
+pub fn import_json(json: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(io::write_to_buffer(&from_json(json)?))
+}
+
+pub(crate) fn to_json(root: &Record) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(root)?)
+}
+
+/// The record tree described by `json`, with the lengths filled in as if it had been
+/// read from a database.
+pub(crate) fn from_json(json: &str) -> anyhow::Result<Record> {
+    let root: Record = serde_json::from_str(json).context("failed to parse the JSON")?;
+    io::read_from_buffer(&io::write_to_buffer(&root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::io;
+
+    #[test]
+    fn json_round_trip() {
+        let bytes = include_bytes!("./sample/iTunesDB");
+        let written = io::write_to_buffer(&io::read_from_buffer(bytes).unwrap());
+
+        let json = super::export_json(bytes).unwrap();
+        assert!(json.contains("\"mhbd\""));
+        assert!(json.contains("\"text\": \"Song One\""));
+        assert!(!json.contains("\"len\""));
+
+        assert_eq!(super::import_json(&json).unwrap(), written);
+
+        // Hand edits come back as records
+        let edited = json.replacen("Song One", "Edited", 1);
+        let root = super::from_json(&edited).unwrap();
+        let super::Record::mhbd(master) = root else {
+            panic!("root is not an mhbd record");
+        };
+        assert_eq!(
+            master.tracks().next().unwrap().title().as_deref(),
+            Some("Edited")
+        );
+
+        assert!(super::import_json("{\"mhbd\": {}}").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use std::cmp::Ordering;
+
+use super::{
+    Data, DataContainer, JumpTable, JumpTableEntry, LibraryPlaylistIndex, Master, Record, Track,
+};
+
+/// Sort orders the device browses the library by. Each gets a LibraryPlaylistIndex
+/// (mhod 52) and a JumpTable (mhod 53) in the master playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IndexType {
+    Title = 0x03,
+    Album = 0x04,
+    Artist = 0x05,
+    Genre = 0x07,
+    Composer = 0x12,
+}
+
+const INDEX_TYPES: [IndexType; 5] = [
+    IndexType::Title,
+    IndexType::Album,
+    IndexType::Artist,
+    IndexType::Genre,
+    IndexType::Composer,
+];
+
+/// A string folded the way the iPod collates it: case and accents are ignored,
+/// leading punctuation is skipped and names starting with anything other than a
+/// letter sort after all the letters.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CollationKey {
+    non_letter: bool,
+    folded: String,
+}
+
+impl CollationKey {
+    /// Uses the sort name when one is set, otherwise the name without a leading article.
+    fn new(name: Option<String>, sort_name: Option<String>) -> Self {
+        let name = match sort_name.filter(|sort_name| !sort_name.is_empty()) {
+            Some(sort_name) => sort_name,
+            None => strip_article(&name.unwrap_or_default()).to_string(),
+        };
+
+        let folded: String = name
+            .chars()
+            .skip_while(|c| !c.is_alphanumeric())
+            .flat_map(fold_char)
+            .collect();
+
+        CollationKey {
+            non_letter: !folded.starts_with(|c: char| c.is_alphabetic()),
+            folded,
+        }
+    }
+
+    /// The jump table letter: the uppercase first letter, or '0' for everything else.
+    fn letter(&self) -> u16 {
+        match self.folded.chars().next() {
+            Some(c) if !self.non_letter => c
+                .to_uppercase()
+                .next()
+                .and_then(|c| u16::try_from(c as u32).ok())
+                .unwrap_or(b'0' as u16),
+            _ => b'0' as u16,
+        }
+    }
+}
+
+fn strip_article(name: &str) -> &str {
+    for article in ["the ", "a ", "an "] {
+        if name.len() > article.len()
+            && name.is_char_boundary(article.len())
+            && name[..article.len()].eq_ignore_ascii_case(article)
+        {
+            return &name[article.len()..];
+        }
+    }
+    name
+}
+
+fn fold_char(c: char) -> Vec<char> {
+    let folded = match c {
+        'À'..='Å' | 'à'..='å' | 'Ā'..='ą' => 'a',
+        'Ç' | 'ç' | 'Ć'..='č' => 'c',
+        'È'..='Ë' | 'è'..='ë' | 'Ē'..='ě' => 'e',
+        'Ì'..='Ï' | 'ì'..='ï' | 'Ĩ'..='ı' => 'i',
+        'Ñ' | 'ñ' | 'Ń'..='ň' => 'n',
+        'Ò'..='Ö' | 'Ø' | 'ò'..='ö' | 'ø' | 'Ō'..='ő' => 'o',
+        'Ù'..='Ü' | 'ù'..='ü' | 'Ũ'..='ų' => 'u',
+        'Ý' | 'ý' | 'ÿ' => 'y',
+        'Š' | 'š' | 'Ś'..='ş' => 's',
+        'Ž' | 'ž' | 'Ź'..='ż' => 'z',
+        c => return c.to_lowercase().collect(),
+    };
+    vec![folded]
+}
+
+struct TrackKeys {
+    title: CollationKey,
+    album: CollationKey,
+    album_artist: CollationKey,
+    artist: CollationKey,
+    genre: CollationKey,
+    composer: CollationKey,
+    disc: u32,
+    track: u32,
+}
+
+impl TrackKeys {
+    fn new(track: &Track) -> Self {
+        TrackKeys {
+            title: CollationKey::new(track.title(), track.title_sort()),
+            album: CollationKey::new(track.album(), track.album_sort()),
+            album_artist: CollationKey::new(
+                track.album_artist().or_else(|| track.artist()),
+                track.album_artist_sort().or_else(|| track.artist_sort()),
+            ),
+            artist: CollationKey::new(track.artist(), track.artist_sort()),
+            genre: CollationKey::new(track.genre(), None),
+            composer: CollationKey::new(track.composer(), track.composer_sort()),
+            disc: track.album_disc_index,
+            track: track.album_index,
+        }
+    }
+
+    fn primary(&self, index_type: IndexType) -> &CollationKey {
+        match index_type {
+            IndexType::Title => &self.title,
+            IndexType::Album => &self.album,
+            IndexType::Artist => &self.artist,
+            IndexType::Genre => &self.genre,
+            IndexType::Composer => &self.composer,
+        }
+    }
+
+    /// Tracks of an album stay in disc and track order in every index but Title.
+    fn cmp_album(&self, other: &Self) -> Ordering {
+        self.album
+            .cmp(&other.album)
+            .then_with(|| self.album_artist.cmp(&other.album_artist))
+            .then_with(|| self.disc.cmp(&other.disc))
+            .then_with(|| self.track.cmp(&other.track))
+            .then_with(|| self.title.cmp(&other.title))
+    }
+
+    fn cmp(&self, other: &Self, index_type: IndexType) -> Ordering {
+        match index_type {
+            IndexType::Title => self.title.cmp(&other.title),
+            IndexType::Album => self.cmp_album(other),
+            IndexType::Artist => self
+                .artist
+                .cmp(&other.artist)
+                .then_with(|| self.cmp_album(other)),
+            IndexType::Genre => self
+                .genre
+                .cmp(&other.genre)
+                .then_with(|| self.artist.cmp(&other.artist))
+                .then_with(|| self.cmp_album(other)),
+            IndexType::Composer => self
+                .composer
+                .cmp(&other.composer)
+                .then_with(|| self.title.cmp(&other.title)),
+        }
+    }
+}
+
+fn build_index(keys: &[TrackKeys], index_type: IndexType) -> (LibraryPlaylistIndex, JumpTable) {
+    let mut indices: Vec<u32> = (0..keys.len() as u32).collect();
+    indices.sort_by(|a, b| keys[*a as usize].cmp(&keys[*b as usize], index_type));
+
+    let mut entries = Vec::<JumpTableEntry>::new();
+
+    for (position, index) in indices.iter().enumerate() {
+        let letter = keys[*index as usize].primary(index_type).letter();
+
+        match entries.last_mut() {
+            Some(entry) if entry.letter == letter => entry.count += 1,
+            _ => entries.push(JumpTableEntry {
+                letter,
+                start: position as u32,
+                count: 1,
+            }),
+        }
+    }
+
+    (
+        LibraryPlaylistIndex {
+            index_type: index_type as u32,
+            indices,
+        },
+        JumpTable {
+            index_type: index_type as u32,
+            entries,
+        },
+    )
+}
+
+/// Regenerates the library playlist indices and jump tables of the master playlist
+/// in every playlist list. Indices are positions in the track list.
+pub(crate) fn rebuild_library_indices(master: &mut Master) {
+    let keys: Vec<TrackKeys> = match master.list(0x01) {
+        Some(list) => list
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                Record::mhit(track) => Some(TrackKeys::new(track)),
+                _ => None,
+            })
+            .collect(),
+        None => return,
+    };
+
+    let mut mhods = Vec::new();
+
+    for index_type in INDEX_TYPES {
+        let (index, jump_table) = build_index(&keys, index_type);
+
+        mhods.push(Record::mhod(DataContainer::new(
+            Data::LibraryPlaylistIndex(index),
+        )));
+        mhods.push(Record::mhod(DataContainer::new(Data::JumpTable(
+            jump_table,
+        ))));
+    }
+
+    for list_type in [0x02, 0x03, 0x05] {
+        let Some(list) = master.list_mut(list_type) else {
+            continue;
+        };
+
+        for child in &mut list.children {
+            match child {
+                Record::mhyp(playlist) if playlist.is_master_flag != 0 => {
+                    playlist.children.retain(|child| {
+                        !matches!(
+                            child,
+                            Record::mhod(DataContainer {
+                                data: Data::LibraryPlaylistIndex(_) | Data::JumpTable(_),
+                                ..
+                            })
+                        )
+                    });
+                    playlist.children.extend(mhods.iter().cloned());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CollationKey, IndexType};
+    use crate::db::itunesdb::{io, Data, DataContainer, Record};
+
+    #[test]
+    fn collation() {
+        let key = |name: &str| CollationKey::new(Some(name.to_string()), None);
+
+        assert!(key("The Beatles") < key("Coldplay"));
+        assert!(key("zebra") < key("10 Years"));
+        assert!(key("Émilie") < key("Fleet Foxes"));
+        assert_eq!(key("\"Weird Al\" Yankovic").letter(), 'W' as u16);
+        assert_eq!(key("...And You Will Know Us").letter(), 'A' as u16);
+        assert_eq!(key("311").letter(), '0' as u16);
+        assert_eq!(
+            CollationKey::new(Some("Beatles".into()), Some("Zz".into())).letter(),
+            'Z' as u16
+        );
+    }
+
+    #[test]
+    fn rebuild_library_indices() {
+        let mut root = io::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap();
+        let Record::mhbd(master) = &mut root else {
+            panic!("root is not a master record");
+        };
+
+        super::rebuild_library_indices(master);
+
+        let written = io::write_to_buffer(&root);
+        let Record::mhbd(master) = io::read_from_buffer(&written).unwrap() else {
+            panic!("root is not a master record");
+        };
+
+        let track_count = master.list(0x01).unwrap().children.len();
+        let playlist = master
+            .list(0x02)
+            .unwrap()
+            .children
+            .iter()
+            .find_map(|child| match child {
+                Record::mhyp(playlist) if playlist.is_master_flag != 0 => Some(playlist),
+                _ => None,
+            })
+            .expect("no master playlist");
+
+        let mut index_count = 0;
+
+        for child in &playlist.children {
+            match child {
+                Record::mhod(DataContainer {
+                    data: Data::LibraryPlaylistIndex(index),
+                    ..
+                }) => {
+                    let mut sorted = index.indices.clone();
+                    sorted.sort();
+
+                    assert_eq!(sorted, (0..track_count as u32).collect::<Vec<_>>());
+                    index_count += 1;
+                }
+                Record::mhod(DataContainer {
+                    data: Data::JumpTable(jump_table),
+                    ..
+                }) => {
+                    let mut next = 0;
+
+                    for entry in &jump_table.entries {
+                        assert_eq!(entry.start, next);
+                        next += entry.count;
+                    }
+                    assert_eq!(next as usize, track_count);
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(index_count, super::INDEX_TYPES.len());
+    }
+}
@@ -1,12 +1,31 @@
-#![allow(unused, non_camel_case_types, non_snake_case)]
+#![allow(
+    unused,
+    non_camel_case_types,
+    non_snake_case,
+    clippy::large_enum_variant,
+    clippy::enum_variant_names
+)]
 
 use binrw::binrw;
 
+pub(crate) mod albums;
+pub(crate) mod chapters;
+pub(crate) mod check;
+pub(crate) mod dump;
+pub(crate) mod duplicates;
 pub(crate) mod io;
+#[cfg(feature = "serde")]
+pub(crate) mod json;
+pub(crate) mod library_index;
+pub(crate) mod playlist;
+pub(crate) mod podcasts;
+pub(crate) mod rebuild;
+pub(crate) mod track;
 
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum Record {
     /// Upper level records
     #[brw(magic = b"mhbd")]
@@ -32,15 +51,35 @@ pub(crate) enum Record {
     /// Leaf record, variable len
     #[brw(magic = b"mhod")]
     mhod(DataContainer),
+
+    /// Any record with a magic not listed above, kept byte for byte
+    Unknown(UnknownRecord),
 }
 
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct UnknownRecord {
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
+    magic: [u8; 4],
+    header_len: u32,
+    len: u32,
+
+    #[br(count = len.saturating_sub(12))]
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
+    bytes: Vec<u8>,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Master {
     #[bw(calc = 244)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
     unk_0x0C: u32,
     version: u32,
@@ -54,14 +93,17 @@ pub(crate) struct Master {
     unk_0x24: u64,
     unk_0x2C: u32,
     unk_0x30: u16,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x32: [u8; 20],
     lang: u16,
     persistent_id: u64,
     unk_0x50: u32,
     unk_0x54: u32,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     hash_0x58: [u8; 20],
     timezone_offset: i32,
     unk_0x70: u16,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     hash_0x72: [u8; 46],
     unk_0xA0: u32,
     audio_lang: u16,
@@ -72,27 +114,130 @@ pub(crate) struct Master {
     children: Vec<Record>,
 }
 
+impl Master {
+    pub(crate) fn list(&self, list_type: u32) -> Option<&RecordList> {
+        self.children.iter().find_map(|child| match child {
+            Record::mhsd(container) if container.list.as_u32() == list_type => {
+                container.list.records()
+            }
+            _ => None,
+        })
+    }
+
+    pub(crate) fn list_mut(&mut self, list_type: u32) -> Option<&mut RecordList> {
+        self.children.iter_mut().find_map(|child| match child {
+            Record::mhsd(container) if container.list.as_u32() == list_type => {
+                container.list.records_mut()
+            }
+            _ => None,
+        })
+    }
+
+    pub(crate) fn tracks(&self) -> impl Iterator<Item = &Track> {
+        self.list(0x01)
+            .into_iter()
+            .flat_map(|list| &list.children)
+            .filter_map(|child| match child {
+                Record::mhit(track) => Some(track),
+                _ => None,
+            })
+    }
+
+    pub(crate) fn track_mut(&mut self, unique_id: u32) -> Option<&mut Track> {
+        self.list_mut(0x01)?
+            .children
+            .iter_mut()
+            .find_map(|child| match child {
+                Record::mhit(track) if track.unique_id == unique_id => Some(track),
+                _ => None,
+            })
+    }
+
+    /// An id no track uses yet.
+    pub(crate) fn next_track_id(&self) -> u32 {
+        self.tracks()
+            .map(|track| track.unique_id)
+            .max()
+            .unwrap_or(0)
+            .saturating_add(1)
+    }
+
+    /// Adds the track to the track list and the master playlists.
+    pub(crate) fn add_track(&mut self, track: Track) {
+        let unique_id = track.unique_id;
+
+        if let Some(tracks) = self.list_mut(0x01) {
+            tracks.children.push(Record::mhit(track));
+        }
+
+        for list_type in [0x02, 0x03] {
+            let Some(playlists) = self.list_mut(list_type) else {
+                continue;
+            };
+
+            for playlist in playlists.children.iter_mut() {
+                match playlist {
+                    Record::mhyp(playlist) if playlist.is_master_flag != 0 => playlist
+                        .entries
+                        .push(Record::mhip(PlaylistEntry::new(unique_id))),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Removes the track from the track list and every playlist.
+    pub(crate) fn remove_track(&mut self, unique_id: u32) -> Option<Track> {
+        let tracks = self.list_mut(0x01)?;
+        let position = tracks.children.iter().position(
+            |child| matches!(child, Record::mhit(track) if track.unique_id == unique_id),
+        )?;
+        let Record::mhit(track) = tracks.children.remove(position) else {
+            return None;
+        };
+
+        for list_type in [0x02, 0x03, 0x05] {
+            let Some(playlists) = self.list_mut(list_type) else {
+                continue;
+            };
+
+            for playlist in playlists.children.iter_mut() {
+                if let Record::mhyp(playlist) = playlist {
+                    playlist.entries.retain(|entry| {
+                        !matches!(entry, Record::mhip(entry) if entry.track_id == unique_id)
+                    });
+                }
+            }
+        }
+
+        Some(track)
+    }
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct ListContainer {
     #[bw(calc = 96)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
 
     #[bw(calc = list.as_u32())]
     list_type: u32,
 
     #[brw(pad_before = 80)]
-    #[br(args { list_type: list_type })]
+    #[br(args { list_type: list_type, bytes_left: len.saturating_sub(header_len) })]
     list: List,
 }
 
 #[binrw]
 #[brw(little)]
-#[br(import { list_type: u32 })]
+#[br(import { list_type: u32, bytes_left: u32 })]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum List {
     #[br(pre_assert(list_type == 0x01))]
     #[brw(magic = b"mhlt")]
@@ -113,6 +258,17 @@ pub enum List {
     #[br(pre_assert(list_type == 0x05))]
     #[brw(magic = b"mhlp")]
     InclSmartPlaylists(RecordList),
+
+    /// List types not listed above (newer iTunes versions write more), kept byte for byte
+    Unknown {
+        #[br(calc = list_type)]
+        #[bw(ignore)]
+        list_type: u32,
+
+        #[br(count = bytes_left)]
+        #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
+        bytes: Vec<u8>,
+    },
 }
 
 impl List {
@@ -123,6 +279,29 @@ impl List {
             List::Podcasts(_) => 0x03,
             List::Albums(_) => 0x04,
             List::InclSmartPlaylists(_) => 0x05,
+            List::Unknown { list_type, .. } => *list_type,
+        }
+    }
+
+    pub(crate) fn records(&self) -> Option<&RecordList> {
+        match self {
+            List::Tracks(list)
+            | List::Playlists(list)
+            | List::Podcasts(list)
+            | List::Albums(list)
+            | List::InclSmartPlaylists(list) => Some(list),
+            List::Unknown { .. } => None,
+        }
+    }
+
+    pub(crate) fn records_mut(&mut self) -> Option<&mut RecordList> {
+        match self {
+            List::Tracks(list)
+            | List::Playlists(list)
+            | List::Podcasts(list)
+            | List::Albums(list)
+            | List::InclSmartPlaylists(list) => Some(list),
+            List::Unknown { .. } => None,
         }
     }
 }
@@ -130,6 +309,7 @@ impl List {
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct RecordList {
     #[bw(calc = 92)]
     header_len: u32,
@@ -146,10 +326,12 @@ pub(crate) struct RecordList {
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Track {
     #[bw(calc = 624)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
 
     #[bw(calc = children.len() as u32)]
@@ -157,6 +339,7 @@ pub(crate) struct Track {
 
     unique_id: u32, // unique id for the track, used by playlists
     visible: u32,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     file_type: [u8; 4], // looks big endian. file extension padded with spaces (ie: ' 3PM')
     vbr_flag: u8,
     mp3_flag: u8,
@@ -212,29 +395,36 @@ pub(crate) struct Track {
     samples_count_gapless: u64,
     unk_0xC4: u32, // seems to be always 0
     samples_before_end_gapless: u32,
-    mp3_encoded: u32,       // set to 1 for mp3 encoding?
-    media_type: u32,        // VERY IMPORTANT, denotes media type
-    season_number: u32,     // for tv shows only
-    episode_number: u32,    // for tv shows only
-    unk_0xDC: u32,          // seems to be 0x01 for protected files?
+    mp3_encoded: u32,    // set to 1 for mp3 encoding?
+    media_type: u32,     // VERY IMPORTANT, denotes media type
+    season_number: u32,  // for tv shows only
+    episode_number: u32, // for tv shows only
+    unk_0xDC: u32,       // seems to be 0x01 for protected files?
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0xE0: [u8; 24], // might be fields, looks like all 0s
-    gapless_data: u32,      // size in bytes from first synch frame, can be 0 for AAC
+    gapless_data: u32,   // size in bytes from first synch frame, can be 0 for AAC
     unk_0xFC: u32,
     is_gapless_track_flag: u16,
     is_gapless_album_flag: u16,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x0104: [u8; 28],
     unk_0x0120: u32,          // seems to be set to 0x8DDA0000 across samples
     unk_0x0124: u64,          // seems to be set to across track_items in database, possibly an id
     file_size_bytes_u64: u64, // seems to be the size of the track in bytes again, possibly as u64
-    unk_0x0134: [u8; 6],      // each byte seems to be set to 0x80
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
+    unk_0x0134: [u8; 6], // each byte seems to be set to 0x80
     album_id: u16,            // supposedly an album id, looks like its always 0 though
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x013A: [u8; 36], // looks like padding
     mhii_link: u64,           // need more research
     unk_0x0168: u64,          // seems to always be 0x20
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x0170: [u8; 112],
     unk_0x01E0: u32, // seems to always be 0x30DB
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x01E4: [u8; 16],
     unk_0x01F4: u32,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x01F8: [u8; 20],
 
     unk_0x020C: u32,
@@ -250,16 +440,18 @@ pub(crate) struct Track {
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Album {
     #[bw(calc = 88)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
 
     #[bw(calc = children.len() as u32)]
     child_count: u32,
 
-    unk_0x10: u32, // looks like the track ids and such that start with 5
+    album_id: u32, // referenced by the album_id field of every track on the album
     unk_0x14: u64, // looks like an id
     unk_0x1C: u32, // always 2?
     unk_0x20: u64, // looks like another id
@@ -272,10 +464,12 @@ pub(crate) struct Album {
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Playlist {
     #[bw(calc = 184)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
 
     #[bw(calc = children.len() as u32)]
@@ -294,6 +488,7 @@ pub(crate) struct Playlist {
     string_obj_count: u16,
     is_podcast_playlist_flag: u16,
     sort_order: u32,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x30: [u8; 40],
     hfs_timestamp_0x58: u32,
 
@@ -308,10 +503,12 @@ pub(crate) struct Playlist {
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct PlaylistEntry {
     #[bw(calc = 76)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
 
     #[bw(calc = children.len() as u32)]
@@ -322,9 +519,11 @@ pub(crate) struct PlaylistEntry {
     group_id: u32, // doesn't seem to actually be useful
     track_id: u32, // corresponds to an actual track in the track list
     hfs_timestamp_0x28: u32,
-    padding_0x32: [u8; 12],
     podcast_group_id: u32, // parent group that the podcast should be under, 0 for all other cases
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
+    padding_0x32: [u8; 12],
     unk_0x48: u32,
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     padding_0x52: [u8; 8],
     unk_0x60: u64,
 
@@ -337,6 +536,7 @@ pub(crate) struct PlaylistEntry {
 #[brw(little)]
 #[br(import { data_type: u32, bytes_left: u32 })]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Data {
     #[br(pre_assert(data_type ==  1))]
     Title(Utf16String),
@@ -381,46 +581,46 @@ pub enum Data {
     PodcastRssUrl(#[br(args { bytes_left })] Utf8PodcastUrl),
 
     #[br(pre_assert(data_type == 17))]
-    ChapterData(#[br(args { bytes_left })] Blob),
+    ChapterData(#[br(args { bytes_left })] ChapterData),
 
     #[br(pre_assert(data_type == 18))]
     Subtitle(Utf16String),
 
     #[br(pre_assert(data_type == 19))]
-    Show(#[br(args { bytes_left })] Blob),
+    Show(Utf16String),
 
     #[br(pre_assert(data_type == 20))]
-    EpisodeNumber(#[br(args { bytes_left })] Blob),
+    EpisodeNumber(Utf16String),
 
     #[br(pre_assert(data_type == 21))]
-    TvNetwork(#[br(args { bytes_left })] Blob),
+    TvNetwork(Utf16String),
 
     #[br(pre_assert(data_type == 22))]
     AlbumArtist(Utf16String),
 
     #[br(pre_assert(data_type == 23))]
-    ArtistSort(#[br(args { bytes_left })] Blob),
+    ArtistSort(Utf16String),
 
     #[br(pre_assert(data_type == 24))]
-    Keywords(#[br(args { bytes_left })] Blob),
+    Keywords(Utf16String),
 
     #[br(pre_assert(data_type == 25))]
-    TvShowLocale(#[br(args { bytes_left })] Blob),
+    TvShowLocale(Utf16String),
 
     #[br(pre_assert(data_type == 27))]
-    TitleSort(#[br(args { bytes_left })] Blob),
+    TitleSort(Utf16String),
 
     #[br(pre_assert(data_type == 28))]
-    AlbumSort(#[br(args { bytes_left })] Blob),
+    AlbumSort(Utf16String),
 
     #[br(pre_assert(data_type == 29))]
-    AlbumArtistSort(#[br(args { bytes_left })] Blob),
+    AlbumArtistSort(Utf16String),
 
     #[br(pre_assert(data_type == 30))]
-    ComposerSort(#[br(args { bytes_left })] Blob),
+    ComposerSort(Utf16String),
 
     #[br(pre_assert(data_type == 31))]
-    TvShowSort(#[br(args { bytes_left })] Blob),
+    TvShowSort(Utf16String),
 
     #[br(pre_assert(data_type == 32))]
     UnknownVideoBinary(#[br(args { bytes_left })] Blob),
@@ -429,16 +629,16 @@ pub enum Data {
     Copyright(Utf16String),
 
     #[br(pre_assert(data_type == 50))]
-    SmartPlaylistData(#[br(args { bytes_left })] Blob),
+    SmartPlaylistData(SmartPlaylistData),
 
     #[br(pre_assert(data_type == 51))]
-    SmartPlaylistRules(#[br(args { bytes_left })] Blob),
+    SmartPlaylistRules(SmartPlaylistRules),
 
     #[br(pre_assert(data_type == 52))]
-    LibraryPlaylistIndex(#[br(args { bytes_left })] Blob),
+    LibraryPlaylistIndex(LibraryPlaylistIndex),
 
     #[br(pre_assert(data_type == 53))]
-    JumpTable(#[br(args { bytes_left })] Blob),
+    JumpTable(JumpTable),
 
     #[br(pre_assert(data_type == 100))]
     ColumnSizingAndOrder(#[br(args { bytes_left })] Blob),
@@ -447,66 +647,395 @@ pub enum Data {
     UnknownObject(#[br(args { bytes_left })] Blob),
 
     #[br(pre_assert(data_type == 200))]
-    AlbumInAlbumList(#[br(args { bytes_left })] Blob),
+    AlbumInAlbumList(Utf16String),
 
     #[br(pre_assert(data_type == 201))]
-    ArtistInAlbumList(#[br(args { bytes_left })] Blob),
+    ArtistInAlbumList(Utf16String),
 
     #[br(pre_assert(data_type == 202))]
-    ArtistSortInAlbumList(#[br(args { bytes_left })] Blob),
+    ArtistSortInAlbumList(Utf16String),
 
     #[br(pre_assert(data_type == 203))]
-    PodcastUrlInAlbumList(#[br(args { bytes_left })] Blob),
+    PodcastUrlInAlbumList(Utf16String),
 
     #[br(pre_assert(data_type == 204))]
-    TvShowInAlbumList(#[br(args { bytes_left })] Blob),
+    TvShowInAlbumList(Utf16String),
+
+    /// Types not listed above, or listed ones that failed to parse, kept byte for byte
+    Unknown {
+        #[br(calc = data_type)]
+        #[bw(ignore)]
+        data_type: u32,
+
+        #[br(count = bytes_left)]
+        #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
+        bytes: Vec<u8>,
+    },
+}
+
+impl Data {
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            Data::Title(_) => 1,
+            Data::Location(_) => 2,
+            Data::Album(_) => 3,
+            Data::Artist(_) => 4,
+            Data::Genre(_) => 5,
+            Data::Filetype(_) => 6,
+            Data::EqSetting(_) => 7,
+            Data::Comment(_) => 8,
+            Data::Category(_) => 9,
+            Data::Composer(_) => 12,
+            Data::Grouping(_) => 13,
+            Data::Description(_) => 14,
+            Data::PodcastEnclosureUrl(_) => 15,
+            Data::PodcastRssUrl(_) => 16,
+            Data::ChapterData(_) => 17,
+            Data::Subtitle(_) => 18,
+            Data::Show(_) => 19,
+            Data::EpisodeNumber(_) => 20,
+            Data::TvNetwork(_) => 21,
+            Data::AlbumArtist(_) => 22,
+            Data::ArtistSort(_) => 23,
+            Data::Keywords(_) => 24,
+            Data::TvShowLocale(_) => 25,
+            Data::TitleSort(_) => 27,
+            Data::AlbumSort(_) => 28,
+            Data::AlbumArtistSort(_) => 29,
+            Data::ComposerSort(_) => 30,
+            Data::TvShowSort(_) => 31,
+            Data::UnknownVideoBinary(_) => 32,
+            Data::Copyright(_) => 39,
+            Data::SmartPlaylistData(_) => 50,
+            Data::SmartPlaylistRules(_) => 51,
+            Data::LibraryPlaylistIndex(_) => 52,
+            Data::JumpTable(_) => 53,
+            Data::ColumnSizingAndOrder(_) => 100,
+            Data::UnknownObject(_) => 102,
+            Data::AlbumInAlbumList(_) => 200,
+            Data::ArtistInAlbumList(_) => 201,
+            Data::ArtistSortInAlbumList(_) => 202,
+            Data::PodcastUrlInAlbumList(_) => 203,
+            Data::TvShowInAlbumList(_) => 204,
+            Data::Unknown { data_type, .. } => *data_type,
+        }
+    }
+
+    /// Builds the string mhod of the given type, `None` if the type isn't a string.
+    pub(crate) fn from_text(data_type: u32, text: &str) -> Option<Data> {
+        let string = Utf16String::new(text);
+
+        Some(match data_type {
+            1 => Data::Title(string),
+            2 => Data::Location(string),
+            3 => Data::Album(string),
+            4 => Data::Artist(string),
+            5 => Data::Genre(string),
+            6 => Data::Filetype(string),
+            7 => Data::EqSetting(string),
+            8 => Data::Comment(string),
+            9 => Data::Category(string),
+            12 => Data::Composer(string),
+            13 => Data::Grouping(string),
+            14 => Data::Description(string),
+            18 => Data::Subtitle(string),
+            19 => Data::Show(string),
+            20 => Data::EpisodeNumber(string),
+            21 => Data::TvNetwork(string),
+            22 => Data::AlbumArtist(string),
+            23 => Data::ArtistSort(string),
+            24 => Data::Keywords(string),
+            25 => Data::TvShowLocale(string),
+            27 => Data::TitleSort(string),
+            28 => Data::AlbumSort(string),
+            29 => Data::AlbumArtistSort(string),
+            30 => Data::ComposerSort(string),
+            31 => Data::TvShowSort(string),
+            39 => Data::Copyright(string),
+            200 => Data::AlbumInAlbumList(string),
+            201 => Data::ArtistInAlbumList(string),
+            202 => Data::ArtistSortInAlbumList(string),
+            203 => Data::PodcastUrlInAlbumList(string),
+            204 => Data::TvShowInAlbumList(string),
+            _ => return None,
+        })
+    }
+
+    /// Returns the decoded text of string mhods, `None` for binary ones.
+    pub(crate) fn text(&self) -> Option<String> {
+        match self {
+            Data::Title(string)
+            | Data::Location(string)
+            | Data::Album(string)
+            | Data::Artist(string)
+            | Data::Genre(string)
+            | Data::Filetype(string)
+            | Data::EqSetting(string)
+            | Data::Comment(string)
+            | Data::Category(string)
+            | Data::Composer(string)
+            | Data::Grouping(string)
+            | Data::Description(string)
+            | Data::Subtitle(string)
+            | Data::Show(string)
+            | Data::EpisodeNumber(string)
+            | Data::TvNetwork(string)
+            | Data::AlbumArtist(string)
+            | Data::ArtistSort(string)
+            | Data::Keywords(string)
+            | Data::TvShowLocale(string)
+            | Data::TitleSort(string)
+            | Data::AlbumSort(string)
+            | Data::AlbumArtistSort(string)
+            | Data::ComposerSort(string)
+            | Data::TvShowSort(string)
+            | Data::Copyright(string)
+            | Data::AlbumInAlbumList(string)
+            | Data::ArtistInAlbumList(string)
+            | Data::ArtistSortInAlbumList(string)
+            | Data::PodcastUrlInAlbumList(string)
+            | Data::TvShowInAlbumList(string) => Some(string.as_string()),
+            _ => None,
+        }
+    }
 }
 
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct DataContainer {
     #[bw(calc = 24)]
     header_len: u32,
 
+    #[cfg_attr(feature = "serde", serde(skip))] // recomputed by io::write_to_buffer
     len: u32,
+    #[cfg_attr(feature = "serde", serde(skip))] // follows from data, see io::write_to_buffer
     data_type: u32,
 
     #[brw(pad_before = 8)]
-    #[br(args { data_type: data_type, bytes_left: len - header_len })]
+    #[br(args { data_type: data_type, bytes_left: len.saturating_sub(header_len) })]
+    #[br(pad_size_to = len.saturating_sub(header_len))]
     data: Data,
 }
 
+impl DataContainer {
+    pub(crate) fn new(data: Data) -> Self {
+        DataContainer {
+            len: 0, // filled in by io::write_to_buffer
+            data_type: data.as_u32(),
+            data,
+        }
+    }
+}
+
 #[binrw]
 #[brw(little)]
 #[br(import { bytes_left: u32 })]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Blob {
     #[br(count = bytes_left)]
+    #[cfg_attr(feature = "serde", serde(with = "json::hex_bytes"))]
     bytes: Vec<u8>,
 }
 
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "json::Text", from = "json::Text"))]
 pub(crate) struct Utf16String {
-    position: u32,
+    encoding: u32, // 1 for utf16, 2 for utf8 (written by some third party tools)
 
-    #[bw(calc = string.len() as u32 * 2)]
+    #[bw(calc = bytes.len() as u32)]
     len: u32,
 
     unk_0x08: u32,
     unk_0x0C: u32,
 
-    #[br(count = len / 2)]
-    string: Vec<u16>,
+    #[br(count = len)]
+    bytes: Vec<u8>,
+}
+
+impl Utf16String {
+    pub(crate) fn new(s: &str) -> Self {
+        Utf16String {
+            encoding: 1,
+            unk_0x08: 0,
+            unk_0x0C: 0,
+            bytes: s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        }
+    }
+
+    pub(crate) fn as_string(&self) -> String {
+        match self.encoding {
+            2 => String::from_utf8_lossy(&self.bytes).into_owned(),
+            _ => {
+                let units: Vec<u16> = self
+                    .bytes
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect();
+
+                String::from_utf16_lossy(&units)
+            }
+        }
+    }
+}
+
+/// Chapter list of audiobooks and podcasts. The chapters are stored as a big endian
+/// atom tree after three little endian words, see chapters.rs.
+#[binrw]
+#[brw(little)]
+#[br(import { bytes_left: u32 })]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct ChapterData {
+    unk_0x00: u32,
+    unk_0x04: u32,
+    unk_0x08: u32,
+
+    #[br(count = bytes_left.saturating_sub(12))]
+    #[br(try_map = |atoms: Vec<u8>| chapters::Chapters::from_atoms(&atoms))]
+    #[bw(map = |chapters: &chapters::Chapters| chapters.to_atoms())]
+    chapters: chapters::Chapters,
+}
+
+/// Track indices into the track list, sorted by one of the library sort orders.
+/// Lives in the master playlist so the device doesn't have to sort when browsing.
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct LibraryPlaylistIndex {
+    index_type: u32, // which sort order this is, see library_index::IndexType
+
+    #[bw(calc = indices.len() as u32)]
+    count: u32,
+
+    #[brw(pad_before = 40)]
+    #[br(count = count)]
+    indices: Vec<u32>,
+}
+
+/// Letter jump table for the LibraryPlaylistIndex with the same `index_type`.
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct JumpTable {
+    index_type: u32,
+
+    #[bw(calc = entries.len() as u32)]
+    count: u32,
+
+    #[brw(pad_before = 8)]
+    #[br(count = count)]
+    entries: Vec<JumpTableEntry>,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct JumpTableEntry {
+    #[brw(pad_after = 2)]
+    letter: u16, // uppercase utf16 letter, '0' for entries not starting with a letter
+
+    start: u32, // position of the first entry in the LibraryPlaylistIndex
+    count: u32,
+}
+
+/// Settings of a smart playlist, its rules are in the SmartPlaylistRules next to it.
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct SmartPlaylistData {
+    live_update: u8,
+    check_rules: u8,
+    check_limits: u8,
+    limit_type: u8, // 1 minutes, 2 MB, 3 songs, 4 hours, 5 GB
+    #[brw(pad_after = 3)]
+    limit_sort: u8, // 2 random, 3 title, 4 album, 5 artist, 7 genre, 0x10 date added...
+    limit_value: u32,
+    match_checked_only: u8,
+    #[brw(pad_after = 58)]
+    reverse_limit_sort: u8,
+}
+
+/// The rules of a smart playlist. Unlike the rest of the database they are big endian.
+#[binrw]
+#[brw(big, magic = b"SLst")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct SmartPlaylistRules {
+    unk_0x04: u32,
+
+    #[bw(calc = rules.len() as u32)]
+    count: u32,
+
+    #[brw(pad_after = 120)]
+    match_any: u32, // 0 when every rule has to match, 1 when any does
+
+    #[br(count = count)]
+    rules: Vec<SmartPlaylistRule>,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct SmartPlaylistRule {
+    field: u32, // 2 title, 3 album, 4 artist, 5 bitrate, 8 genre, 0x16 play count...
+
+    #[brw(pad_after = 44)]
+    action: u32, // what to compare by, the 0x01000000 bit is set for string operands
+
+    #[br(args { action })]
+    operand: RuleOperand,
+}
+
+#[binrw]
+#[brw(big)]
+#[br(import { action: u32 })]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum RuleOperand {
+    #[br(pre_assert(action & 0x0100_0000 != 0))]
+    Text {
+        #[bw(calc = text.encode_utf16().count() as u32 * 2)]
+        len: u32,
+
+        #[br(count = len / 2, try_map = |units: Vec<u16>| String::from_utf16(&units))]
+        #[bw(map = |text: &String| text.encode_utf16().collect::<Vec<u16>>())]
+        text: String,
+    },
+
+    /// Numbers, dates and ranges. Dates relative to now are a count of `units` seconds.
+    Value {
+        #[br(temp, assert(len == 0x44))]
+        #[bw(calc = 0x44)]
+        len: u32,
+
+        from_value: u64,
+        from_date: i64,
+        from_units: u64,
+        to_value: u64,
+        to_date: i64,
+        to_units: u64,
+        unk_0x34: [u32; 5],
+    },
 }
 
 #[binrw]
 #[brw(little)]
 #[br(import { bytes_left: u32 })]
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct Utf8PodcastUrl {
     #[br(count = bytes_left)]
+    #[cfg_attr(feature = "serde", serde(with = "json::utf8"))]
     url: Vec<u8>,
 }
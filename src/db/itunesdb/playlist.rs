@@ -0,0 +1,161 @@
+use super::{Data, DataContainer, Master, Playlist, PlaylistEntry, Record, Utf16String};
+use crate::util;
+
+// Lists holding the user's playlists, the podcast list repeats them with podcasts grouped
+const PLAYLIST_LISTS: [u32; 2] = [0x02, 0x03];
+
+// sort_order of playlists kept in the order entries were added
+const SORT_MANUAL: u32 = 1;
+
+impl Playlist {
+    /// An empty, regular playlist.
+    pub(crate) fn new(name: &str) -> Self {
+        let now = util::hfs_now();
+
+        Playlist {
+            len: 0, // filled in by io::write_to_buffer
+            is_master_flag: 0,
+            flag_0x15: 0,
+            flag_0x16: 0,
+            flag_0x17: 0,
+            hfs_timestamp_0x18: now,
+            persistent_id: rand::random(),
+            unk_0x24: 0,
+            string_obj_count: 1,
+            is_podcast_playlist_flag: 0,
+            sort_order: SORT_MANUAL,
+            padding_0x30: [0; 40],
+            hfs_timestamp_0x58: now,
+            children: vec![Record::mhod(DataContainer::new(Data::Title(
+                Utf16String::new(name),
+            )))],
+            entries: Vec::new(),
+        }
+    }
+
+    /// The playlist listing every track, named after the device.
+    pub(crate) fn new_master(name: &str) -> Self {
+        Playlist {
+            is_master_flag: 1,
+            ..Playlist::new(name)
+        }
+    }
+
+    pub(crate) fn name(&self) -> Option<String> {
+        self.children.iter().find_map(|child| match child {
+            Record::mhod(container) if container.data_type == 1 => container.data.text(),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn persistent_id(&self) -> u64 {
+        self.persistent_id
+    }
+
+    pub(crate) fn is_master(&self) -> bool {
+        self.is_master_flag != 0
+    }
+
+    /// Unique ids of the tracks in the playlist, in order. Podcast group entries
+    /// aren't tracks and are skipped.
+    pub(crate) fn track_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entries.iter().filter_map(|entry| match entry {
+            Record::mhip(entry) if !entry.is_group() => Some(entry.track_id),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn push_track(&mut self, track_id: u32) {
+        self.entries
+            .push(Record::mhip(PlaylistEntry::new(track_id)));
+    }
+
+    pub(crate) fn remove_track(&mut self, track_id: u32) {
+        self.entries
+            .retain(|entry| !matches!(entry, Record::mhip(entry) if entry.track_id == track_id));
+    }
+
+    pub(crate) fn clear_tracks(&mut self) {
+        self.entries
+            .retain(|entry| !matches!(entry, Record::mhip(_)));
+    }
+}
+
+impl PlaylistEntry {
+    /// An entry pointing at the track with the given unique id.
+    pub(crate) fn new(track_id: u32) -> Self {
+        PlaylistEntry {
+            len: 0, // filled in by io::write_to_buffer
+            podcast_group_flag: 0,
+            unk_0x18: 0,
+            group_id: 0,
+            track_id,
+            hfs_timestamp_0x28: util::hfs_now(),
+            padding_0x32: [0; 12],
+            podcast_group_id: 0,
+            unk_0x48: 0,
+            padding_0x52: [0; 8],
+            unk_0x60: 0,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl Master {
+    /// Every playlist, in the playlist and podcast lists.
+    pub(crate) fn playlists(&self) -> impl Iterator<Item = &Playlist> {
+        PLAYLIST_LISTS
+            .into_iter()
+            .filter_map(|list_type| self.list(list_type))
+            .flat_map(|list| &list.children)
+            .filter_map(|child| match child {
+                Record::mhyp(playlist) => Some(playlist),
+                _ => None,
+            })
+    }
+
+    /// The copies of the playlist with the given persistent id, one per list it's in.
+    pub(crate) fn playlist_mut(&mut self, persistent_id: u64) -> Vec<&mut Playlist> {
+        self.children
+            .iter_mut()
+            .filter_map(|child| match child {
+                Record::mhsd(container) if PLAYLIST_LISTS.contains(&container.list.as_u32()) => {
+                    container.list.records_mut()
+                }
+                _ => None,
+            })
+            .flat_map(|list| list.children.iter_mut())
+            .filter_map(|child| match child {
+                Record::mhyp(playlist) if playlist.persistent_id == persistent_id => Some(playlist),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds the playlist to the playlist and podcast lists.
+    pub(crate) fn add_playlist(&mut self, playlist: Playlist) {
+        for list_type in PLAYLIST_LISTS {
+            if let Some(list) = self.list_mut(list_type) {
+                list.children.push(Record::mhyp(playlist.clone()));
+            }
+        }
+    }
+
+    /// Removes the playlist from every list, returns whether it was found.
+    pub(crate) fn remove_playlist(&mut self, persistent_id: u64) -> bool {
+        let mut found = false;
+        for list_type in PLAYLIST_LISTS {
+            if let Some(list) = self.list_mut(list_type) {
+                list.children.retain(|child| {
+                    let matches = matches!(
+                        child,
+                        Record::mhyp(playlist) if playlist.persistent_id == persistent_id
+                    );
+                    found |= matches;
+                    !matches
+                });
+            }
+        }
+        found
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+
+use super::{
+    Data, DataContainer, Master, Playlist, PlaylistEntry, Record, Track, Utf16String,
+    Utf8PodcastUrl,
+};
+use crate::util;
+
+// media_type of podcast episodes
+const MEDIA_TYPE_PODCAST: u32 = 0x04;
+
+// podcast_unplayed of episodes that haven't been played, shown with a bullet
+const UNPLAYED: u8 = 0x02;
+
+// podcast_group_flag of the mhips that head a show in the podcast list
+const GROUP_FLAG: u16 = 0x100;
+
+/// What sets a podcast episode apart from a regular track, see
+/// [`crate::iPod::add_podcast`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PodcastEpisode {
+    pub show: String,          // the podcast, episodes are grouped by it
+    pub enclosure_url: String, // where the episode was downloaded from
+    pub rss_url: String,       // the feed
+    pub description: Option<String>,
+}
+
+impl Utf8PodcastUrl {
+    pub(crate) fn new(url: &str) -> Self {
+        Utf8PodcastUrl {
+            url: url.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl Track {
+    /// Turns the track into an unplayed episode of `episode.show`. The device groups
+    /// podcasts by album, so the show becomes the album.
+    pub(crate) fn set_podcast(&mut self, episode: &PodcastEpisode) {
+        self.podcast_flag = 1;
+        self.skip_on_shuffle_flag = 1;
+        self.remember_playback_position_flag = 1;
+        self.podcast_unplayed = UNPLAYED;
+        self.media_type = MEDIA_TYPE_PODCAST;
+
+        self.set_string(3, &episode.show);
+        if let Some(description) = &episode.description {
+            self.set_string(14, description);
+        }
+        self.set_data(Data::PodcastEnclosureUrl(Utf8PodcastUrl::new(
+            &episode.enclosure_url,
+        )));
+        self.set_data(Data::PodcastRssUrl(Utf8PodcastUrl::new(&episode.rss_url)));
+    }
+
+    pub(crate) fn is_podcast(&self) -> bool {
+        self.podcast_flag != 0
+    }
+
+    /// The enclosure (15) or RSS (16) URL.
+    pub(crate) fn podcast_url(&self, data_type: u32) -> Option<String> {
+        self.data().find_map(|data| match data {
+            Data::PodcastEnclosureUrl(url) | Data::PodcastRssUrl(url)
+                if data.as_u32() == data_type =>
+            {
+                Some(String::from_utf8_lossy(&url.url).into_owned())
+            }
+            _ => None,
+        })
+    }
+}
+
+impl Playlist {
+    /// The playlist the device shows as the Podcasts menu.
+    pub(crate) fn new_podcasts() -> Self {
+        Playlist {
+            is_podcast_playlist_flag: 1,
+            ..Playlist::new("Podcasts")
+        }
+    }
+
+    pub(crate) fn is_podcasts(&self) -> bool {
+        self.is_podcast_playlist_flag != 0
+    }
+}
+
+impl PlaylistEntry {
+    /// The entry heading the episodes of `show`, which point at it by `group_id`.
+    pub(crate) fn new_group(group_id: u32, show: &str) -> Self {
+        PlaylistEntry {
+            podcast_group_flag: GROUP_FLAG,
+            group_id,
+            children: vec![Record::mhod(DataContainer::new(Data::Title(
+                Utf16String::new(show),
+            )))],
+            ..PlaylistEntry::new(0)
+        }
+    }
+
+    pub(crate) fn is_group(&self) -> bool {
+        self.podcast_group_flag != 0
+    }
+}
+
+/// Regenerates the Podcasts playlist from the podcast episodes in the track list. The
+/// playlist list gets the episodes as they are, the podcast list (mhsd type 3) one
+/// group entry per show with its episodes after it. The playlist is created when the
+/// first episode is added and kept once there are none.
+pub(crate) fn rebuild_podcasts(master: &mut Master) {
+    // Episodes by show, in the order of the track list
+    let mut shows = BTreeMap::<String, Vec<u32>>::new();
+    for track in master.tracks().filter(|track| track.is_podcast()) {
+        shows
+            .entry(track.album().unwrap_or_default())
+            .or_default()
+            .push(track.unique_id);
+    }
+
+    let existing = master
+        .playlists()
+        .find(|playlist| playlist.is_podcasts())
+        .map(|playlist| playlist.persistent_id);
+    let playlist_id = match existing {
+        Some(playlist_id) => playlist_id,
+        None if shows.is_empty() => return,
+        None => {
+            let playlist = Playlist::new_podcasts();
+            let playlist_id = playlist.persistent_id;
+            master.add_playlist(playlist);
+            playlist_id
+        }
+    };
+
+    // Group ids are only compared with podcast_group_id, they start past the track ids
+    // so they can't be mistaken for one
+    let first_group_id = master.next_track_id();
+    let now = util::hfs_now();
+    let episodes: Vec<Record> = shows
+        .values()
+        .flatten()
+        .map(|&track_id| Record::mhip(PlaylistEntry::new(track_id)))
+        .collect();
+    let mut grouped = Vec::new();
+    for (group_id, (show, track_ids)) in (first_group_id..).zip(&shows) {
+        grouped.push(Record::mhip(PlaylistEntry::new_group(group_id, show)));
+        for &track_id in track_ids {
+            grouped.push(Record::mhip(PlaylistEntry {
+                podcast_group_id: group_id,
+                ..PlaylistEntry::new(track_id)
+            }));
+        }
+    }
+
+    for list_type in [0x02, 0x03] {
+        let Some(list) = master.list_mut(list_type) else {
+            continue;
+        };
+
+        for child in list.children.iter_mut() {
+            if let Record::mhyp(playlist) = child {
+                if playlist.persistent_id == playlist_id {
+                    let mut entries = match list_type {
+                        0x03 => grouped.clone(),
+                        _ => episodes.clone(),
+                    };
+                    // Entries keep the time they were first added
+                    for entry in &mut entries {
+                        if let Record::mhip(entry) = entry {
+                            entry.hfs_timestamp_0x28 = added_at(playlist, entry).unwrap_or(now);
+                        }
+                    }
+                    playlist.entries = entries;
+                }
+            }
+        }
+    }
+}
+
+/// When the episode or show of `entry` was added to `playlist`.
+fn added_at(playlist: &Playlist, entry: &PlaylistEntry) -> Option<u32> {
+    playlist.entries.iter().find_map(|existing| match existing {
+        Record::mhip(existing)
+            if existing.track_id == entry.track_id
+                && existing.is_group() == entry.is_group()
+                && existing
+                    .children
+                    .iter()
+                    .map(text)
+                    .eq(entry.children.iter().map(text)) =>
+        {
+            Some(existing.hfs_timestamp_0x28)
+        }
+        _ => None,
+    })
+}
+
+fn text(record: &Record) -> Option<String> {
+    match record {
+        Record::mhod(container) => container.data.text(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::PodcastEpisode;
+    use crate::{
+        db::itunesdb::{check, io, Record},
+        iPod,
+        media::mp3,
+        tests::device,
+    };
+
+    fn episode(show: &str) -> PodcastEpisode {
+        PodcastEpisode {
+            show: show.to_string(),
+            enclosure_url: format!("https://example.com/{show}.mp3"),
+            rss_url: "https://example.com/feed.xml".to_string(),
+            description: Some("An episode".to_string()),
+        }
+    }
+
+    #[test]
+    fn add_podcasts() {
+        let mount = device();
+        let source = tempfile::tempdir().unwrap();
+        let path = source.path().join("episode.mp3");
+        fs::write(&path, mp3::tests::tagged_mp3()).unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let first = ipod.add_podcast(&path, &episode("Show B")).unwrap();
+        let second = ipod.add_podcast(&path, &episode("Show A")).unwrap();
+        let third = ipod.add_podcast(&path, &episode("Show B")).unwrap();
+        ipod.save().unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let master = ipod.master().unwrap();
+
+        let track = master
+            .tracks()
+            .find(|track| track.unique_id == first)
+            .unwrap();
+        assert!(track.is_podcast());
+        assert_eq!(track.skip_on_shuffle_flag, 1);
+        assert_eq!(track.remember_playback_position_flag, 1);
+        assert_eq!(track.podcast_unplayed, 2);
+        assert_eq!(track.media_type, 4);
+        assert_eq!(track.album().as_deref(), Some("Show B"));
+        assert_eq!(
+            track.podcast_url(15).as_deref(),
+            Some("https://example.com/Show B.mp3")
+        );
+        assert_eq!(
+            track.podcast_url(16).as_deref(),
+            Some("https://example.com/feed.xml")
+        );
+
+        let podcasts: Vec<_> = master
+            .playlists()
+            .filter(|playlist| playlist.is_podcasts())
+            .collect();
+        assert_eq!(podcasts.len(), 2);
+        assert_eq!(podcasts[0].name().as_deref(), Some("Podcasts"));
+        assert_eq!(
+            podcasts[0].track_ids().collect::<Vec<_>>(),
+            vec![second, first, third]
+        );
+
+        // One group per show, the episodes point at theirs
+        let entries: Vec<_> = podcasts[1]
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Record::mhip(entry) if entry.is_group() => {
+                    Some((entry.group_id, entry.children.iter().find_map(super::text)))
+                }
+                Record::mhip(entry) => Some((entry.podcast_group_id, None)),
+                _ => None,
+            })
+            .collect();
+        let (a, b) = (entries[0].0, entries[2].0);
+        assert_ne!(a, b);
+        assert_eq!(
+            entries,
+            vec![
+                (a, Some("Show A".to_string())),
+                (a, None),
+                (b, Some("Show B".to_string())),
+                (b, None),
+                (b, None),
+            ]
+        );
+        assert_eq!(
+            podcasts[1].track_ids().collect::<Vec<_>>(),
+            vec![second, first, third]
+        );
+        // The device looks an episode's group up by the word at 0x20 of its mhip
+        let bytes = fs::read(mount.path().join("iPod_Control/iTunes/iTunesDB")).unwrap();
+        let group_refs: Vec<u32> = bytes
+            .windows(4)
+            .enumerate()
+            .filter(|(_, magic)| *magic == b"mhip")
+            .map(|(at, _)| u32::from_le_bytes(bytes[at + 0x20..at + 0x24].try_into().unwrap()))
+            .filter(|&group_ref| group_ref != 0)
+            .collect();
+        assert_eq!(group_refs, vec![a, b, b]);
+
+        // Group entries aren't taken for missing tracks
+        check::validate(&ipod.itunesdb).unwrap();
+
+        // Groups go away with their last episode
+        ipod.remove_track(second).unwrap();
+        ipod.save().unwrap();
+        let ipod = iPod::open(mount.path()).unwrap();
+        let groups = ipod
+            .master()
+            .unwrap()
+            .playlists()
+            .filter(|playlist| playlist.is_podcasts())
+            .flat_map(|playlist| &playlist.entries)
+            .filter(|entry| matches!(entry, Record::mhip(entry) if entry.is_group()))
+            .count();
+        assert_eq!(groups, 1);
+    }
+
+    #[test]
+    fn no_podcasts() {
+        let mut root = io::read_from_buffer(include_bytes!("./sample/iTunesDB")).unwrap();
+        let Record::mhbd(master) = &mut root else {
+            panic!("root is not a master record");
+        };
+        let before = io::write_to_buffer(&Record::mhbd(master.clone()));
+
+        super::rebuild_podcasts(master);
+
+        assert_eq!(io::write_to_buffer(&root), before);
+    }
+}
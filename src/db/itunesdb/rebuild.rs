@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use super::{List, ListContainer, Master, Playlist, Record, RecordList, Track};
+use crate::{db::checksum::HashingScheme, files, media::soundcheck::ReplayGain};
+
+// Database version iTunes 7 writes
+const VERSION: u32 = 0x19;
+
+// "en", as stored in the mhbd
+const LANG_EN: u16 = u16::from_le_bytes(*b"en");
+
+impl Master {
+    /// An empty database: a track list, then podcast and playlist lists holding only
+    /// the master playlist, named `name`. The album list is added when it is written.
+    pub(crate) fn new(hashing_scheme: HashingScheme, name: &str) -> Self {
+        let list = |list: List| Record::mhsd(ListContainer { len: 0, list });
+
+        let mut master = Master {
+            len: 0, // filled in by io::write_to_buffer
+            unk_0x0C: 1,
+            version: VERSION,
+            database_id: rand::random(),
+            unk_0x20: 1,
+            hashing_scheme: hashing_scheme.as_u16(),
+            unk_0x24: 0,
+            unk_0x2C: 0,
+            unk_0x30: 0,
+            padding_0x32: [0; 20],
+            lang: LANG_EN,
+            persistent_id: rand::random(),
+            unk_0x50: 0,
+            unk_0x54: 0,
+            hash_0x58: [0; 20],
+            timezone_offset: 0,
+            unk_0x70: 0,
+            hash_0x72: [0; 46],
+            unk_0xA0: 0,
+            audio_lang: 0,
+            subtitle_lang: 0,
+            children: vec![
+                list(List::Tracks(RecordList {
+                    children: Vec::new(),
+                })),
+                list(List::Podcasts(RecordList {
+                    children: Vec::new(),
+                })),
+                list(List::Playlists(RecordList {
+                    children: Vec::new(),
+                })),
+            ],
+        };
+
+        master.add_playlist(Playlist::new_master(name));
+        master
+    }
+}
+
+/// A fresh database with a track for every audio file in the music folders of the
+/// device mounted at `mount`. Also returns the files that couldn't be read.
+pub(crate) fn rebuild_from_files(
+    mount: &Path,
+    hashing_scheme: HashingScheme,
+    replay_gain: ReplayGain,
+) -> (Record, Vec<PathBuf>) {
+    let mut master = Master::new(hashing_scheme, "iPod");
+    let mut skipped = Vec::new();
+
+    for path in files::music_files(mount) {
+        let location = files::location(mount, &path);
+
+        match (
+            Track::from_file(master.next_track_id(), &path, replay_gain),
+            location,
+        ) {
+            (Ok(mut track), Some(location)) => {
+                track.set_string(2, &location);
+                master.add_track(track);
+            }
+            _ => skipped.push(path),
+        }
+    }
+
+    (Record::mhbd(master), skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::{
+        db::{
+            checksum::HashingScheme,
+            itunesdb::{check, io, Record},
+        },
+        media::{mp3, mp4},
+        ReplayGain,
+    };
+
+    #[test]
+    fn rebuild_from_files() {
+        let mount = tempfile::tempdir().unwrap();
+        let music = mount.path().join("iPod_Control/Music");
+        fs::create_dir_all(music.join("F00")).unwrap();
+        fs::create_dir_all(music.join("F13")).unwrap();
+        fs::write(music.join("F00/ABCD.mp3"), mp3::tests::tagged_mp3()).unwrap();
+        fs::write(music.join("F13/EFGH.m4a"), mp4::tests::m4a(b"mp4a")).unwrap();
+        fs::write(music.join("F13/JUNK.mp3"), b"not audio").unwrap();
+
+        let (mut root, skipped) =
+            super::rebuild_from_files(mount.path(), HashingScheme::Hash58, ReplayGain::Track);
+        assert_eq!(skipped, vec![music.join("F13/JUNK.mp3")]);
+
+        let buf = io::write_database(
+            &mut root,
+            Some("000A270013E10993"),
+            &[Box::new(crate::db::checksum::Hash58Signer)],
+        )
+        .unwrap();
+        let root = io::read_from_buffer(&buf).unwrap();
+
+        // Only the unreadable file is left over
+        assert_eq!(
+            check::check(&root, mount.path()),
+            vec![check::Problem::OrphanedFile {
+                path: music.join("F13/JUNK.mp3")
+            }]
+        );
+
+        let Record::mhbd(master) = &root else {
+            panic!("root is not a master record");
+        };
+        let titles: Vec<_> = master.tracks().map(|track| track.title()).collect();
+        assert_eq!(
+            titles,
+            vec![Some("Ace of Spades".to_string()), Some("Été".to_string())]
+        );
+        assert_eq!(master.list(0x03).unwrap().children.len(), 1);
+        assert_eq!(master.list(0x04).unwrap().children.len(), 2);
+    }
+}
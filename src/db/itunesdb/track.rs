@@ -0,0 +1,494 @@
+use std::path::{Path, PathBuf};
+
+use super::{Data, DataContainer, Record, Track};
+use crate::{
+    files,
+    media::{
+        soundcheck::{self, ReplayGain},
+        tags::{self, AudioFile, Codec, Tags},
+    },
+    util,
+};
+
+// media_type of plain audio tracks
+const MEDIA_TYPE_AUDIO: u32 = 1;
+
+impl Track {
+    /// An empty audio track, visible and added now.
+    pub(crate) fn new(unique_id: u32) -> Self {
+        Track {
+            len: 0, // filled in by io::write_to_buffer
+            unique_id,
+            visible: 1,
+            file_type: [0; 4],
+            vbr_flag: 0,
+            mp3_flag: 0,
+            compilation_flag: 0,
+            rating: 0,
+            hfs_time_last_modified: 0,
+            file_size_bytes_u32: 0,
+            duration_ms: 0,
+            album_index: 0,
+            album_track_count: 0,
+            release_year: 0,
+            bitrate: 0,
+            sample_rate: 0,
+            playback_volume_adj: 0,
+            start_offset_ms: 0,
+            stop_offset_ms: 0,
+            soundcheck: 0,
+            play_count_1: 0,
+            play_count_2: 0,
+            hfs_time_last_played: 0,
+            album_disc_index: 0,
+            album_disc_count: 0,
+            drm_user_id: 0,
+            hfs_time_date_added: util::hfs_now(),
+            bookmark_ms: 0,
+            persistent_id: rand::random(),
+            unchecked_flag: 0,
+            last_rating: 0,
+            bpm: 0,
+            artwork_count: 0,
+            audio_format_tag: 0,
+            artwork_size_bytes: 0,
+            unk_0x84: 0,
+            IEEE_f32_sample_rate: 0,
+            hfs_time_release_date: 0,
+            unk_0x90: 0,
+            unk_0x92: 0,
+            unk_0x94: 0,
+            unk_0x98: 0,
+            skip_count: 0,
+            hfs_time_last_skipped: 0,
+            has_artwork: 2,
+            skip_on_shuffle_flag: 0,
+            remember_playback_position_flag: 0,
+            podcast_flag: 0,
+            unk_0xA8: 0,
+            has_lyrics_flag: 0,
+            is_movie_flag: 0,
+            podcast_unplayed: 1,
+            unk_0xB3: 0,
+            unk_0xB4: 0,
+            samples_before_start_gapless: 0,
+            samples_count_gapless: 0,
+            unk_0xC4: 0,
+            samples_before_end_gapless: 0,
+            mp3_encoded: 0,
+            media_type: MEDIA_TYPE_AUDIO,
+            season_number: 0,
+            episode_number: 0,
+            unk_0xDC: 0,
+            padding_0xE0: [0; 24],
+            gapless_data: 0,
+            unk_0xFC: 0,
+            is_gapless_track_flag: 0,
+            is_gapless_album_flag: 0,
+            padding_0x0104: [0; 28],
+            unk_0x0120: 0,
+            unk_0x0124: 0,
+            file_size_bytes_u64: 0,
+            unk_0x0134: [0; 6],
+            album_id: 0,
+            padding_0x013A: [0; 36],
+            mhii_link: 0,
+            unk_0x0168: 0,
+            padding_0x0170: [0; 112],
+            unk_0x01E0: 0,
+            padding_0x01E4: [0; 16],
+            unk_0x01F4: 0,
+            padding_0x01F8: [0; 20],
+            unk_0x020C: 0,
+            unk_0x22C: 0,
+            children: Vec::new(),
+        }
+    }
+
+    /// A track for the audio file at `path`, filled in from its tags and stream
+    /// properties. The location mhod is left for the caller, which knows where the
+    /// file ends up on the device. `replay_gain` picks the gain Sound Check uses.
+    pub(crate) fn from_file(
+        unique_id: u32,
+        path: impl AsRef<Path>,
+        replay_gain: ReplayGain,
+    ) -> anyhow::Result<Self> {
+        let file = tags::read_audio_file(path)?;
+
+        let mut track = Track::new(unique_id);
+        track.set_audio_file(&file, replay_gain);
+        Ok(track)
+    }
+
+    /// Fills in the track from the tags and stream properties of `file`. Everything
+    /// that comes from the file is replaced, tags the file doesn't have are removed, so
+    /// a track that gets a new file keeps nothing of the old one but its location.
+    pub(crate) fn set_audio_file(&mut self, file: &AudioFile, replay_gain: ReplayGain) {
+        let tags = &file.tags;
+        let properties = &file.properties;
+
+        for (data_type, text) in [
+            (1, &tags.title),
+            (4, &tags.artist),
+            (3, &tags.album),
+            (22, &tags.album_artist),
+            (5, &tags.genre),
+            (12, &tags.composer),
+            (13, &tags.grouping),
+            (8, &tags.comment),
+            (27, &tags.title_sort),
+            (23, &tags.artist_sort),
+            (28, &tags.album_sort),
+            (29, &tags.album_artist_sort),
+            (30, &tags.composer_sort),
+        ] {
+            match text {
+                Some(text) => self.set_string(data_type, text),
+                None => self.remove_data(data_type),
+            }
+        }
+
+        self.album_index = tags.track_number.unwrap_or(0);
+        self.album_track_count = tags.track_count.unwrap_or(0);
+        self.album_disc_index = tags.disc_number.unwrap_or(0);
+        self.album_disc_count = tags.disc_count.unwrap_or(0);
+        self.release_year = tags.year.unwrap_or(0);
+        self.bpm = tags.bpm.unwrap_or(0);
+        self.compilation_flag = tags.compilation as u8;
+        self.soundcheck = soundcheck::soundcheck(tags, replay_gain).unwrap_or(0);
+
+        // file_type is the extension as a little endian word, ie. "MP3 " is stored " 3PM"
+        let (extension, description) = match properties.codec {
+            Codec::Mp3 => (b"MP3 ", "MPEG audio file"),
+            Codec::Aac => (b"M4A ", "AAC audio file"),
+            Codec::Alac => (b"M4A ", "Apple Lossless audio file"),
+        };
+        self.file_type = *extension;
+        self.file_type.reverse();
+        self.set_string(6, description);
+
+        self.mp3_flag = (properties.codec == Codec::Mp3) as u8;
+        self.mp3_encoded = (properties.codec == Codec::Mp3) as u32;
+        self.vbr_flag = properties.vbr as u8;
+        self.audio_format_tag = 0xFFFF;
+
+        self.bitrate = properties.bitrate_kbps;
+        // 16.16 fixed point, rates past 65535 Hz only fit the float
+        self.sample_rate =
+            u16::try_from(properties.sample_rate).map_or(0, |rate| (rate as u32) << 16);
+        self.IEEE_f32_sample_rate = (properties.sample_rate as f32).to_bits();
+        self.duration_ms = properties.duration_ms;
+        self.file_size_bytes_u32 = file.file_size.min(u32::MAX as u64) as u32;
+        self.file_size_bytes_u64 = file.file_size;
+
+        let gapless = properties.gapless.unwrap_or_default();
+        self.samples_before_start_gapless = gapless.pregap;
+        self.samples_before_end_gapless = gapless.postgap;
+        self.samples_count_gapless = gapless.sample_count;
+        self.gapless_data = gapless.gapless_data;
+        self.is_gapless_track_flag = properties.gapless.is_some() as u16;
+        self.is_gapless_album_flag = tags.gapless_album as u16;
+    }
+
+    /// The metadata of the track as tags, the reverse of `set_audio_file`.
+    pub(crate) fn tags(&self) -> Tags {
+        let nonzero = |value: u32| Some(value).filter(|value| *value != 0);
+
+        Tags {
+            title: self.title(),
+            artist: self.artist(),
+            album: self.album(),
+            album_artist: self.album_artist(),
+            genre: self.genre(),
+            composer: self.composer(),
+            grouping: self.grouping(),
+            comment: self.comment(),
+            title_sort: self.title_sort(),
+            artist_sort: self.artist_sort(),
+            album_sort: self.album_sort(),
+            album_artist_sort: self.album_artist_sort(),
+            composer_sort: self.composer_sort(),
+            track_number: nonzero(self.album_index),
+            track_count: nonzero(self.album_track_count),
+            disc_number: nonzero(self.album_disc_index),
+            disc_count: nonzero(self.album_disc_count),
+            year: nonzero(self.release_year),
+            bpm: Some(self.bpm).filter(|bpm| *bpm != 0),
+            compilation: self.compilation_flag != 0,
+            gapless_album: self.is_gapless_album_flag != 0,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    pub(crate) fn unique_id(&self) -> u32 {
+        self.unique_id
+    }
+
+    /// Rating out of 100, 20 per star.
+    pub(crate) fn set_rating(&mut self, rating: u8) {
+        self.rating = rating;
+    }
+
+    pub(crate) fn clear_artwork(&mut self) {
+        self.mhii_link = 0;
+        self.artwork_count = 0;
+        self.artwork_size_bytes = 0;
+        self.has_artwork = 2;
+    }
+
+    pub(crate) fn data(&self) -> impl Iterator<Item = &Data> {
+        self.children.iter().filter_map(|child| match child {
+            Record::mhod(container) => Some(&container.data),
+            _ => None,
+        })
+    }
+
+    /// Replaces the first mhod of the same type as `data`, or adds one.
+    pub(crate) fn set_data(&mut self, data: Data) {
+        let existing = self.children.iter_mut().find_map(|child| match child {
+            Record::mhod(container) if container.data_type == data.as_u32() => Some(container),
+            _ => None,
+        });
+
+        match existing {
+            Some(container) => container.data = data,
+            None => self.children.push(Record::mhod(DataContainer::new(data))),
+        }
+    }
+
+    /// Removes the mhods of the given type.
+    pub(crate) fn remove_data(&mut self, data_type: u32) {
+        self.children.retain(
+            |child| !matches!(child, Record::mhod(container) if container.data_type == data_type),
+        );
+    }
+
+    /// Text of the first string mhod with the given type.
+    pub(crate) fn string(&self, data_type: u32) -> Option<String> {
+        self.data()
+            .find(|data| data.as_u32() == data_type)
+            .and_then(Data::text)
+    }
+
+    /// Sets the string mhod of the given type, ignoring types that aren't strings.
+    pub(crate) fn set_string(&mut self, data_type: u32, text: &str) {
+        if let Some(data) = Data::from_text(data_type, text) {
+            self.set_data(data);
+        }
+    }
+
+    pub(crate) fn title(&self) -> Option<String> {
+        self.string(1)
+    }
+
+    pub(crate) fn album(&self) -> Option<String> {
+        self.string(3)
+    }
+
+    /// Path of the file relative to the mount point, with `:` separators
+    /// (ie. `:iPod_Control:Music:F00:ABCD.mp3`).
+    pub(crate) fn location(&self) -> Option<String> {
+        self.string(2)
+    }
+
+    /// Where the file is on the device mounted at `mount`.
+    pub(crate) fn location_path(&self, mount: &Path) -> Option<PathBuf> {
+        Some(files::location_path(mount, &self.location()?))
+    }
+
+    pub(crate) fn artist(&self) -> Option<String> {
+        self.string(4)
+    }
+
+    pub(crate) fn album_artist(&self) -> Option<String> {
+        self.string(22)
+    }
+
+    pub(crate) fn genre(&self) -> Option<String> {
+        self.string(5)
+    }
+
+    pub(crate) fn comment(&self) -> Option<String> {
+        self.string(8)
+    }
+
+    pub(crate) fn category(&self) -> Option<String> {
+        self.string(9)
+    }
+
+    pub(crate) fn composer(&self) -> Option<String> {
+        self.string(12)
+    }
+
+    pub(crate) fn grouping(&self) -> Option<String> {
+        self.string(13)
+    }
+
+    pub(crate) fn description(&self) -> Option<String> {
+        self.string(14)
+    }
+
+    pub(crate) fn subtitle(&self) -> Option<String> {
+        self.string(18)
+    }
+
+    pub(crate) fn show(&self) -> Option<String> {
+        self.string(19)
+    }
+
+    pub(crate) fn episode_number(&self) -> Option<String> {
+        self.string(20)
+    }
+
+    pub(crate) fn tv_network(&self) -> Option<String> {
+        self.string(21)
+    }
+
+    pub(crate) fn artist_sort(&self) -> Option<String> {
+        self.string(23)
+    }
+
+    pub(crate) fn keywords(&self) -> Option<String> {
+        self.string(24)
+    }
+
+    pub(crate) fn tv_show_locale(&self) -> Option<String> {
+        self.string(25)
+    }
+
+    pub(crate) fn title_sort(&self) -> Option<String> {
+        self.string(27)
+    }
+
+    pub(crate) fn album_sort(&self) -> Option<String> {
+        self.string(28)
+    }
+
+    pub(crate) fn album_artist_sort(&self) -> Option<String> {
+        self.string(29)
+    }
+
+    pub(crate) fn composer_sort(&self) -> Option<String> {
+        self.string(30)
+    }
+
+    pub(crate) fn tv_show_sort(&self) -> Option<String> {
+        self.string(31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{ReplayGain, Track};
+    use crate::db::itunesdb::{io, Record};
+    use crate::media::{mp3, mp4, tags};
+
+    fn from_bytes(bytes: &[u8]) -> Track {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+
+        let track = Track::from_file(7, file.path(), ReplayGain::Album).unwrap();
+
+        // The track has to survive being written and read back
+        let record = Record::mhit(track);
+        let written = io::write_to_buffer(&record);
+        match io::read_from_buffer(&written).unwrap() {
+            Record::mhit(track) => track,
+            _ => panic!("track was not read back"),
+        }
+    }
+
+    #[test]
+    fn from_mp3() {
+        let bytes = mp3::tests::tagged_mp3();
+        let track = from_bytes(&bytes);
+
+        assert_eq!(track.unique_id, 7);
+        assert_eq!(track.title().as_deref(), Some("Ace of Spades"));
+        assert_eq!(track.artist().as_deref(), Some("Motörhead"));
+        assert_eq!(track.genre().as_deref(), Some("Rock"));
+        assert_eq!(track.comment().as_deref(), Some("Loud"));
+        assert_eq!((track.album_index, track.album_track_count), (1, 12));
+        assert_eq!(track.release_year, 1980);
+        assert_eq!(track.bpm, 141);
+        assert_eq!(track.compilation_flag, 1);
+        // No album gain, so the track gain is used over iTunNORM
+        assert_eq!(track.soundcheck, 3981);
+        assert_eq!(&track.file_type, b" 3PM");
+        assert_eq!(track.mp3_flag, 1);
+        assert_eq!(track.bitrate, 128);
+        assert_eq!(track.sample_rate, 44100 << 16);
+        assert_eq!(track.duration_ms, 2606);
+        assert_eq!(track.file_size_bytes_u32, bytes.len() as u32);
+        assert_eq!(track.file_size_bytes_u64, bytes.len() as u64);
+    }
+
+    #[test]
+    fn replace_audio_file() {
+        let read = |bytes: &[u8]| {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(bytes).unwrap();
+            tags::read_audio_file(file.path()).unwrap()
+        };
+        let tagged = read(&mp3::tests::tagged_mp3());
+        let gapless = read(&mp3::tests::lame_mp3(20));
+
+        let mut track = Track::new(7);
+        track.set_audio_file(&tagged, ReplayGain::Album);
+        track.set_audio_file(&gapless, ReplayGain::Album);
+        assert_eq!(track.title(), None);
+        assert_eq!(track.genre(), None);
+        assert_eq!(track.is_gapless_track_flag, 1);
+
+        // Nothing of the gapless file is left for the device to cut the audio by
+        track.set_audio_file(&tagged, ReplayGain::Album);
+        assert_eq!(track.title().as_deref(), Some("Ace of Spades"));
+        assert_eq!(track.is_gapless_track_flag, 0);
+        assert_eq!(
+            (
+                track.samples_before_start_gapless,
+                track.samples_before_end_gapless,
+                track.samples_count_gapless,
+                track.gapless_data
+            ),
+            (0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn high_sample_rate() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&mp3::tests::tagged_mp3()).unwrap();
+        let mut audio = tags::read_audio_file(file.path()).unwrap();
+        audio.properties.sample_rate = 96000;
+
+        let mut track = Track::new(7);
+        track.set_audio_file(&audio, ReplayGain::Album);
+
+        assert_eq!(track.sample_rate, 0);
+        assert_eq!(f32::from_bits(track.IEEE_f32_sample_rate), 96000.0);
+    }
+
+    #[test]
+    fn from_m4a() {
+        let track = from_bytes(&mp4::tests::m4a(b"alac"));
+
+        assert_eq!(track.title().as_deref(), Some("Été"));
+        assert_eq!(track.album_artist().as_deref(), Some("Album Artist"));
+        assert_eq!(track.artist_sort().as_deref(), Some("Artist, The"));
+        assert_eq!((track.album_disc_index, track.album_disc_count), (2, 2));
+        assert_eq!(&track.file_type, b" A4M");
+        assert_eq!(track.mp3_flag, 0);
+        assert_eq!(track.duration_ms, 3000);
+        assert_eq!(
+            track.string(6).as_deref(),
+            Some("Apple Lossless audio file")
+        );
+    }
+}
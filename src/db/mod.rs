@@ -1,3 +1,5 @@
 pub(crate) mod artworkdb;
+pub(crate) mod checksum;
 pub(crate) mod hash58;
+pub(crate) mod hash72;
 pub(crate) mod itunesdb;
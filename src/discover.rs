@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{iPod, mounts, sysinfo, ModelInfo, SYSINFO_EXTENDED_PATH, SYSINFO_PATH};
+
+// Filesystems iPods ship formatted with
+const FS_TYPES: [&str; 2] = ["vfat", "hfsplus"];
+
+// What iPod::open reads the database and SysInfo from. The iPod touch keeps its
+// library in iTunes_Control as SQLite instead, so it isn't reported.
+const CONTROL_DIR: &str = "iPod_Control";
+
+/// A mounted iPod found by [`discover`], with what its SysInfo files say about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub path: PathBuf,
+    pub source: String, // eg. /dev/sdb2
+    pub fs_type: String,
+    pub model_number: Option<String>, // eg. xB147
+    pub product_type: Option<String>,
+    pub family_id: Option<String>,
+    pub serial_num: Option<String>,
+}
+
+impl DiscoveredDevice {
+    pub fn model(&self) -> Option<ModelInfo> {
+        ModelInfo::identify(
+            self.model_number.as_deref(),
+            self.serial_num.as_deref(),
+            self.family_id.as_deref(),
+            self.product_type.as_deref(),
+        )
+    }
+
+    pub fn open(&self) -> anyhow::Result<iPod> {
+        iPod::open(&self.path)
+    }
+}
+
+/// Finds the iPods currently mounted, from /proc/self/mountinfo.
+pub fn discover() -> anyhow::Result<Vec<DiscoveredDevice>> {
+    discover_in(Path::new(mounts::MOUNTINFO_PATH))
+}
+
+/// Finds the mounted iPods listed in the mountinfo file at `mountinfo_path`.
+pub fn discover_in(mountinfo_path: &Path) -> anyhow::Result<Vec<DiscoveredDevice>> {
+    let file = File::open(mountinfo_path)
+        .with_context(|| format!("failed to open {}", mountinfo_path.display()))?;
+
+    Ok(mounts::parse_mountinfo(BufReader::new(file))?
+        .into_iter()
+        .filter(|entry| FS_TYPES.contains(&entry.fs_type.as_str()))
+        .filter_map(|entry| {
+            entry
+                .mount_point
+                .join(CONTROL_DIR)
+                .is_dir()
+                .then(|| device(entry))
+        })
+        .collect())
+}
+
+fn device(entry: mounts::MountEntry) -> DiscoveredDevice {
+    // Unreadable or missing SysInfo files just leave the model info empty
+    let sysinfo_extended = File::open(entry.mount_point.join(SYSINFO_EXTENDED_PATH))
+        .ok()
+        .and_then(|file| sysinfo::parse_extended(BufReader::new(file)).ok())
+        .unwrap_or_default();
+    let sysinfo = File::open(entry.mount_point.join(SYSINFO_PATH))
+        .ok()
+        .and_then(|file| sysinfo::parse_sysinfo(BufReader::new(file)).ok())
+        .unwrap_or_default();
+
+    let value = |extended_key: &str, key: &str| {
+        sysinfo_extended
+            .get(extended_key)
+            .or_else(|| sysinfo.get(key))
+            .cloned()
+    };
+
+    DiscoveredDevice {
+        model_number: value("ModelNumStr", "ModelNumStr"),
+        product_type: value("ProductType", "ProductType"),
+        family_id: value("FamilyID", "FamilyID"),
+        serial_num: value("SerialNumber", "pszSerialNumber"),
+        path: entry.mount_point,
+        source: entry.source,
+        fs_type: entry.fs_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    #[test]
+    fn discover_in() {
+        let root = tempfile::tempdir().unwrap();
+
+        let classic = root.path().join("JOHN S IPOD");
+        fs::create_dir_all(classic.join("iPod_Control/Device")).unwrap();
+        fs::write(
+            classic.join("iPod_Control/Device/SysInfo"),
+            "ModelNumStr: xB147\npszSerialNumber: 8K9466E39ZU\n",
+        )
+        .unwrap();
+
+        let nano = root.path().join("NANO");
+        fs::create_dir_all(nano.join("iPod_Control")).unwrap();
+
+        let touch = root.path().join("TOUCH");
+        fs::create_dir_all(touch.join("iTunes_Control")).unwrap();
+
+        let usb_stick = root.path().join("STICK");
+        fs::create_dir_all(&usb_stick).unwrap();
+
+        let mountinfo = root.path().join("mountinfo");
+        fs::write(
+            &mountinfo,
+            format!(
+                "22 1 259:2 / / rw - ext4 /dev/nvme0n1p2 rw\n\
+                 120 22 8:18 / {} rw - vfat /dev/sdb2 rw\n\
+                 121 22 8:34 / {} rw - hfsplus /dev/sdc2 rw\n\
+                 122 22 8:50 / {} rw - vfat /dev/sdd1 rw\n\
+                 123 22 8:66 / {} rw - vfat /dev/sde1 rw\n",
+                classic.display().to_string().replace(' ', "\\040"),
+                nano.display(),
+                usb_stick.display(),
+                touch.display()
+            ),
+        )
+        .unwrap();
+
+        let devices = super::discover_in(&mountinfo).unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].path, classic);
+        assert_eq!(devices[0].source, "/dev/sdb2");
+        assert_eq!(devices[0].model_number.as_deref(), Some("xB147"));
+        assert_eq!(devices[0].serial_num.as_deref(), Some("8K9466E39ZU"));
+        assert_eq!(devices[0].model().unwrap().model_number, Some("B147"));
+        assert_eq!(devices[1].path, nano);
+        assert_eq!(devices[1].model_number, None);
+    }
+}
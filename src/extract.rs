@@ -0,0 +1,358 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    db::itunesdb::{Master, Playlist, Track},
+    media::{mp3, tags},
+};
+
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Where each file goes under the destination, without its extension. `{artist}`
+    /// (the album artist, or the artist), `{album}`, `{title}`, `{track}`, `{disc}`,
+    /// `{genre}` and `{year}` are filled in from the database.
+    pub template: String,
+    pub retag: bool, // write the database metadata into MP3s that have no tags
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            template: "{artist}/{album}/{track} {title}".to_string(),
+            retag: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractReport {
+    pub copied: Vec<PathBuf>,
+    pub missing: Vec<u32>,          // tracks whose file isn't on the device
+    pub playlists: Vec<PathBuf>,    // M3U8 files written
+    pub not_retagged: Vec<PathBuf>, // copied files without tags that couldn't be given any
+}
+
+/// Makes `text` usable as a file or folder name on any filesystem.
+fn sanitize(text: &str) -> String {
+    let name: String = text
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(120)
+        .collect();
+
+    match name.trim().trim_end_matches('.') {
+        "" => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The path `template` gives the track, relative to the destination.
+fn render(template: &str, track: &Track) -> PathBuf {
+    let tags = track.tags();
+    let number = |number: Option<u32>| format!("{:02}", number.unwrap_or(0));
+
+    let artist = tags
+        .album_artist
+        .clone()
+        .filter(|artist| !artist.is_empty())
+        .or(tags.artist.clone())
+        .unwrap_or_else(|| "Unknown Artist".to_string());
+    let fields = [
+        ("{artist}", artist),
+        (
+            "{album}",
+            tags.album
+                .clone()
+                .unwrap_or_else(|| "Unknown Album".to_string()),
+        ),
+        (
+            "{title}",
+            tags.title.clone().unwrap_or_else(|| "Untitled".to_string()),
+        ),
+        ("{track}", number(tags.track_number)),
+        ("{disc}", number(tags.disc_number)),
+        ("{genre}", tags.genre.clone().unwrap_or_default()),
+        (
+            "{year}",
+            tags.year.map(|year| year.to_string()).unwrap_or_default(),
+        ),
+    ];
+
+    template
+        .split('/')
+        .map(|part| {
+            let filled = fields
+                .iter()
+                .fold(part.to_string(), |part, (field, value)| {
+                    part.replace(field, &sanitize(value))
+                });
+            sanitize(&filled)
+        })
+        .collect()
+}
+
+/// `path`, or `path (2)` and so on when it is already taken.
+fn unique_path(path: PathBuf, taken: &mut HashSet<PathBuf>) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut candidate = path.clone();
+    let mut n = 2;
+    while candidate.exists() || taken.contains(&candidate) {
+        candidate = path.with_file_name(format!("{stem} ({n}){extension}"));
+        n += 1;
+    }
+
+    taken.insert(candidate.clone());
+    candidate
+}
+
+/// Writes the track's metadata into the copied file when it is an MP3 without tags.
+/// MP4 files are left as they are, rewriting their atoms isn't supported. Returns
+/// false for files that have no tags and didn't get any.
+fn retag(path: &Path, track: &Track) -> anyhow::Result<bool> {
+    let Ok(file) = tags::read_audio_file(path) else {
+        return Ok(false);
+    };
+    if file.tags.title.is_some() || file.tags.artist.is_some() {
+        return Ok(true);
+    }
+    if file.properties.codec != tags::Codec::Mp3 {
+        return Ok(false);
+    }
+
+    let bytes = fs::read(path)?;
+    let mut retagged = mp3::write_id3v2(&track.tags());
+    retagged.extend_from_slice(&bytes[mp3::id3v2_len(&bytes).min(bytes.len())..]);
+    fs::write(path, retagged)?;
+
+    Ok(true)
+}
+
+/// An M3U8 playlist of the extracted files, with paths relative to `dest`.
+fn write_m3u8(
+    dest: &Path,
+    playlist: &Playlist,
+    master: &Master,
+    extracted: &HashMap<u32, PathBuf>,
+    taken: &mut HashSet<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    let name = playlist.name().unwrap_or_else(|| "Playlist".to_string());
+    let path = unique_path(dest.join(format!("{}.m3u8", sanitize(&name))), taken);
+
+    let mut text = String::from("#EXTM3U\n");
+    for track_id in playlist.track_ids() {
+        let (Some(file), Some(track)) = (
+            extracted.get(&track_id),
+            master.tracks().find(|track| track.unique_id() == track_id),
+        ) else {
+            continue;
+        };
+        let relative = file.strip_prefix(dest).unwrap_or(file);
+        let relative: Vec<_> = relative
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect();
+
+        text.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            track.duration_ms() / 1000,
+            track.artist().unwrap_or_default(),
+            track.title().unwrap_or_default(),
+            relative.join("/")
+        ));
+    }
+
+    fs::write(&path, text).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Copies the tracks off the device mounted at `mount` into `dest`, named by
+/// `options.template`, and writes the playlists next to them as M3U8. With a
+/// `playlist` name only its tracks and that playlist are extracted.
+pub(crate) fn extract(
+    master: &Master,
+    mount: &Path,
+    dest: &Path,
+    options: &ExtractOptions,
+    playlist: Option<&str>,
+) -> anyhow::Result<ExtractReport> {
+    // The podcast list repeats the playlists, each is exported once
+    let mut seen = HashSet::new();
+    let playlists: Vec<&Playlist> = master
+        .playlists()
+        .filter(|found| !found.is_master())
+        .filter(|found| playlist.is_none() || found.name().as_deref() == playlist)
+        .filter(|found| seen.insert(found.persistent_id()))
+        .collect();
+
+    let tracks: Vec<&Track> = match playlist {
+        Some(name) => {
+            let Some(playlist) = playlists.first() else {
+                bail!("no playlist called \"{name}\"");
+            };
+            let ids: HashSet<u32> = playlist.track_ids().collect();
+            master
+                .tracks()
+                .filter(|track| ids.contains(&track.unique_id()))
+                .collect()
+        }
+        None => master.tracks().collect(),
+    };
+
+    fs::create_dir_all(dest).with_context(|| format!("failed to create {}", dest.display()))?;
+
+    let mut report = ExtractReport::default();
+    let mut taken = HashSet::new();
+    let mut extracted = HashMap::new();
+
+    for track in tracks {
+        let Some(source) = track.location_path(mount).filter(|path| path.is_file()) else {
+            report.missing.push(track.unique_id());
+            continue;
+        };
+
+        let mut target = dest.join(render(&options.template, track));
+        if let Some(extension) = source.extension() {
+            let name = format!(
+                "{}.{}",
+                target.file_name().unwrap_or_default().to_string_lossy(),
+                extension.to_string_lossy().to_lowercase()
+            );
+            target.set_file_name(name);
+        }
+        let target = unique_path(target, &mut taken);
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&source, &target).with_context(|| {
+            format!(
+                "failed to copy {} to {}",
+                source.display(),
+                target.display()
+            )
+        })?;
+        if options.retag && !retag(&target, track)? {
+            report.not_retagged.push(target.clone());
+        }
+
+        extracted.insert(track.unique_id(), target.clone());
+        report.copied.push(target);
+    }
+
+    for playlist in playlists
+        .into_iter()
+        .take(if playlist.is_some() { 1 } else { usize::MAX })
+    {
+        let path = write_m3u8(dest, playlist, master, &extracted, &mut taken)?;
+        report.playlists.push(path);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::ExtractOptions;
+    use crate::{
+        iPod,
+        media::{mp3, mp4},
+        tests::device,
+    };
+
+    #[test]
+    fn extract() {
+        let mount = device();
+        let music = mount.path().join("iPod_Control/Music");
+        for folder in ["F00", "F01"] {
+            fs::create_dir_all(music.join(folder)).unwrap();
+        }
+        fs::write(music.join("F00/AAAA.mp3"), mp3::tests::tagged_mp3()).unwrap();
+        fs::write(music.join("F01/BBBB.mp3"), mp3::tests::mp3(&[], 10, &[])).unwrap();
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let report = ipod
+            .extract(dest.path(), &ExtractOptions::default())
+            .unwrap();
+
+        let album = dest.path().join("Artist A/Album X");
+        assert_eq!(
+            report.copied,
+            vec![album.join("00 Song One.mp3"), album.join("00 Song Two.mp3")]
+        );
+        assert_eq!(report.missing, vec![103]);
+        assert!(report.not_retagged.is_empty());
+
+        // The tagged file is copied as it is, the untagged one gets the database's tags
+        assert_eq!(
+            fs::read(&report.copied[0]).unwrap(),
+            mp3::tests::tagged_mp3()
+        );
+        let tags = crate::media::tags::read_audio_file(&report.copied[1])
+            .unwrap()
+            .tags;
+        assert_eq!(tags.title.as_deref(), Some("Song Two"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist A"));
+
+        assert_eq!(report.playlists, vec![dest.path().join("Favourites.m3u8")]);
+        assert_eq!(
+            fs::read_to_string(&report.playlists[0]).unwrap(),
+            "#EXTM3U\n#EXTINF:180,Artist A - Song One\nArtist A/Album X/00 Song One.mp3\n"
+        );
+
+        // Extracting again doesn't overwrite anything
+        let report = ipod
+            .extract_playlist("Favourites", dest.path(), &ExtractOptions::default())
+            .unwrap();
+        assert_eq!(report.copied, vec![album.join("00 Song One (2).mp3")]);
+        assert_eq!(
+            report.playlists,
+            vec![dest.path().join("Favourites (2).m3u8")]
+        );
+        assert!(ipod
+            .extract_playlist("Nope", dest.path(), &ExtractOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn untagged_mp4_is_reported() {
+        let mount = device();
+        let music = mount.path().join("iPod_Control/Music/F02");
+        fs::create_dir_all(&music).unwrap();
+        // An M4A whose metadata atoms are gone
+        let mut m4a = mp4::tests::m4a(b"mp4a");
+        let ilst = m4a.windows(4).position(|kind| kind == b"ilst").unwrap();
+        m4a[ilst..ilst + 4].copy_from_slice(b"free");
+        fs::write(music.join("CCCC.mp3"), &m4a).unwrap();
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let report = ipod
+            .extract(dest.path(), &ExtractOptions::default())
+            .unwrap();
+
+        assert_eq!(report.copied.len(), 1);
+        assert_eq!(report.not_retagged, report.copied);
+        assert_eq!(fs::read(&report.copied[0]).unwrap(), m4a);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use rand::Rng;
+
+pub(crate) const MUSIC_DIR: &str = "iPod_Control/Music";
+
+// Folders created on devices that don't have any yet, iTunes uses 20 to 50
+const FOLDER_COUNT: usize = 20;
+
+/// The `Fxx` folders under the music directory, created if there are none.
+fn music_folders(mount: &Path) -> anyhow::Result<Vec<String>> {
+    let music_dir = mount.join(MUSIC_DIR);
+
+    let mut folders: Vec<String> = match fs::read_dir(&music_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with('F'))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if folders.is_empty() {
+        for i in 0..FOLDER_COUNT {
+            let name = format!("F{i:02}");
+            fs::create_dir_all(music_dir.join(&name))
+                .with_context(|| format!("failed to create {}", music_dir.join(&name).display()))?;
+            folders.push(name);
+        }
+    }
+
+    folders.sort();
+    Ok(folders)
+}
+
+/// Files in the `Fxx` music folders of the device.
+pub(crate) fn music_files(mount: &Path) -> Vec<PathBuf> {
+    let Ok(folders) = fs::read_dir(mount.join(MUSIC_DIR)) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<PathBuf> = folders
+        .filter_map(Result::ok)
+        .filter(|folder| folder.path().is_dir())
+        .filter_map(|folder| fs::read_dir(folder.path()).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|file| file.path())
+        .filter(|path| path.is_file())
+        .collect();
+    found.sort();
+    found
+}
+
+/// Copies `source` into a random music folder under a random four letter name, the way
+/// iTunes names files. Returns the location to store in the track, ie.
+/// `:iPod_Control:Music:F07:QZXW.mp3`.
+pub(crate) fn copy_to_device(mount: &Path, source: &Path) -> anyhow::Result<String> {
+    let folders = music_folders(mount)?;
+    let extension = source
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| format!(".{}", extension.to_lowercase()))
+        .unwrap_or_default();
+
+    let mut rng = rand::rng();
+    let (relative, path) = loop {
+        let folder = &folders[rng.random_range(0..folders.len())];
+        let name: String = (0..4)
+            .map(|_| rng.random_range(b'A'..=b'Z') as char)
+            .collect();
+
+        let relative = format!("{MUSIC_DIR}/{folder}/{name}{extension}");
+        let path = mount.join(&relative);
+        if !path.exists() {
+            break (relative, path);
+        }
+    };
+
+    fs::copy(source, &path)
+        .with_context(|| format!("failed to copy {} to {}", source.display(), path.display()))?;
+
+    Ok(format!(":{}", relative.replace('/', ":")))
+}
+
+/// Where the file at a track location like `:iPod_Control:Music:F00:ABCD.mp3` is on
+/// the device mounted at `mount`.
+pub(crate) fn location_path(mount: &Path, location: &str) -> PathBuf {
+    location
+        .split(':')
+        .filter(|part| !part.is_empty())
+        .fold(mount.to_path_buf(), |path, part| path.join(part))
+}
+
+/// The track location of the file at `path` on the device mounted at `mount`, the
+/// inverse of `location_path`.
+pub(crate) fn location(mount: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(mount).ok()?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    Some(format!(":{}", parts.join(":")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn copy_to_device() {
+        let mount = tempfile::tempdir().unwrap();
+        let source = mount.path().join("Song.MP3");
+        fs::write(&source, b"audio").unwrap();
+
+        let location = super::copy_to_device(mount.path(), &source).unwrap();
+
+        assert!(location.starts_with(":iPod_Control:Music:F"));
+        assert!(location.ends_with(".mp3"));
+        assert_eq!(location.len(), ":iPod_Control:Music:F00:ABCD.mp3".len());
+
+        let path = super::location_path(mount.path(), &location);
+        assert_eq!(super::location(mount.path(), &path), Some(location));
+        assert_eq!(fs::read(path).unwrap(), b"audio");
+        assert_eq!(
+            fs::read_dir(mount.path().join(super::MUSIC_DIR))
+                .unwrap()
+                .count(),
+            20
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::mounts;
+
+pub(crate) const SYSFS_ROOT: &str = "/sys";
+
+/// The FWID as the 16 uppercase hex digits hash58 expects. SysInfo writes it with a
+/// 0x prefix, USB serials may be lowercase.
+fn normalize(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+
+    (value.len() == 16 && value.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| value.to_ascii_uppercase())
+}
+
+/// Finds the FireWire GUID of the iPod mounted at `mount`, trying in order the
+/// SysInfoExtended plist, the plain SysInfo file and the USB serial number of the
+/// block device behind the mount, which iPods set to their FWID.
+pub(crate) fn resolve_fwid(
+    sysinfo_extended: &HashMap<String, String>,
+    sysinfo: &HashMap<String, String>,
+    mount: &Path,
+    sysfs_root: &Path,
+    mountinfo_path: &Path,
+) -> Option<String> {
+    sysinfo_extended
+        .get("FireWireGUID")
+        .and_then(|fwid| normalize(fwid))
+        .or_else(|| sysinfo.get("FirewireGuid").and_then(|fwid| normalize(fwid)))
+        .or_else(|| usb_serial(mount, sysfs_root, mountinfo_path))
+}
+
+/// The /sys/block directory of the disk `name` is a partition of, or of `name` itself.
+fn disk_dir(sysfs_root: &Path, name: &str) -> Option<PathBuf> {
+    let block = sysfs_root.join("block");
+
+    if block.join(name).exists() {
+        return Some(block.join(name));
+    }
+
+    // sdb2 -> sdb, mmcblk0p1 -> mmcblk0
+    let disk = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let disk = match disk.strip_suffix('p') {
+        Some(stripped) if stripped.ends_with(|c: char| c.is_ascii_digit()) => stripped,
+        _ => disk,
+    };
+
+    block.join(disk).exists().then(|| block.join(disk))
+}
+
+/// Reads the serial of the USB device behind `mount`: the first `serial` file above
+/// /sys/block/<disk>/device, normally at device/../../serial.
+fn usb_serial(mount: &Path, sysfs_root: &Path, mountinfo_path: &Path) -> Option<String> {
+    let entries = mounts::parse_mountinfo(BufReader::new(File::open(mountinfo_path).ok()?)).ok()?;
+
+    let mount = fs::canonicalize(mount).unwrap_or_else(|_| mount.to_path_buf());
+    let entry = mounts::mount_of(&entries, &mount)?;
+    let name = Path::new(&entry.source).file_name()?.to_str()?;
+
+    let sysfs_root = fs::canonicalize(sysfs_root).ok()?;
+    let device = fs::canonicalize(disk_dir(&sysfs_root, name)?.join("device")).ok()?;
+
+    device
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(&sysfs_root) && *dir != sysfs_root)
+        .find_map(|dir| normalize(&fs::read_to_string(dir.join("serial")).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, os::unix::fs::symlink, path::Path};
+
+    const FWID: &str = "000A270013E10993";
+
+    /// Lays out a sysfs tree like the kernel's for a USB mass storage iPod at sdb,
+    /// and a mountinfo file mounting its second partition at `mount`.
+    fn fake_system(root: &Path, mount: &Path) {
+        let usb = root.join("sys/devices/pci0000:00/usb1/1-1");
+        let scsi = usb.join("1-1:1.0/host6/target6:0:0/6:0:0:0");
+        fs::create_dir_all(&scsi).unwrap();
+        fs::write(usb.join("serial"), "000a270013e10993\n").unwrap();
+
+        let disk = root.join("sys/devices/pci0000:00/usb1/1-1/1-1:1.0/block/sdb");
+        fs::create_dir_all(disk.join("sdb2")).unwrap();
+        symlink(&scsi, disk.join("device")).unwrap();
+
+        fs::create_dir_all(root.join("sys/block")).unwrap();
+        symlink(&disk, root.join("sys/block/sdb")).unwrap();
+
+        fs::write(
+            root.join("mountinfo"),
+            format!(
+                "22 1 259:2 / / rw - ext4 /dev/nvme0n1p2 rw\n\
+                 120 22 8:18 / {} rw,nosuid - vfat /dev/sdb2 rw\n",
+                mount.display()
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn fwid_from_sysinfo_files() {
+        let extended = HashMap::from([("FireWireGUID".into(), FWID.into())]);
+        let sysinfo = HashMap::from([("FirewireGuid".into(), "0x1111222233334444".into())]);
+        let none = HashMap::new();
+
+        let resolve = |extended, sysinfo| {
+            super::resolve_fwid(
+                extended,
+                sysinfo,
+                Path::new("/nonexistent"),
+                Path::new("/nonexistent"),
+                Path::new("/nonexistent"),
+            )
+        };
+
+        assert_eq!(resolve(&extended, &sysinfo).as_deref(), Some(FWID));
+        assert_eq!(
+            resolve(&none, &sysinfo).as_deref(),
+            Some("1111222233334444")
+        );
+        assert_eq!(resolve(&none, &none), None);
+    }
+
+    #[test]
+    fn fwid_from_usb_serial() {
+        let root = tempfile::tempdir().unwrap();
+        let mount = root.path().join("media/IPOD");
+        fs::create_dir_all(&mount).unwrap();
+        fake_system(root.path(), &mount);
+
+        let fwid = super::resolve_fwid(
+            &HashMap::new(),
+            &HashMap::new(),
+            &mount,
+            &root.path().join("sys"),
+            &root.path().join("mountinfo"),
+        );
+
+        assert_eq!(fwid.as_deref(), Some(FWID));
+    }
+}
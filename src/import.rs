@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    db::itunesdb::{Master, Playlist, Track},
+    media::tags,
+    sync,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub playlist_id: u64,
+    pub created: bool,           // false when a playlist with the name was updated
+    pub track_ids: Vec<u32>,     // the resolved entries, in order
+    pub unresolved: Vec<String>, // entries as written in the playlist file
+}
+
+/// An entry of a playlist file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    location: String,
+    title: Option<String>, // from #EXTINF or TitleN, usually "Artist - Title"
+}
+
+fn decode(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        // Plain M3U files are usually Latin-1
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+/// The entries of an M3U or M3U8 file, and the name from `#PLAYLIST:` if it has one.
+fn parse_m3u(text: &str) -> (Option<String>, Vec<Entry>) {
+    let mut name = None;
+    let mut title = None;
+    let mut entries = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+        } else if let Some(playlist) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(playlist.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(Entry {
+                location: line.to_string(),
+                title: title.take(),
+            });
+        }
+    }
+
+    (name, entries)
+}
+
+/// The entries of a PLS file, ordered by their number.
+fn parse_pls(text: &str) -> Vec<Entry> {
+    let mut files = BTreeMap::new();
+    let mut titles = HashMap::new();
+
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let (field, number) =
+            key.split_at(key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len()));
+        let Ok(number) = number.parse::<u32>() else {
+            continue;
+        };
+
+        let value = value.trim().to_string();
+        match field {
+            "file" => files.insert(number, value),
+            "title" => titles.insert(number, value),
+            _ => None,
+        };
+    }
+
+    files
+        .into_iter()
+        .map(|(number, location)| Entry {
+            location,
+            title: titles.remove(&number),
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+
+    while at < bytes.len() {
+        let escaped = (bytes[at] == b'%')
+            .then(|| text.get(at + 1..at + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                at += 3;
+            }
+            None => {
+                decoded.push(bytes[at]);
+                at += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The file an entry points at. Relative entries are relative to `folder`, the one
+/// the playlist is in.
+fn entry_path(location: &str, folder: &Path) -> PathBuf {
+    let location = match location.strip_prefix("file://") {
+        Some(url) => percent_decode(url.strip_prefix("localhost").unwrap_or(url)),
+        None => location.to_string(),
+    };
+
+    // Playlists written on Windows use backslashes
+    folder.join(location.replace('\\', "/"))
+}
+
+/// The names in `path` with `..` applied, lowercased since playlists from case
+/// insensitive filesystems don't always match the case of the files.
+fn components(path: &Path) -> Vec<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_lowercase()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    parts
+}
+
+/// The track whose file is at `path`: one on the device, or the one synced from the
+/// source file with the longest path `path` ends with.
+fn resolve_by_path(
+    path: &[String],
+    locations: &[(u32, Vec<String>)],
+    synced: &[(u32, Vec<String>)],
+) -> Option<u32> {
+    if let Some((track_id, _)) = locations.iter().find(|(_, location)| location == path) {
+        return Some(*track_id);
+    }
+
+    synced
+        .iter()
+        .filter(|(_, source)| !source.is_empty() && path.ends_with(source))
+        .max_by_key(|(_, source)| source.len())
+        .map(|(track_id, _)| *track_id)
+}
+
+fn normalize(text: &str) -> String {
+    text.trim().to_lowercase()
+}
+
+/// The track with the title and artist of the file at `path`, or of the entry's title
+/// when the file can't be read. An album match breaks ties.
+fn resolve_by_tags(master: &Master, path: &Path, entry: &Entry) -> Option<u32> {
+    let (title, artist, album) = match tags::read_audio_file(path) {
+        Ok(file) if file.tags.title.is_some() => {
+            (file.tags.title?, file.tags.artist, file.tags.album)
+        }
+        _ => match entry.title.as_deref()?.split_once(" - ") {
+            Some((artist, title)) => (title.to_string(), Some(artist.to_string()), None),
+            None => (entry.title.clone()?, None, None),
+        },
+    };
+
+    let matches = |value: Option<String>, wanted: &Option<String>| {
+        wanted
+            .as_deref()
+            .is_none_or(|wanted| value.is_some_and(|value| normalize(&value) == normalize(wanted)))
+    };
+    let candidates: Vec<&Track> = master
+        .tracks()
+        .filter(|track| matches(track.title(), &Some(title.clone())))
+        .filter(|track| matches(track.artist(), &artist))
+        .collect();
+
+    candidates
+        .iter()
+        .find(|track| matches(track.album(), &album))
+        .or(candidates.first())
+        .map(|track| track.unique_id())
+}
+
+/// Reads the M3U, M3U8 or PLS playlist at `path` and fills the playlist of the same
+/// name with the tracks its entries resolve to, creating the playlist if needed.
+pub(crate) fn import_playlist(
+    master: &mut Master,
+    mount: &Path,
+    path: &Path,
+) -> anyhow::Result<ImportReport> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let text = decode(&bytes);
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let (name, entries) = match extension.as_deref() {
+        Some("m3u" | "m3u8") => parse_m3u(&text),
+        Some("pls") => (None, parse_pls(&text)),
+        _ => bail!("{} is not an M3U, M3U8 or PLS playlist", path.display()),
+    };
+    let name = name
+        .or_else(|| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "Playlist".to_string());
+
+    let track_ids: HashSet<u32> = master.tracks().map(|track| track.unique_id()).collect();
+    let locations: Vec<(u32, Vec<String>)> = master
+        .tracks()
+        .filter_map(|track| Some((track.unique_id(), components(&track.location_path(mount)?))))
+        .collect();
+    let synced: Vec<(u32, Vec<String>)> = sync::synced_sources(mount)?
+        .into_iter()
+        .filter(|(track_id, _)| track_ids.contains(track_id))
+        .map(|(track_id, source)| (track_id, components(&source)))
+        .collect();
+
+    let folder = path.parent().unwrap_or(Path::new(""));
+    let mut report = ImportReport::default();
+    for entry in &entries {
+        let path = entry_path(&entry.location, folder);
+        match resolve_by_path(&components(&path), &locations, &synced)
+            .or_else(|| resolve_by_tags(master, &path, entry))
+        {
+            Some(track_id) => report.track_ids.push(track_id),
+            None => report.unresolved.push(entry.location.clone()),
+        }
+    }
+
+    let existing = master
+        .playlists()
+        .find(|playlist| !playlist.is_master() && playlist.name().as_deref() == Some(&name))
+        .map(|playlist| playlist.persistent_id());
+    report.playlist_id = match existing {
+        Some(playlist_id) => playlist_id,
+        None => {
+            let playlist = Playlist::new(&name);
+            let playlist_id = playlist.persistent_id();
+            master.add_playlist(playlist);
+            report.created = true;
+            playlist_id
+        }
+    };
+
+    for playlist in master.playlist_mut(report.playlist_id) {
+        playlist.clear_tracks();
+        for track_id in &report.track_ids {
+            playlist.push_track(*track_id);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{iPod, tests::device};
+
+    fn write_sync_state(mount: &std::path::Path) {
+        let fingerprint = "00".repeat(20);
+        fs::write(
+            mount.join("iPod_Control/iTunes/SyncState"),
+            format!("101\t{fingerprint}\t0\t0\tArtist A/Song One.mp3\n"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn import_m3u8() {
+        let mount = device();
+        write_sync_state(mount.path());
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("Road Trip.m3u8");
+        fs::write(
+            &path,
+            "\u{feff}#EXTM3U\n\
+             #EXTINF:180,Artist A - Song One\n\
+             Music/Artist A/Song One.mp3\n\
+             #EXTINF:200,Artist A - Song Two\n\
+             Music/Moved/02 Song Two.mp3\n\
+             file:///elsewhere/Not%20Here.mp3\n",
+        )
+        .unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let report = ipod.import_playlist(&path).unwrap();
+        assert!(report.created);
+        assert_eq!(report.track_ids, vec![101, 102]);
+        assert_eq!(report.unresolved, vec!["file:///elsewhere/Not%20Here.mp3"]);
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        let playlists: Vec<_> = ipod
+            .master()
+            .unwrap()
+            .playlists()
+            .filter(|playlist| playlist.persistent_id() == report.playlist_id)
+            .map(|playlist| (playlist.name(), playlist.track_ids().collect::<Vec<_>>()))
+            .collect();
+        let expected = (Some("Road Trip".to_string()), vec![101, 102]);
+        assert_eq!(playlists, vec![expected.clone(), expected]);
+    }
+
+    #[test]
+    fn import_pls_updates_playlist() {
+        let mount = device();
+        write_sync_state(mount.path());
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("Favourites.pls");
+        fs::write(
+            &path,
+            "[playlist]\n\
+             File1=C:\\Music\\ARTIST A\\Song One.mp3\n\
+             File2=D:\\Other Song.mp3\n\
+             Title2=Other Song\n\
+             File3=nothing.mp3\n\
+             NumberOfEntries=3\n",
+        )
+        .unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let favourites = ipod
+            .master()
+            .unwrap()
+            .playlists()
+            .find(|playlist| playlist.name().as_deref() == Some("Favourites"))
+            .unwrap()
+            .persistent_id();
+
+        let report = ipod.import_playlist(&path).unwrap();
+        assert!(!report.created);
+        assert_eq!(report.playlist_id, favourites);
+        assert_eq!(report.track_ids, vec![101, 103]);
+        assert_eq!(report.unresolved, vec!["nothing.mp3"]);
+
+        assert!(ipod
+            .import_playlist(folder.path().join("Favourites.txt"))
+            .is_err());
+    }
+}
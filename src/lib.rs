@@ -1,25 +1,645 @@
 #![allow(unused, non_camel_case_types)]
 
-use std::path::PathBuf;
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
 
 pub(crate) mod db;
+pub(crate) mod discover;
+pub(crate) mod extract;
+pub(crate) mod files;
+pub(crate) mod fwid;
+pub(crate) mod import;
+pub(crate) mod library;
+pub(crate) mod media;
+pub(crate) mod model;
+pub(crate) mod mounts;
+pub(crate) mod sync;
+pub(crate) mod sysinfo;
+pub(crate) mod transaction;
 pub(crate) mod util;
 
+pub use db::checksum::{DatabaseSigner, DatabaseVerification, HashStatus, HashingScheme};
+pub use db::itunesdb::check::Problem;
+pub use db::itunesdb::dump::{diff_itunesdb, dump_itunesdb};
+pub use db::itunesdb::duplicates::{DuplicateGroup, DuplicateStrategy};
+#[cfg(feature = "serde")]
+pub use db::itunesdb::json::{export_json, import_json};
+pub use db::itunesdb::podcasts::PodcastEpisode;
+pub use discover::{discover, discover_in, DiscoveredDevice};
+pub use extract::{ExtractOptions, ExtractReport};
+pub use import::ImportReport;
+pub use library::{AlbumInfo, PlaylistInfo, TrackInfo};
+pub use media::soundcheck::{gain_from_soundcheck, soundcheck_from_gain, ReplayGain};
+pub use model::{Color, Model, ModelInfo};
+pub use sync::{SyncAction, SyncOptions, SyncPlan};
+pub use transaction::{TrackText, Transaction};
+
+use db::checksum::{Hash58Signer, Hash72Signer};
+
+const ITUNESDB_PATH: &str = "iPod_Control/iTunes/iTunesDB";
+const SYSINFO_EXTENDED_PATH: &str = "iPod_Control/Device/SysInfoExtended";
+const SYSINFO_PATH: &str = "iPod_Control/Device/SysInfo";
+const HASH_INFO_PATH: &str = "iPod_Control/Device/HashInfo";
+
 pub struct iPod {
     path: PathBuf,
-    fwid: String,
+    fwid: Option<String>, // None when neither SysInfo file nor the USB serial has it
     serial_num: String,
     product_type: String,
     build_version: String,
+    model: Option<ModelInfo>,
+    signers: Vec<Box<dyn DatabaseSigner>>,
     itunesdb: db::itunesdb::Record,
+    removed: Vec<PathBuf>, // files of removed tracks, deleted once the database is saved
 }
 
-impl iPod {}
+impl iPod {
+    /// Opens the iPod mounted at `path`, reading its device info and iTunesDB.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let itunesdb_path = path.join(ITUNESDB_PATH);
+        let buf = fs::read(&itunesdb_path)
+            .with_context(|| format!("failed to read {}", itunesdb_path.display()))?;
+        let itunesdb = db::itunesdb::io::read_from_buffer(&buf)?;
+
+        Self::with_database(path, itunesdb, Some(&buf))
+    }
+
+    /// Recreates the iTunesDB of the iPod mounted at `path` from the music files on it,
+    /// for when the database is lost or unreadable. Nothing is written until
+    /// [`iPod::save`], so a signer can be registered first. Also returns the files that
+    /// couldn't be read, which are left alone and show up in [`iPod::check`].
+    pub fn rebuild_from_files(path: impl AsRef<Path>) -> anyhow::Result<(Self, Vec<PathBuf>)> {
+        let path = path.as_ref().to_path_buf();
+        let empty = db::itunesdb::Master::new(HashingScheme::None, "iPod");
+        let mut ipod = Self::with_database(path, db::itunesdb::Record::mhbd(empty), None)?;
+
+        // Devices the SysInfo doesn't identify get whichever checksum we can sign
+        let scheme = match &ipod.model {
+            Some(model) => model.hashing_scheme(),
+            None if ipod
+                .signers
+                .iter()
+                .any(|signer| signer.scheme() == HashingScheme::Hash72) =>
+            {
+                HashingScheme::Hash72
+            }
+            None if ipod.fwid.is_some() => HashingScheme::Hash58,
+            None => HashingScheme::None,
+        };
+
+        let (itunesdb, skipped) =
+            db::itunesdb::rebuild::rebuild_from_files(&ipod.path, scheme, ReplayGain::default());
+        ipod.itunesdb = itunesdb;
+
+        if let Some(dir) = ipod.path.join(ITUNESDB_PATH).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok((ipod, skipped))
+    }
+
+    /// Reads the device info of the iPod mounted at `path` to go with its database.
+    /// `signed` is the database as read from the device, used to recover the HashInfo.
+    fn with_database(
+        path: PathBuf,
+        itunesdb: db::itunesdb::Record,
+        signed: Option<&[u8]>,
+    ) -> anyhow::Result<Self> {
+        // Older firmwares don't write SysInfoExtended, and the plain SysInfo may be
+        // missing too, so both are optional.
+        let sysinfo_extended = match File::open(path.join(SYSINFO_EXTENDED_PATH)) {
+            Ok(file) => sysinfo::parse_extended(BufReader::new(file))?,
+            Err(_) => Default::default(),
+        };
+        let sysinfo = match File::open(path.join(SYSINFO_PATH)) {
+            Ok(file) => sysinfo::parse_sysinfo(BufReader::new(file))?,
+            Err(_) => Default::default(),
+        };
+
+        let extended = |key: &str| sysinfo_extended.get(key).cloned().unwrap_or_default();
+        // SysInfoExtended first, the plain SysInfo names some keys differently
+        let value = |extended_key: &str, key: &str| {
+            sysinfo_extended
+                .get(extended_key)
+                .or_else(|| sysinfo.get(key))
+                .map(String::as_str)
+        };
+        // Only hash58 and hashAB need the FWID, older iPods have no checksum at all
+        let fwid = fwid::resolve_fwid(
+            &sysinfo_extended,
+            &sysinfo,
+            &path,
+            Path::new(fwid::SYSFS_ROOT),
+            Path::new(mounts::MOUNTINFO_PATH),
+        );
+
+        // Devices using hash72 need the HashInfo iTunes leaves behind, or one recovered
+        // from the last database iTunes signed. Without either the device still opens,
+        // saving fails and verify_database can't check the hash.
+        let hash_info = match fs::read(path.join(HASH_INFO_PATH)) {
+            Ok(bytes) => db::hash72::HashInfo::from_bytes(&bytes).ok(),
+            Err(_) => match (signed, &fwid) {
+                (Some(buf), Some(fwid)) => {
+                    db::checksum::extract_hash_info(buf, fwid).ok().flatten()
+                }
+                _ => None,
+            },
+        };
+
+        let mut signers: Vec<Box<dyn DatabaseSigner>> = vec![Box::new(Hash58Signer)];
+        if let Some(hash_info) = hash_info {
+            signers.push(Box::new(Hash72Signer(hash_info)));
+        }
+
+        let serial_num = value("SerialNumber", "pszSerialNumber");
+        let model = ModelInfo::identify(
+            value("ModelNumStr", "ModelNumStr"),
+            serial_num,
+            sysinfo_extended.get("FamilyID").map(String::as_str),
+            sysinfo_extended.get("ProductType").map(String::as_str),
+        );
+
+        Ok(iPod {
+            fwid,
+            model,
+            serial_num: serial_num.unwrap_or_default().to_string(),
+            product_type: extended("ProductType"),
+            build_version: extended("VisibleBuildID"),
+            signers,
+            path,
+            itunesdb,
+            removed: Vec::new(),
+        })
+    }
+
+    /// The model of the device, when its SysInfo files identify it.
+    pub fn model(&self) -> Option<&ModelInfo> {
+        self.model.as_ref()
+    }
+
+    /// Where the device is mounted.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The FireWire GUID the database checksums are keyed by, as 16 hex digits.
+    pub fn fwid(&self) -> Option<&str> {
+        self.fwid.as_deref()
+    }
+
+    pub fn tracks(&self) -> anyhow::Result<Vec<TrackInfo>> {
+        Ok(library::tracks(self.master()?))
+    }
+
+    /// The playlists, including the master playlist.
+    pub fn playlists(&self) -> anyhow::Result<Vec<PlaylistInfo>> {
+        Ok(library::playlists(self.master()?))
+    }
+
+    pub fn albums(&self) -> anyhow::Result<Vec<AlbumInfo>> {
+        Ok(library::albums(self.master()?))
+    }
+
+    /// Writes the iTunesDB back to the device, regenerating the album list, library
+    /// indices and checksum, then deletes the files of tracks removed since.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let buf = db::itunesdb::io::write_database(
+            &mut self.itunesdb,
+            self.fwid.as_deref(),
+            &self.signers,
+        )?;
+        self.write_itunesdb(buf)?;
+        self.delete_removed();
+        Ok(())
+    }
+
+    /// Deletes the files of removed tracks, which the database on the device no longer
+    /// points at. One that can't be deleted is left for [`iPod::check`] to find.
+    fn delete_removed(&mut self) {
+        for path in self.removed.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Replaces the iTunesDB on the device, through a temporary file so a failed write
+    /// leaves the old one in place.
+    fn write_itunesdb(&self, buf: Vec<u8>) -> anyhow::Result<()> {
+        let itunesdb_path = self.path.join(ITUNESDB_PATH);
+        let tmp_path = itunesdb_path.with_extension("tmp");
+
+        fs::write(&tmp_path, buf)?;
+        fs::rename(&tmp_path, &itunesdb_path)?;
+
+        Ok(())
+    }
+
+    /// Starts staging changes to the database, see [`Transaction`].
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    /// Registers the signer used for databases with its hashing scheme, replacing the
+    /// built in one if there is one. Needed to save databases of devices using hashAB.
+    pub fn register_signer(&mut self, signer: impl DatabaseSigner + 'static) {
+        self.signers
+            .retain(|registered| registered.scheme() != signer.scheme());
+        self.signers.push(Box::new(signer));
+    }
+
+    /// The database as JSON, see [`export_json`].
+    #[cfg(feature = "serde")]
+    pub fn export_json(&self) -> anyhow::Result<String> {
+        db::itunesdb::json::to_json(&self.itunesdb)
+    }
+
+    /// Replaces the database with the one described by `json`, see [`import_json`].
+    /// Nothing is written until [`iPod::save`].
+    #[cfg(feature = "serde")]
+    pub fn import_json(&mut self, json: &str) -> anyhow::Result<()> {
+        let itunesdb = db::itunesdb::json::from_json(json)?;
+        db::itunesdb::check::validate(&itunesdb)
+            .context("refusing to import an invalid database")?;
+
+        self.itunesdb = itunesdb;
+        Ok(())
+    }
+
+    /// Checks the checksums of the iTunesDB currently on the device against the ones
+    /// computed from its bytes by the registered signers.
+    pub fn verify_database(&self) -> anyhow::Result<DatabaseVerification> {
+        let itunesdb_path = self.path.join(ITUNESDB_PATH);
+        let buf = fs::read(&itunesdb_path)
+            .with_context(|| format!("failed to read {}", itunesdb_path.display()))?;
+
+        db::checksum::verify(&buf, self.fwid.as_deref(), &self.signers)
+    }
+
+    /// The iTunesDB file on the device as a record tree, see [`dump_itunesdb`].
+    pub fn dump_database(&self) -> anyhow::Result<String> {
+        let itunesdb_path = self.path.join(ITUNESDB_PATH);
+        let buf = fs::read(&itunesdb_path)
+            .with_context(|| format!("failed to read {}", itunesdb_path.display()))?;
+
+        db::itunesdb::dump::dump_itunesdb(&buf)
+    }
+
+    fn master(&self) -> anyhow::Result<&db::itunesdb::Master> {
+        match &self.itunesdb {
+            db::itunesdb::Record::mhbd(master) => Ok(master),
+            _ => anyhow::bail!("iTunesDB does not start with an mhbd record"),
+        }
+    }
+
+    fn master_mut(&mut self) -> anyhow::Result<&mut db::itunesdb::Master> {
+        match &mut self.itunesdb {
+            db::itunesdb::Record::mhbd(master) => Ok(master),
+            _ => anyhow::bail!("iTunesDB does not start with an mhbd record"),
+        }
+    }
+
+    /// Copies the audio file at `source` to the device and adds a track for it, filled
+    /// in from its tags. Returns the unique id of the new track.
+    pub fn add_track(
+        &mut self,
+        source: impl AsRef<Path>,
+        replay_gain: ReplayGain,
+    ) -> anyhow::Result<u32> {
+        let source = source.as_ref();
+        let unique_id = self.master()?.next_track_id();
+
+        let mut track = db::itunesdb::Track::from_file(unique_id, source, replay_gain)
+            .with_context(|| format!("failed to read {}", source.display()))?;
+        let location = files::copy_to_device(&self.path, source)?;
+        track.set_string(2, &location);
+
+        self.master_mut()?.add_track(track);
+        Ok(unique_id)
+    }
+
+    /// Copies the audio file at `source` to the device and adds it as an unplayed
+    /// episode of `episode.show`, listed under the show in the Podcasts menu once saved.
+    /// Returns the unique id of the new track.
+    pub fn add_podcast(
+        &mut self,
+        source: impl AsRef<Path>,
+        episode: &PodcastEpisode,
+    ) -> anyhow::Result<u32> {
+        let unique_id = self.add_track(source, ReplayGain::default())?;
+        if let Some(track) = self.master_mut()?.track_mut(unique_id) {
+            track.set_podcast(episode);
+        }
+        Ok(unique_id)
+    }
+
+    /// Removes the track from the database. Its file is deleted by the next
+    /// [`iPod::save`], so the database on the device never points at a missing file.
+    pub fn remove_track(&mut self, unique_id: u32) -> anyhow::Result<()> {
+        let Some(track) = self.master_mut()?.remove_track(unique_id) else {
+            anyhow::bail!("no track with id {unique_id}");
+        };
+
+        if let Some(path) = track.location_path(&self.path) {
+            self.removed.push(path);
+        }
+        Ok(())
+    }
+
+    /// Makes the device match the audio files under `source`: files are added, tracks
+    /// of changed files updated and tracks of deleted files removed. Returns the plan,
+    /// only computed when `options.dry_run` is set.
+    pub fn sync(
+        &mut self,
+        source: impl AsRef<Path>,
+        options: &SyncOptions,
+    ) -> anyhow::Result<SyncPlan> {
+        let plan = sync::plan(self, source.as_ref(), options)?;
+        if !options.dry_run {
+            sync::apply(self, &plan, options)?;
+        }
+        Ok(plan)
+    }
+
+    /// Looks for problems with the database and the files on the device.
+    pub fn check(&self) -> Vec<Problem> {
+        db::itunesdb::check::check(&self.itunesdb, &self.path)
+    }
+
+    /// Fixes the problems [`iPod::check`] finds and saves the database. Tracks whose
+    /// file is missing are removed and music files no track points at are added back.
+    /// Returns the problems that couldn't be fixed.
+    pub fn repair(&mut self) -> anyhow::Result<Vec<Problem>> {
+        let problems = self.check();
+        let unfixed = db::itunesdb::check::repair(
+            &mut self.itunesdb,
+            &self.path,
+            &problems,
+            ReplayGain::default(),
+        );
+
+        self.save()?;
+        Ok(unfixed)
+    }
+
+    /// Groups the tracks that `strategy` considers copies of each other.
+    pub fn find_duplicates(
+        &self,
+        strategy: DuplicateStrategy,
+    ) -> anyhow::Result<Vec<DuplicateGroup>> {
+        Ok(db::itunesdb::duplicates::find_duplicates(
+            self.master()?,
+            strategy,
+            &self.path,
+        ))
+    }
+
+    /// Merges every duplicate into its keeper and removes it from the database, see
+    /// [`iPod::find_duplicates`]. Files no remaining track points at are deleted by the
+    /// next [`iPod::save`].
+    pub fn remove_duplicates(&mut self, groups: &[DuplicateGroup]) -> anyhow::Result<()> {
+        let master = self.master_mut()?;
+        let removed = db::itunesdb::duplicates::remove_duplicates(master, groups);
+        let master = self.master()?;
+
+        let orphaned = db::itunesdb::duplicates::orphaned_files(master, &removed, &self.path);
+        self.removed.extend(orphaned);
+        Ok(())
+    }
+
+    /// Imports the M3U, M3U8 or PLS playlist at `path` into the playlist of the same
+    /// name, creating it if needed, and saves the database. Entries are matched to
+    /// tracks by the source files they were synced from, or by title and artist.
+    pub fn import_playlist(&mut self, path: impl AsRef<Path>) -> anyhow::Result<ImportReport> {
+        let mount = self.path.clone();
+        let report = import::import_playlist(self.master_mut()?, &mount, path.as_ref())?;
+
+        self.save()?;
+        Ok(report)
+    }
+
+    /// Copies every track off the device into `dest`, organized by
+    /// `options.template`, and writes each playlist next to them as an M3U8.
+    pub fn extract(
+        &self,
+        dest: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> anyhow::Result<ExtractReport> {
+        extract::extract(self.master()?, &self.path, dest.as_ref(), options, None)
+    }
+
+    /// Like [`iPod::extract`], but only the tracks of the playlist called `name`.
+    pub fn extract_playlist(
+        &self,
+        name: &str,
+        dest: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> anyhow::Result<ExtractReport> {
+        extract::extract(
+            self.master()?,
+            &self.path,
+            dest.as_ref(),
+            options,
+            Some(name),
+        )
+    }
+}
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use quick_xml::{events::Event, Reader};
-    use std::{fs::File, io::BufReader, path::PathBuf};
+    use std::{
+        fs::{self, File},
+        io::BufReader,
+        path::PathBuf,
+    };
+
+    /// A mounted device with the sample database and SysInfoExtended.
+    pub(crate) fn device() -> tempfile::TempDir {
+        let mount = tempfile::tempdir().unwrap();
+        fs::create_dir_all(mount.path().join("iPod_Control/iTunes")).unwrap();
+        fs::create_dir_all(mount.path().join("iPod_Control/Device")).unwrap();
+
+        // The sample's playlist entries have their track id 4 bytes late, where the
+        // timestamp goes. Moved into place so the playlists point at the tracks.
+        let mut itunesdb = include_bytes!("db/itunesdb/sample/iTunesDB").to_vec();
+        let mut at = 0;
+        while let Some(found) = itunesdb[at..].windows(4).position(|magic| magic == b"mhip") {
+            at += found;
+            itunesdb.copy_within(at + 0x1C..at + 0x20, at + 0x18);
+            itunesdb[at + 0x1C..at + 0x20].fill(0);
+            at += 4;
+        }
+
+        fs::write(mount.path().join(super::ITUNESDB_PATH), itunesdb).unwrap();
+        fs::write(
+            mount.path().join(super::SYSINFO_EXTENDED_PATH),
+            include_bytes!("db/itunesdb/sample/ExtendedSysInfoXml"),
+        )
+        .unwrap();
+        mount
+    }
+
+    #[test]
+    fn rebuild_from_files() {
+        let mount = device();
+        fs::remove_dir_all(mount.path().join("iPod_Control/iTunes")).unwrap();
+        let music = mount.path().join("iPod_Control/Music/F04");
+        fs::create_dir_all(&music).unwrap();
+        fs::write(
+            music.join("WXYZ.mp3"),
+            crate::media::mp3::tests::tagged_mp3(),
+        )
+        .unwrap();
+        fs::write(music.join("BAD.mp3"), b"not audio").unwrap();
+
+        let (mut ipod, skipped) = super::iPod::rebuild_from_files(mount.path()).unwrap();
+        assert_eq!(skipped, vec![music.join("BAD.mp3")]);
+        assert!(!mount.path().join(super::ITUNESDB_PATH).exists());
+        ipod.save().unwrap();
+
+        let ipod = super::iPod::open(mount.path()).unwrap();
+        assert_eq!(
+            ipod.check(),
+            vec![super::Problem::OrphanedFile {
+                path: music.join("BAD.mp3")
+            }]
+        );
+        assert_eq!(ipod.master().unwrap().tracks().count(), 1);
+        let verification = ipod.verify_database().unwrap();
+        assert!(verification.is_valid(), "{verification:?}");
+    }
+
+    #[test]
+    fn open_without_fwid() {
+        // No SysInfo files and a database older iPods leave unsigned
+        let mount = tempfile::tempdir().unwrap();
+        fs::create_dir_all(mount.path().join("iPod_Control/iTunes")).unwrap();
+        let mut root = crate::db::itunesdb::Record::mhbd(crate::db::itunesdb::Master::new(
+            super::HashingScheme::None,
+            "iPod",
+        ));
+        let buf = crate::db::itunesdb::io::write_database(&mut root, None, &[]).unwrap();
+        fs::write(mount.path().join(super::ITUNESDB_PATH), buf).unwrap();
+
+        let mut ipod = super::iPod::open(mount.path()).unwrap();
+        assert!(ipod.model().is_none());
+        ipod.save().unwrap();
+        let verification = ipod.verify_database().unwrap();
+        assert!(verification.is_valid(), "{verification:?}");
+    }
+
+    #[test]
+    fn open_without_hash_info() {
+        // A hash72 database that some other tool left unsigned, and a corrupt HashInfo
+        let mount = device();
+        let mut buf = fs::read(mount.path().join(super::ITUNESDB_PATH)).unwrap();
+        buf[0x22..0x24].copy_from_slice(&2u16.to_le_bytes());
+        buf[0x72..0xA0].fill(0xFF);
+        fs::write(mount.path().join(super::ITUNESDB_PATH), buf).unwrap();
+
+        let mut ipod = super::iPod::open(mount.path()).unwrap();
+        let verification = ipod.verify_database().unwrap();
+        assert_eq!(verification.hash72, super::HashStatus::Unverifiable);
+        let error = ipod.save().unwrap_err();
+        assert!(format!("{error:#}").contains("no signer"), "{error:#}");
+
+        fs::write(
+            mount.path().join(super::HASH_INFO_PATH),
+            b"HASHv0 truncated",
+        )
+        .unwrap();
+        assert!(super::iPod::open(mount.path()).is_ok());
+    }
+
+    #[test]
+    fn identify_from_sysinfo_serial() {
+        let mount = device();
+        fs::remove_file(mount.path().join(super::SYSINFO_EXTENDED_PATH)).unwrap();
+        fs::write(
+            mount.path().join(super::SYSINFO_PATH),
+            "pszSerialNumber: 8K9466E39ZU\nFirewireGuid: 0x000A270013E10993\n",
+        )
+        .unwrap();
+
+        let ipod = super::iPod::open(mount.path()).unwrap();
+        assert_eq!(ipod.serial_num, "8K9466E39ZU");
+        let model = ipod.model().unwrap();
+        assert_eq!((model.model, model.generation), (super::Model::Classic, 3));
+    }
+
+    #[test]
+    fn rebuild_without_fwid() {
+        // Nothing says which iPod this is, so the database is left unsigned
+        let mount = tempfile::tempdir().unwrap();
+        let music = mount.path().join("iPod_Control/Music/F00");
+        fs::create_dir_all(&music).unwrap();
+        fs::write(
+            music.join("AAAA.mp3"),
+            crate::media::mp3::tests::tagged_mp3(),
+        )
+        .unwrap();
+
+        let (mut ipod, skipped) = super::iPod::rebuild_from_files(mount.path()).unwrap();
+        assert!(skipped.is_empty());
+        ipod.save().unwrap();
+
+        let verification = ipod.verify_database().unwrap();
+        assert_eq!(verification.scheme, super::HashingScheme::None);
+    }
+
+    #[test]
+    fn remove_track_keeps_file_until_saved() {
+        let mount = device();
+        let music = mount.path().join("iPod_Control/Music/F00");
+        fs::create_dir_all(&music).unwrap();
+        fs::write(
+            music.join("AAAA.mp3"),
+            crate::media::mp3::tests::tagged_mp3(),
+        )
+        .unwrap();
+
+        let mut ipod = super::iPod::open(mount.path()).unwrap();
+        ipod.remove_track(101).unwrap();
+        assert!(music.join("AAAA.mp3").exists());
+
+        ipod.save().unwrap();
+        assert!(!music.join("AAAA.mp3").exists());
+    }
+
+    #[test]
+    fn remove_duplicates_deletes_orphaned_files_when_saved() {
+        let mount = device();
+        let music = mount.path().join("iPod_Control/Music/F00");
+        fs::create_dir_all(&music).unwrap();
+        fs::write(
+            music.join("AAAA.mp3"),
+            crate::media::mp3::tests::tagged_mp3(),
+        )
+        .unwrap();
+
+        let mut ipod = super::iPod::open(mount.path()).unwrap();
+        let keeper = ipod
+            .master()
+            .unwrap()
+            .tracks()
+            .map(|track| track.unique_id())
+            .find(|&unique_id| unique_id != 101)
+            .unwrap();
+        ipod.remove_duplicates(&[super::DuplicateGroup {
+            keeper,
+            duplicates: vec![101],
+        }])
+        .unwrap();
+        assert!(music.join("AAAA.mp3").exists());
+
+        ipod.save().unwrap();
+        assert!(!music.join("AAAA.mp3").exists());
+    }
 
     #[test]
     fn check_fwid() {
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::db::itunesdb::{albums, Master};
+
+/// A track as listed by [`crate::iPod::tracks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub id: u32,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration_ms: u32,
+    pub location: Option<String>, // eg. ":iPod_Control:Music:F00:ABCD.mp3"
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistInfo {
+    pub id: u64,
+    pub name: Option<String>,
+    pub is_master: bool, // the playlist listing every track
+    pub track_ids: Vec<u32>,
+}
+
+/// Tracks grouped by album and album artist, the way the device groups them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumInfo {
+    pub album: String,
+    pub artist: String,
+    pub track_ids: Vec<u32>,
+}
+
+pub(crate) fn tracks(master: &Master) -> Vec<TrackInfo> {
+    master
+        .tracks()
+        .map(|track| TrackInfo {
+            id: track.unique_id(),
+            title: track.title(),
+            artist: track.artist(),
+            album: track.album(),
+            duration_ms: track.duration_ms(),
+            location: track.location(),
+        })
+        .collect()
+}
+
+pub(crate) fn playlists(master: &Master) -> Vec<PlaylistInfo> {
+    // The podcast list repeats the playlists
+    let mut seen = HashSet::new();
+
+    master
+        .playlists()
+        .filter(|playlist| seen.insert(playlist.persistent_id()))
+        .map(|playlist| PlaylistInfo {
+            id: playlist.persistent_id(),
+            name: playlist.name(),
+            is_master: playlist.is_master(),
+            track_ids: playlist.track_ids().collect(),
+        })
+        .collect()
+}
+
+pub(crate) fn albums(master: &Master) -> Vec<AlbumInfo> {
+    let mut albums: Vec<AlbumInfo> = Vec::new();
+    let mut positions = HashMap::new();
+
+    for track in master.tracks() {
+        let (album, artist) = albums::track_album_key(track);
+        let position = *positions
+            .entry((album.clone(), artist.clone()))
+            .or_insert_with(|| {
+                albums.push(AlbumInfo {
+                    album,
+                    artist,
+                    track_ids: Vec::new(),
+                });
+                albums.len() - 1
+            });
+        albums[position].track_ids.push(track.unique_id());
+    }
+
+    albums.sort_by(|a, b| (&a.artist, &a.album).cmp(&(&b.artist, &b.album)));
+    albums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AlbumInfo;
+    use crate::{iPod, tests::device};
+
+    #[test]
+    fn list_library() {
+        let mount = device();
+        let ipod = iPod::open(mount.path()).unwrap();
+
+        let tracks = ipod.tracks().unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].title.as_deref(), Some("Song One"));
+        assert_eq!(
+            tracks[0].location.as_deref(),
+            Some(":iPod_Control:Music:F00:AAAA.mp3")
+        );
+
+        let playlists = ipod.playlists().unwrap();
+        let names: Vec<_> = playlists
+            .iter()
+            .map(|playlist| (playlist.name.as_deref(), playlist.is_master))
+            .collect();
+        assert_eq!(
+            names,
+            vec![(Some("iPod"), true), (Some("Favourites"), false)]
+        );
+        assert_eq!(playlists[1].track_ids, vec![101, 103]);
+
+        let album = |album: &str, artist: &str, track_ids: Vec<u32>| AlbumInfo {
+            album: album.to_string(),
+            artist: artist.to_string(),
+            track_ids,
+        };
+        assert_eq!(
+            ipod.albums().unwrap(),
+            vec![
+                album("Album X", "Artist A", vec![101, 102]),
+                album("Album Y", "Artist B", vec![103]),
+            ]
+        );
+    }
+}
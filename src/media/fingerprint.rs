@@ -0,0 +1,66 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::bail;
+use sha1::{Digest, Sha1};
+
+use super::{mp3, mp4};
+
+/// SHA1 of the audio stream of an MP3 or MP4 file, leaving out the tags so files that
+/// only differ in their metadata hash the same.
+pub(crate) fn audio_hash(path: impl AsRef<Path>) -> anyhow::Result<[u8; 20]> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+
+    let audio = match magic {
+        [_, _, _, _, b'f', b't', b'y', b'p'] => {
+            let Some(mdat) = mp4::find(&mut reader, &[b"mdat"])? else {
+                bail!("no mdat atom");
+            };
+            mp4::read_body(&mut reader, &mdat)?
+        }
+        _ => {
+            let mut buf = Vec::new();
+            reader.seek(SeekFrom::Start(0))?;
+            reader.read_to_end(&mut buf)?;
+
+            // ID3v2 at the start, its size syncsafe, and ID3v1 in the last 128 bytes
+            let start = mp3::id3v2_len(&buf);
+            let end = match buf.len().checked_sub(128) {
+                Some(at) if buf[at..].starts_with(b"TAG") => at,
+                _ => buf.len(),
+            };
+
+            buf.get(start..end).unwrap_or_default().to_vec()
+        }
+    };
+
+    Ok(Sha1::digest(&audio).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::media::mp3;
+
+    fn hash(bytes: &[u8]) -> [u8; 20] {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        super::audio_hash(file.path()).unwrap()
+    }
+
+    #[test]
+    fn tags_dont_change_the_hash() {
+        let tagged = mp3::tests::tagged_mp3();
+        let untagged = mp3::tests::mp3(&[], 100, &[]);
+
+        assert_eq!(hash(&tagged), hash(&untagged));
+        assert_ne!(hash(&untagged), hash(&mp3::tests::mp3(&[], 99, &[])));
+    }
+}
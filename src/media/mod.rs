@@ -0,0 +1,5 @@
+pub(crate) mod fingerprint;
+pub(crate) mod mp3;
+pub(crate) mod mp4;
+pub(crate) mod soundcheck;
+pub(crate) mod tags;
@@ -0,0 +1,883 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use anyhow::bail;
+
+use super::{
+    soundcheck,
+    tags::{self, AudioProperties, Codec, Gapless, Tags},
+};
+
+const ID3V1_LEN: u64 = 128;
+
+pub(crate) fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0u32, |acc, byte| (acc << 7) | (*byte & 0x7F) as u32)
+}
+
+/// Undoes ID3 unsynchronisation: every 0xFF 0x00 pair was written for a lone 0xFF.
+fn unsynchronise(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut previous = 0u8;
+
+    for &byte in bytes {
+        if !(previous == 0xFF && byte == 0x00) {
+            out.push(byte);
+        }
+        previous = byte;
+    }
+
+    out
+}
+
+/// Decodes an ID3 text frame body. Values after the first NUL are extra values in
+/// ID3v2.4, only the first is kept.
+fn decode_text(encoding: u8, bytes: &[u8]) -> String {
+    let text = match encoding {
+        // UTF-16 with a byte order mark, or big endian without one
+        1 | 2 => {
+            let (big_endian, bytes) = match bytes {
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                _ => (encoding == 2, bytes),
+            };
+
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| match big_endian {
+                    true => u16::from_be_bytes([pair[0], pair[1]]),
+                    false => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .take_while(|unit| *unit != 0)
+                .collect();
+
+            return String::from_utf16_lossy(&units).trim().to_string();
+        }
+        3 => String::from_utf8_lossy(bytes).into_owned(),
+        _ => bytes.iter().map(|byte| *byte as char).collect(), // ISO-8859-1
+    };
+
+    text.split('\0')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// Splits a NUL terminated string off the front of `bytes`, two NULs for UTF-16.
+fn split_terminated(encoding: u8, bytes: &[u8]) -> (&[u8], &[u8]) {
+    let position = match encoding {
+        1 | 2 => bytes
+            .chunks_exact(2)
+            .position(|pair| pair == [0, 0])
+            .map(|i| (i * 2, i * 2 + 2)),
+        _ => bytes.iter().position(|byte| *byte == 0).map(|i| (i, i + 1)),
+    };
+
+    match position {
+        Some((end, rest)) => (&bytes[..end], &bytes[rest..]),
+        None => (bytes, &[]),
+    }
+}
+
+/// ID3v2 genres are either names or ID3v1 indices like "(17)", "17" or "(17)Rock".
+fn parse_genre(text: &str) -> String {
+    let index = match text.strip_prefix('(') {
+        Some(rest) => rest.split(')').next(),
+        None => Some(text),
+    };
+
+    match index
+        .and_then(|index| index.parse::<usize>().ok())
+        .and_then(tags::id3v1_genre)
+    {
+        Some(genre) => genre,
+        None => match text.rsplit_once(')') {
+            Some((_, name)) if text.starts_with('(') && !name.is_empty() => name.to_string(),
+            _ => text.to_string(),
+        },
+    }
+}
+
+/// Parses the frames of an ID3v2.3 or ID3v2.4 tag, `tag` being everything after the
+/// 10 byte header.
+fn parse_id3v2(major: u8, flags: u8, tag: &[u8]) -> Tags {
+    let tag = match (major, flags & 0x80) {
+        (3, 0x80) => unsynchronise(tag),
+        _ => tag.to_vec(),
+    };
+
+    let mut at = 0;
+    if flags & 0x40 != 0 {
+        at = match major {
+            3 => {
+                4 + tag
+                    .get(..4)
+                    .map_or(0, |size| u32::from_be_bytes(size.try_into().unwrap()))
+            }
+            _ => tag.get(..4).map_or(0, syncsafe),
+        } as usize;
+    }
+
+    let mut tags = Tags::default();
+
+    while at + 10 <= tag.len() && tag[at] != 0 {
+        let id = &tag[at..at + 4];
+        let size = match major {
+            4 => syncsafe(&tag[at + 4..at + 8]),
+            _ => u32::from_be_bytes(tag[at + 4..at + 8].try_into().unwrap()),
+        } as usize;
+        let frame_flags = tag[at + 9];
+
+        let Some(body) = tag.get(at + 10..at + 10 + size) else {
+            break;
+        };
+        at += 10 + size;
+
+        // Compressed and encrypted frames are skipped
+        if major == 4 && frame_flags & 0x0C != 0 || major == 3 && frame_flags & 0xC0 != 0 {
+            continue;
+        }
+
+        let body = match (major, frame_flags & 0x02) {
+            (4, 0x02) => unsynchronise(body),
+            _ => body.to_vec(),
+        };
+        // Data length indicator
+        let body = match (major, frame_flags & 0x01) {
+            (4, 0x01) => body.get(4..).unwrap_or_default().to_vec(),
+            _ => body,
+        };
+
+        let Some((&encoding, text)) = body.split_first() else {
+            continue;
+        };
+
+        if id == b"COMM" {
+            // Language, then a description. iTunes keeps its own data in described comments.
+            let (description, text) = split_terminated(encoding, text.get(3..).unwrap_or_default());
+            let text = decode_text(encoding, text);
+
+            match decode_text(encoding, description).as_str() {
+                "" if tags.comment.is_none() && !text.is_empty() => tags.comment = Some(text),
+                "iTunNORM" => tags.itunnorm = soundcheck::parse_itunnorm(&text),
+                _ => {}
+            }
+            continue;
+        }
+
+        if id == b"TXXX" {
+            let (description, text) = split_terminated(encoding, text);
+            let gain = soundcheck::parse_gain(&decode_text(encoding, text));
+
+            match decode_text(encoding, description)
+                .to_ascii_uppercase()
+                .as_str()
+            {
+                "REPLAYGAIN_TRACK_GAIN" => tags.track_gain = gain,
+                "REPLAYGAIN_ALBUM_GAIN" => tags.album_gain = gain,
+                _ => {}
+            }
+            continue;
+        }
+
+        if id[0] != b'T' {
+            continue;
+        }
+
+        let text = decode_text(encoding, text);
+        if text.is_empty() {
+            continue;
+        }
+
+        match id {
+            b"TIT2" => tags.title = Some(text),
+            b"TPE1" => tags.artist = Some(text),
+            b"TALB" => tags.album = Some(text),
+            b"TPE2" => tags.album_artist = Some(text),
+            b"TCON" => tags.genre = Some(parse_genre(&text)),
+            b"TCOM" => tags.composer = Some(text),
+            b"TIT1" => tags.grouping = Some(text),
+            b"TSOT" => tags.title_sort = Some(text),
+            b"TSOP" => tags.artist_sort = Some(text),
+            b"TSOA" => tags.album_sort = Some(text),
+            b"TSO2" => tags.album_artist_sort = Some(text),
+            b"TSOC" => tags.composer_sort = Some(text),
+            b"TRCK" => (tags.track_number, tags.track_count) = tags::parse_position(&text),
+            b"TPOS" => (tags.disc_number, tags.disc_count) = tags::parse_position(&text),
+            b"TYER" | b"TDRC" => tags.year = tags.year.or(tags::parse_year(&text)),
+            b"TBPM" => tags.bpm = text.parse::<f32>().ok().map(|bpm| bpm.round() as u16),
+            b"TCMP" => tags.compilation = text == "1",
+            _ => {}
+        }
+    }
+
+    tags
+}
+
+/// Parses a 128 byte ID3v1 or ID3v1.1 tag.
+fn parse_id3v1(tag: &[u8; 128]) -> Tags {
+    let text = |range: std::ops::Range<usize>| {
+        Some(decode_text(0, &tag[range])).filter(|text| !text.is_empty())
+    };
+
+    // ID3v1.1 keeps the track number in the last byte of the comment
+    let track_number = match (tag[125], tag[126]) {
+        (0, track) if track != 0 => Some(track as u32),
+        _ => None,
+    };
+
+    Tags {
+        title: text(3..33),
+        artist: text(33..63),
+        album: text(63..93),
+        year: tags::parse_year(&decode_text(0, &tag[93..97])),
+        comment: text(97..125),
+        track_number,
+        genre: tags::id3v1_genre(tag[127] as usize),
+        ..Default::default()
+    }
+}
+
+fn encode_syncsafe(value: u32) -> [u8; 4] {
+    [21, 14, 7, 0].map(|shift| ((value >> shift) & 0x7F) as u8)
+}
+
+/// Length of the ID3v2 tag at the start of the file, 0 without one.
+pub(crate) fn id3v2_len(file: &[u8]) -> usize {
+    match file.get(..10) {
+        Some([b'I', b'D', b'3', _, _, flags, size @ ..]) => {
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            10 + syncsafe(size) as usize + footer
+        }
+        _ => 0,
+    }
+}
+
+/// An ID3v2.4 tag holding `tags` as UTF-8 text frames.
+pub(crate) fn write_id3v2(tags: &Tags) -> Vec<u8> {
+    let position = |number: Option<u32>, count: Option<u32>| match (number, count) {
+        (Some(number), Some(count)) => Some(format!("{number}/{count}")),
+        (Some(number), None) => Some(number.to_string()),
+        _ => None,
+    };
+
+    let frames = [
+        (b"TIT2", tags.title.clone()),
+        (b"TPE1", tags.artist.clone()),
+        (b"TALB", tags.album.clone()),
+        (b"TPE2", tags.album_artist.clone()),
+        (b"TCON", tags.genre.clone()),
+        (b"TCOM", tags.composer.clone()),
+        (b"TIT1", tags.grouping.clone()),
+        (b"TSOT", tags.title_sort.clone()),
+        (b"TSOP", tags.artist_sort.clone()),
+        (b"TSOA", tags.album_sort.clone()),
+        (b"TSO2", tags.album_artist_sort.clone()),
+        (b"TSOC", tags.composer_sort.clone()),
+        (b"TRCK", position(tags.track_number, tags.track_count)),
+        (b"TPOS", position(tags.disc_number, tags.disc_count)),
+        (b"TDRC", tags.year.map(|year| year.to_string())),
+        (b"TBPM", tags.bpm.map(|bpm| bpm.to_string())),
+        (b"TCMP", tags.compilation.then(|| "1".to_string())),
+    ];
+
+    let mut body = Vec::new();
+    for (id, text) in frames {
+        let Some(text) = text.filter(|text| !text.is_empty()) else {
+            continue;
+        };
+
+        body.extend(id);
+        body.extend(encode_syncsafe(text.len() as u32 + 1));
+        body.extend([0, 0, 3]); // no flags, UTF-8
+        body.extend(text.as_bytes());
+    }
+    if let Some(comment) = tags.comment.as_ref().filter(|comment| !comment.is_empty()) {
+        body.extend(b"COMM");
+        body.extend(encode_syncsafe(comment.len() as u32 + 5));
+        body.extend([0, 0, 3]);
+        body.extend(b"eng\0");
+        body.extend(comment.as_bytes());
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend(encode_syncsafe(body.len() as u32));
+    tag.extend(body);
+    tag
+}
+
+/// An MPEG audio frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameHeader {
+    mpeg1: bool,
+    layer: u8,
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    padding: u32,
+    mono: bool,
+}
+
+#[rustfmt::skip]
+const BITRATES: [[u32; 15]; 5] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448], // MPEG1 layer 1
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],    // MPEG1 layer 2
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],     // MPEG1 layer 3
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],    // MPEG2 layer 1
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],         // MPEG2 layer 2 and 3
+];
+
+impl FrameHeader {
+    fn parse(header: [u8; 4]) -> Option<Self> {
+        let header = u32::from_be_bytes(header);
+        if header >> 21 != 0x7FF {
+            return None;
+        }
+
+        let version = (header >> 19) & 0x3; // 0 MPEG2.5, 2 MPEG2, 3 MPEG1
+        let layer = match (header >> 17) & 0x3 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return None,
+        };
+        let bitrate_index = ((header >> 12) & 0xF) as usize;
+        let sample_rate_index = ((header >> 10) & 0x3) as usize;
+
+        if version == 1 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+
+        let mpeg1 = version == 3;
+        let table = match (mpeg1, layer) {
+            (true, layer) => layer as usize - 1,
+            (false, 1) => 3,
+            (false, _) => 4,
+        };
+        let sample_rate = [44100, 48000, 32000][sample_rate_index]
+            >> match version {
+                3 => 0,
+                2 => 1,
+                _ => 2,
+            };
+
+        Some(FrameHeader {
+            mpeg1,
+            layer,
+            bitrate_kbps: BITRATES[table][bitrate_index],
+            sample_rate,
+            padding: (header >> 9) & 0x1,
+            mono: (header >> 6) & 0x3 == 3,
+        })
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.mpeg1) {
+            (1, _) => 384,
+            (3, false) => 576,
+            _ => 1152,
+        }
+    }
+
+    fn frame_len(&self) -> u32 {
+        match self.layer {
+            1 => (12 * self.bitrate_kbps * 1000 / self.sample_rate + self.padding) * 4,
+            _ => {
+                self.samples_per_frame() / 8 * self.bitrate_kbps * 1000 / self.sample_rate
+                    + self.padding
+            }
+        }
+    }
+
+    /// Offset of a Xing/Info header in the first frame, after the side information.
+    fn xing_offset(&self) -> usize {
+        4 + match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+}
+
+/// Decoders output this many samples of their own before the first encoded one.
+const DECODER_DELAY: u32 = 529;
+
+/// What the Xing/Info or VBRI header in the first frame says about the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VbrHeader {
+    frames: u32,
+    bytes: Option<u32>,
+    vbr: bool,
+    delay_padding: Option<(u32, u32)>, // encoder delay and padding in samples, from the LAME tag
+}
+
+fn vbr_header(frame: &[u8], header: &FrameHeader) -> Option<VbrHeader> {
+    let be_u32 = |at: usize| {
+        frame
+            .get(at..at + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+
+    let xing = header.xing_offset();
+    if let Some(tag @ (b"Xing" | b"Info")) = frame.get(xing..xing + 4) {
+        // Without a frame count the stream is treated as CBR
+        let flags = be_u32(xing + 4)?;
+        (flags & 0x1 != 0).then_some(())?;
+
+        let frames = be_u32(xing + 8)?;
+        let bytes = match flags & 0x2 {
+            0 => None,
+            _ => be_u32(xing + 12),
+        };
+
+        // Frames, bytes, table of contents and quality are each optional
+        let lame = xing
+            + 8
+            + [(0x1, 4), (0x2, 4), (0x4, 100), (0x8, 4)]
+                .iter()
+                .filter(|(flag, _)| flags & flag != 0)
+                .map(|(_, len)| len)
+                .sum::<usize>();
+
+        // ffmpeg writes the same layout under its own name
+        let delay_padding = match (frame.get(lame..lame + 4), frame.get(lame + 21..lame + 24)) {
+            (Some(b"LAME" | b"Lavc" | b"Lavf"), Some(&[a, b, c])) => Some((
+                (a as u32) << 4 | (b as u32) >> 4,
+                (b as u32 & 0xF) << 8 | c as u32,
+            )),
+            _ => None,
+        };
+
+        return Some(VbrHeader {
+            frames,
+            bytes,
+            vbr: tag == b"Xing",
+            delay_padding,
+        });
+    }
+
+    match frame.get(36..40) {
+        Some(b"VBRI") => Some(VbrHeader {
+            frames: be_u32(50)?,
+            bytes: be_u32(46),
+            vbr: true,
+            delay_padding: None,
+        }),
+        _ => None,
+    }
+}
+
+/// Offsets of the frames in `audio`, which starts with one, up to the first bad header.
+fn frame_offsets(audio: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut at = 0;
+
+    while let Some(header) = audio
+        .get(at..at + 4)
+        .and_then(|header| FrameHeader::parse(header.try_into().unwrap()))
+    {
+        offsets.push(at);
+        at += header.frame_len() as usize;
+    }
+
+    offsets
+}
+
+/// Finds the first frame in `audio`, requiring the frame after it to line up
+/// so stray sync bytes aren't mistaken for one.
+fn find_frame(audio: &[u8]) -> Option<(usize, FrameHeader)> {
+    (0..audio.len().saturating_sub(4)).find_map(|at| {
+        let header = FrameHeader::parse(audio[at..at + 4].try_into().unwrap())?;
+        let next = at + header.frame_len() as usize;
+
+        match audio.get(next..next + 4) {
+            Some(next_header) => {
+                FrameHeader::parse(next_header.try_into().unwrap()).map(|_| (at, header))
+            }
+            None => Some((at, header)),
+        }
+    })
+}
+
+/// Bytes from the first frame at `first` in `head`, the Xing one, to the 8th frame from
+/// the end. `head` is the audio from `audio_start` on, when it doesn't reach the end
+/// the last frames are looked for in the end of the file.
+fn gapless_data<R: Read + Seek>(
+    reader: &mut R,
+    head: &[u8],
+    audio_start: u64,
+    first: usize,
+    audio_end: u64,
+) -> io::Result<Option<u32>> {
+    let tail;
+    let (frames_start, frames) = if audio_start + head.len() as u64 >= audio_end {
+        (audio_start + first as u64, &head[first..])
+    } else {
+        let tail_start = audio_end - SCAN_LEN;
+        tail = read_range(reader, tail_start, audio_end)?;
+        let Some((at, _)) = find_frame(&tail) else {
+            return Ok(None);
+        };
+        (tail_start + at as u64, &tail[at..])
+    };
+
+    let offsets = frame_offsets(frames);
+    let last = frames_start + offsets[offsets.len().saturating_sub(8)] as u64;
+    Ok(Some(last.saturating_sub(audio_start + first as u64) as u32))
+}
+
+/// How much of the audio is scanned for the first frame and, for gapless playback, the
+/// last ones. The frames in between are never read.
+const SCAN_LEN: u64 = 64 * 1024;
+
+fn read_range<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    reader
+        .by_ref()
+        .take(end.saturating_sub(start))
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads the ID3 tags and stream properties of an MP3 file.
+pub(crate) fn read_audio<R: Read + Seek>(
+    reader: &mut R,
+) -> anyhow::Result<(Tags, AudioProperties)> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+
+    let mut tags = Tags::default();
+    let mut audio_start = 0;
+
+    if let [b'I', b'D', b'3', major, _, flags, size @ ..] = &read_range(reader, 0, 10)?[..] {
+        let size = syncsafe(size) as u64;
+        let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+        if 10 + size > file_len {
+            bail!("ID3v2 tag overruns the file");
+        }
+
+        if matches!(major, 3 | 4) {
+            tags = parse_id3v2(*major, *flags, &read_range(reader, 10, 10 + size)?);
+        }
+        audio_start = 10 + size + footer;
+    }
+
+    let mut audio_end = file_len;
+    if let Some(at) = file_len.checked_sub(ID3V1_LEN) {
+        let id3v1 = read_range(reader, at, file_len)?;
+        if id3v1.starts_with(b"TAG") {
+            tags.merge(parse_id3v1(id3v1[..].try_into().unwrap()));
+            audio_end = at;
+        }
+    }
+
+    let audio_start = audio_start.min(audio_end);
+    let head = read_range(reader, audio_start, audio_end.min(audio_start + SCAN_LEN))?;
+    let Some((at, header)) = find_frame(&head) else {
+        bail!("no MPEG audio frame found");
+    };
+
+    let audio_bytes = audio_end - audio_start - at as u64;
+    let vbr_header = vbr_header(&head[at..], &header);
+
+    let (duration_ms, bitrate_kbps, vbr) = match vbr_header {
+        Some(VbrHeader {
+            frames, bytes, vbr, ..
+        }) => {
+            let duration_ms = frames as u64 * header.samples_per_frame() as u64 * 1000
+                / header.sample_rate as u64;
+            let bytes = bytes.map_or(audio_bytes, u64::from);
+            let bitrate = match duration_ms {
+                0 => header.bitrate_kbps as u64,
+                duration_ms => bytes * 8 / duration_ms,
+            };
+            (duration_ms, bitrate, vbr)
+        }
+        None => (
+            audio_bytes * 8 / header.bitrate_kbps as u64,
+            header.bitrate_kbps as u64,
+            false,
+        ),
+    };
+
+    let gapless = match vbr_header {
+        Some(VbrHeader {
+            frames,
+            delay_padding: Some((delay, padding)),
+            ..
+        }) => {
+            gapless_data(reader, &head, audio_start, at, audio_end)?.map(|gapless_data| {
+                // The device wants the delays as the decoder sees them
+                let total = frames as u64 * header.samples_per_frame() as u64;
+                Gapless {
+                    pregap: delay + DECODER_DELAY,
+                    postgap: padding.saturating_sub(DECODER_DELAY),
+                    sample_count: total.saturating_sub((delay + padding) as u64),
+                    gapless_data,
+                }
+            })
+        }
+        _ => None,
+    };
+
+    Ok((
+        tags,
+        AudioProperties {
+            codec: Codec::Mp3,
+            bitrate_kbps: bitrate_kbps as u32,
+            sample_rate: header.sample_rate,
+            duration_ms: duration_ms as u32,
+            vbr,
+            gapless,
+        },
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use crate::media::tags::Codec;
+
+    /// MPEG1 layer 3, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x40];
+    const FRAME_LEN: usize = 417;
+
+    fn frame(header: [u8; 4], len: usize) -> Vec<u8> {
+        let mut frame = header.to_vec();
+        frame.resize(len, 0);
+        frame
+    }
+
+    fn text_frame(major: u8, id: &[u8; 4], encoding: u8, text: &[u8]) -> Vec<u8> {
+        let size = text.len() as u32 + 1;
+        let size = match major {
+            4 => (size & 0x7F) | ((size << 1) & 0x7F00) | ((size << 2) & 0x7F_0000),
+            _ => size,
+        };
+
+        let mut frame = id.to_vec();
+        frame.extend(size.to_be_bytes());
+        frame.extend([0, 0, encoding]);
+        frame.extend(text);
+        frame
+    }
+
+    fn id3v2(major: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = frames.concat();
+        let size = body.len() as u32;
+
+        let mut tag = vec![b'I', b'D', b'3', major, 0, 0];
+        tag.extend([
+            (size >> 21) as u8 & 0x7F,
+            (size >> 14) as u8 & 0x7F,
+            (size >> 7) as u8 & 0x7F,
+            size as u8 & 0x7F,
+        ]);
+        tag.extend(body);
+        tag
+    }
+
+    fn id3v1(title: &str, artist: &str, track: u8, genre: u8) -> Vec<u8> {
+        let mut tag = vec![0u8; 128];
+        tag[..3].copy_from_slice(b"TAG");
+        tag[3..3 + title.len()].copy_from_slice(title.as_bytes());
+        tag[33..33 + artist.len()].copy_from_slice(artist.as_bytes());
+        tag[93..97].copy_from_slice(b"1999");
+        tag[126] = track;
+        tag[127] = genre;
+        tag
+    }
+
+    /// A CBR MP3 of `frames` silent frames with the given tags around it.
+    pub(crate) fn mp3(id3v2: &[u8], frames: usize, id3v1: &[u8]) -> Vec<u8> {
+        let mut file = id3v2.to_vec();
+        for _ in 0..frames {
+            file.extend(frame(FRAME_HEADER, FRAME_LEN));
+        }
+        file.extend(id3v1);
+        file
+    }
+
+    pub(crate) fn tagged_mp3() -> Vec<u8> {
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("Motörhead".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+
+        let tag = id3v2(
+            3,
+            &[
+                text_frame(3, b"TIT2", 0, b"Ace of Spades"),
+                text_frame(3, b"TPE1", 1, &utf16),
+                text_frame(3, b"TRCK", 0, b"1/12"),
+                text_frame(3, b"TPOS", 0, b"1/1"),
+                text_frame(3, b"TYER", 0, b"1980"),
+                text_frame(3, b"TCON", 0, b"(17)"),
+                text_frame(3, b"TBPM", 0, b"141"),
+                text_frame(3, b"TCMP", 0, b"1"),
+                text_frame(3, b"COMM", 0, b"engiTunNORM\0 0000044E 00000512"),
+                text_frame(3, b"TXXX", 0, b"replaygain_track_gain\0-6.00 dB"),
+                text_frame(3, b"COMM", 0, b"eng\0Loud"),
+            ],
+        );
+
+        mp3(&tag, 100, &id3v1("", "", 0, 0))
+    }
+
+    #[test]
+    fn read_id3v2_3() {
+        let (tags, properties) = super::read_audio(&mut Cursor::new(tagged_mp3())).unwrap();
+
+        assert_eq!(tags.title.as_deref(), Some("Ace of Spades"));
+        assert_eq!(tags.artist.as_deref(), Some("Motörhead"));
+        assert_eq!((tags.track_number, tags.track_count), (Some(1), Some(12)));
+        assert_eq!((tags.disc_number, tags.disc_count), (Some(1), Some(1)));
+        assert_eq!(tags.year, Some(1980));
+        assert_eq!(tags.genre.as_deref(), Some("Rock"));
+        assert_eq!(tags.bpm, Some(141));
+        assert!(tags.compilation);
+        assert_eq!(tags.comment.as_deref(), Some("Loud"));
+        assert_eq!(tags.itunnorm, Some(0x512));
+        assert_eq!(tags.track_gain, Some(-6.0));
+
+        assert_eq!(properties.codec, Codec::Mp3);
+        assert_eq!(properties.bitrate_kbps, 128);
+        assert_eq!(properties.sample_rate, 44100);
+        assert!(!properties.vbr);
+        // 41700 bytes at 128kbps
+        assert_eq!(properties.duration_ms, 2606);
+    }
+
+    #[test]
+    fn read_id3v2_4_with_id3v1_fallback() {
+        let tag = id3v2(
+            4,
+            &[
+                text_frame(4, b"TIT2", 3, "Ünïcode".as_bytes()),
+                text_frame(4, b"TDRC", 3, b"2004-03-01"),
+                text_frame(4, b"TSOP", 3, b"Beatles, The"),
+                text_frame(4, b"TPE2", 3, b"The Beatles\0Someone Else"),
+            ],
+        );
+        let file = mp3(&tag, 10, &id3v1("Ignored", "The Beatles", 7, 13));
+
+        let (tags, _) = super::read_audio(&mut Cursor::new(file)).unwrap();
+
+        assert_eq!(tags.title.as_deref(), Some("Ünïcode"));
+        assert_eq!(tags.year, Some(2004));
+        assert_eq!(tags.artist_sort.as_deref(), Some("Beatles, The"));
+        assert_eq!(tags.album_artist.as_deref(), Some("The Beatles"));
+        assert_eq!(tags.artist.as_deref(), Some("The Beatles"));
+        assert_eq!(tags.track_number, Some(7));
+        assert_eq!(tags.genre.as_deref(), Some("Pop"));
+    }
+
+    #[test]
+    fn read_xing_vbr() {
+        let mut first = frame(FRAME_HEADER, FRAME_LEN);
+        first[36..40].copy_from_slice(b"Xing");
+        first[40..44].copy_from_slice(&3u32.to_be_bytes());
+        first[44..48].copy_from_slice(&1000u32.to_be_bytes());
+        first[48..52].copy_from_slice(&417_000u32.to_be_bytes());
+
+        let mut file = first;
+        file.extend(mp3(&[], 2, &[]));
+
+        let (_, properties) = super::read_audio(&mut Cursor::new(file)).unwrap();
+
+        assert!(properties.vbr);
+        assert_eq!(properties.duration_ms, 26122);
+        assert_eq!(properties.bitrate_kbps, 127);
+    }
+
+    /// A LAME encoded CBR MP3 of `frames` frames after the Info one, with 576 samples
+    /// of delay and 1000 of padding.
+    pub(crate) fn lame_mp3(frames: u32) -> Vec<u8> {
+        // Info frame with frame count, byte count and the LAME tag after them
+        let mut file = frame(FRAME_HEADER, FRAME_LEN);
+        file[36..40].copy_from_slice(b"Info");
+        file[40..44].copy_from_slice(&3u32.to_be_bytes());
+        file[44..48].copy_from_slice(&frames.to_be_bytes());
+        file[48..52].copy_from_slice(&(frames * FRAME_LEN as u32).to_be_bytes());
+        file[52..61].copy_from_slice(b"LAME3.100");
+        file[73..76].copy_from_slice(&[0x24, 0x03, 0xE8]);
+
+        file.extend(mp3(&[], frames as usize, &[]));
+        file
+    }
+
+    #[test]
+    fn read_lame_gapless() {
+        let file = lame_mp3(20);
+
+        let (_, properties) = super::read_audio(&mut Cursor::new(file)).unwrap();
+        let gapless = properties.gapless.unwrap();
+
+        assert!(!properties.vbr);
+        assert_eq!(gapless.pregap, 576 + 529);
+        assert_eq!(gapless.postgap, 1000 - 529);
+        assert_eq!(gapless.sample_count, 20 * 1152 - 576 - 1000);
+        // The Info frame and 12 audio frames come before the 8th from the end
+        assert_eq!(gapless.gapless_data, 13 * FRAME_LEN as u32);
+    }
+
+    /// Counts the bytes read through it.
+    struct CountingReader<R> {
+        inner: R,
+        read: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.read += read;
+            Ok(read)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn read_only_the_ends() {
+        // About 4MB, most of which is never read
+        let file = lame_mp3(10_000);
+        let mut reader = CountingReader {
+            inner: Cursor::new(&file),
+            read: 0,
+        };
+
+        let (_, properties) = super::read_audio(&mut reader).unwrap();
+
+        assert!(reader.read <= 2 * super::SCAN_LEN as usize + 256);
+        assert_eq!(properties.duration_ms, 261224);
+        let gapless = properties.gapless.unwrap();
+        assert_eq!(gapless.sample_count, 10_000 * 1152 - 576 - 1000);
+        assert_eq!(gapless.gapless_data, 9_993 * FRAME_LEN as u32);
+    }
+
+    #[test]
+    fn write_id3v2_round_trip() {
+        let (tags, _) = super::read_audio(&mut Cursor::new(tagged_mp3())).unwrap();
+
+        let file = mp3(&super::write_id3v2(&tags), 10, &[]);
+        let (written, properties) = super::read_audio(&mut Cursor::new(&file)).unwrap();
+
+        assert_eq!(written.title, tags.title);
+        assert_eq!(written.artist, tags.artist);
+        assert_eq!(
+            (written.track_number, written.track_count),
+            (Some(1), Some(12))
+        );
+        assert_eq!(written.year, Some(1980));
+        assert_eq!(written.genre.as_deref(), Some("Rock"));
+        assert_eq!(written.comment.as_deref(), Some("Loud"));
+        assert!(written.compilation);
+        assert_eq!(properties.codec, Codec::Mp3);
+        assert_eq!(file.len() - super::id3v2_len(&file), 10 * FRAME_LEN);
+    }
+}
@@ -0,0 +1,724 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::{bail, ensure};
+
+use super::{
+    soundcheck,
+    tags::{self, AudioProperties, Codec, Gapless, Tags},
+};
+
+/// Atoms that only contain other atoms.
+const CONTAINERS: [&[u8; 4]; 10] = [
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"edts", b"dinf", b"tref", b"ilst",
+];
+
+/// An atom in an MP4 file. `offset` and `size` describe its body, after the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Atom {
+    pub kind: [u8; 4],
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl Atom {
+    /// Offset of the first child. `meta` has a version and flags word before its children.
+    fn children_offset(&self) -> u64 {
+        match &self.kind {
+            b"meta" => self.offset + 4,
+            _ => self.offset,
+        }
+    }
+
+    pub fn is_container(&self) -> bool {
+        CONTAINERS.contains(&&self.kind) || &self.kind == b"meta"
+    }
+}
+
+/// Reads the atom headers between `start` and `end`.
+pub(crate) fn atoms<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+) -> anyhow::Result<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut offset = start;
+
+    while offset + 8 <= end {
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+
+        let size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+
+        let (header_len, total) = match size {
+            0 => (8, end - offset),
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                (16, u64::from_be_bytes(large))
+            }
+            size => (8, size),
+        };
+
+        ensure!(
+            total >= header_len && offset + total <= end,
+            "atom {} at {offset} overruns its parent",
+            String::from_utf8_lossy(&kind)
+        );
+
+        atoms.push(Atom {
+            kind,
+            offset: offset + header_len,
+            size: total - header_len,
+        });
+        offset += total;
+    }
+
+    Ok(atoms)
+}
+
+/// Top level atoms of the file.
+pub(crate) fn root<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Vec<Atom>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    atoms(reader, 0, end)
+}
+
+pub(crate) fn children<R: Read + Seek>(reader: &mut R, atom: &Atom) -> anyhow::Result<Vec<Atom>> {
+    if !atom.is_container() {
+        bail!(
+            "{} is not a container atom",
+            String::from_utf8_lossy(&atom.kind)
+        );
+    }
+    atoms(reader, atom.children_offset(), atom.offset + atom.size)
+}
+
+/// Follows `path` down from the top level, taking the first match at every level.
+pub(crate) fn find<R: Read + Seek>(
+    reader: &mut R,
+    path: &[&[u8; 4]],
+) -> anyhow::Result<Option<Atom>> {
+    let mut level = root(reader)?;
+    let mut found = None;
+
+    for (depth, kind) in path.iter().enumerate() {
+        let Some(atom) = level.iter().find(|atom| &atom.kind == *kind).copied() else {
+            return Ok(None);
+        };
+
+        if depth + 1 < path.len() {
+            level = children(reader, &atom)?;
+        }
+        found = Some(atom);
+    }
+
+    Ok(found)
+}
+
+pub(crate) fn child<R: Read + Seek>(
+    reader: &mut R,
+    atom: &Atom,
+    kind: &[u8; 4],
+) -> anyhow::Result<Option<Atom>> {
+    Ok(children(reader, atom)?
+        .into_iter()
+        .find(|child| &child.kind == kind))
+}
+
+pub(crate) fn read_body<R: Read + Seek>(reader: &mut R, atom: &Atom) -> anyhow::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(atom.offset))?;
+
+    let mut body = vec![0u8; atom.size as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+pub(crate) fn be_u16(bytes: &[u8], at: usize) -> anyhow::Result<u16> {
+    match bytes.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes(bytes.try_into().unwrap())),
+        None => bail!("atom body too short"),
+    }
+}
+
+pub(crate) fn be_u32(bytes: &[u8], at: usize) -> anyhow::Result<u32> {
+    match bytes.get(at..at + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes(bytes.try_into().unwrap())),
+        None => bail!("atom body too short"),
+    }
+}
+
+pub(crate) fn be_u64(bytes: &[u8], at: usize) -> anyhow::Result<u64> {
+    match bytes.get(at..at + 8) {
+        Some(bytes) => Ok(u64::from_be_bytes(bytes.try_into().unwrap())),
+        None => bail!("atom body too short"),
+    }
+}
+
+/// Chapter start times in milliseconds and titles. Uses the QuickTime chapter track if
+/// the file has one, otherwise the Nero `chpl` atom. Empty if the file has neither.
+pub(crate) fn read_chapters<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Vec<(u32, String)>> {
+    let Some(moov) = find(reader, &[b"moov"])? else {
+        bail!("no moov atom");
+    };
+
+    let chapters = read_chapter_track(reader, &moov)?;
+    if !chapters.is_empty() {
+        return Ok(chapters);
+    }
+
+    match find(reader, &[b"moov", b"udta", b"chpl"])? {
+        Some(chpl) => read_chpl(&read_body(reader, &chpl)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn track_id<R: Read + Seek>(reader: &mut R, trak: &Atom) -> anyhow::Result<Option<u32>> {
+    let Some(tkhd) = child(reader, trak, b"tkhd")? else {
+        return Ok(None);
+    };
+    let body = read_body(reader, &tkhd)?;
+
+    Ok(Some(match body.first() {
+        Some(1) => be_u32(&body, 20)?,
+        _ => be_u32(&body, 12)?,
+    }))
+}
+
+fn read_chapter_track<R: Read + Seek>(
+    reader: &mut R,
+    moov: &Atom,
+) -> anyhow::Result<Vec<(u32, String)>> {
+    let traks: Vec<Atom> = children(reader, moov)?
+        .into_iter()
+        .filter(|atom| &atom.kind == b"trak")
+        .collect();
+
+    let mut chapter_ids = Vec::new();
+
+    for trak in &traks {
+        let Some(tref) = child(reader, trak, b"tref")? else {
+            continue;
+        };
+        if let Some(chap) = child(reader, &tref, b"chap")? {
+            let body = read_body(reader, &chap)?;
+            for at in (0..body.len() / 4).map(|i| i * 4) {
+                chapter_ids.push(be_u32(&body, at)?);
+            }
+        }
+    }
+
+    for trak in &traks {
+        match track_id(reader, trak)? {
+            Some(id) if chapter_ids.contains(&id) => return read_text_samples(reader, trak),
+            _ => {}
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// Reads every sample of a text track with its start time.
+fn read_text_samples<R: Read + Seek>(
+    reader: &mut R,
+    trak: &Atom,
+) -> anyhow::Result<Vec<(u32, String)>> {
+    let Some(mdia) = child(reader, trak, b"mdia")? else {
+        bail!("chapter track has no mdia atom");
+    };
+    let Some(mdhd) = child(reader, &mdia, b"mdhd")? else {
+        bail!("chapter track has no mdhd atom");
+    };
+    let mdhd = read_body(reader, &mdhd)?;
+    let timescale = match mdhd.first() {
+        Some(1) => be_u32(&mdhd, 20)?,
+        _ => be_u32(&mdhd, 12)?,
+    } as u64;
+    ensure!(timescale != 0, "chapter track has a zero timescale");
+
+    let Some(minf) = child(reader, &mdia, b"minf")? else {
+        bail!("chapter track has no minf atom");
+    };
+    let Some(stbl) = child(reader, &minf, b"stbl")? else {
+        bail!("chapter track has no stbl atom");
+    };
+
+    let table = |reader: &mut R, kind: &[u8; 4]| -> anyhow::Result<Option<Vec<u8>>> {
+        match child(reader, &stbl, kind)? {
+            Some(atom) => Ok(Some(read_body(reader, &atom)?)),
+            None => Ok(None),
+        }
+    };
+
+    // Sample durations
+    let stts = table(reader, b"stts")?.unwrap_or_default();
+    let mut starts = Vec::new();
+    let mut time = 0u64;
+    for entry in 0..be_u32(&stts, 4).unwrap_or(0) as usize {
+        let count = be_u32(&stts, 8 + entry * 8)?;
+        let delta = be_u32(&stts, 12 + entry * 8)? as u64;
+        for _ in 0..count {
+            starts.push(time * 1000 / timescale);
+            time += delta;
+        }
+    }
+
+    // Sample sizes
+    let stsz = table(reader, b"stsz")?.unwrap_or_default();
+    let uniform_size = be_u32(&stsz, 4).unwrap_or(0);
+    let sample_count = be_u32(&stsz, 8).unwrap_or(0) as usize;
+    let sizes: Vec<u32> = (0..sample_count)
+        .map(|i| match uniform_size {
+            0 => be_u32(&stsz, 12 + i * 4),
+            size => Ok(size),
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    // Chunk offsets
+    let chunk_offsets: Vec<u64> = match (table(reader, b"stco")?, table(reader, b"co64")?) {
+        (Some(stco), _) => (0..be_u32(&stco, 4)? as usize)
+            .map(|i| be_u32(&stco, 8 + i * 4).map(u64::from))
+            .collect::<anyhow::Result<_>>()?,
+        (None, Some(co64)) => (0..be_u32(&co64, 4)? as usize)
+            .map(|i| be_u64(&co64, 8 + i * 8))
+            .collect::<anyhow::Result<_>>()?,
+        (None, None) => bail!("chapter track has no chunk offsets"),
+    };
+
+    // Samples per chunk, as (first chunk, samples per chunk) runs
+    let stsc = table(reader, b"stsc")?.unwrap_or_default();
+    let runs: Vec<(u32, u32)> = (0..be_u32(&stsc, 4).unwrap_or(0) as usize)
+        .map(|i| Ok((be_u32(&stsc, 8 + i * 12)?, be_u32(&stsc, 12 + i * 12)?)))
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut chapters = Vec::new();
+    let mut sample = 0usize;
+
+    for (chunk, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let per_chunk = runs
+            .iter()
+            .rev()
+            .find(|(first, _)| *first as usize <= chunk + 1)
+            .map_or(1, |(_, per_chunk)| *per_chunk);
+
+        let mut offset = *chunk_offset;
+
+        for _ in 0..per_chunk {
+            let (Some(size), Some(start)) = (sizes.get(sample), starts.get(sample)) else {
+                break;
+            };
+
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut text = vec![0u8; *size as usize];
+            reader.read_exact(&mut text)?;
+
+            chapters.push((*start as u32, decode_text_sample(&text)?));
+
+            offset += *size as u64;
+            sample += 1;
+        }
+    }
+
+    Ok(chapters)
+}
+
+/// Text samples are a big endian length followed by UTF-8, or UTF-16 with a byte order mark.
+fn decode_text_sample(sample: &[u8]) -> anyhow::Result<String> {
+    let len = be_u16(sample, 0)? as usize;
+    let Some(text) = sample.get(2..2 + len) else {
+        bail!("text sample shorter than its length");
+    };
+
+    Ok(match text {
+        [0xFE, 0xFF, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        text => String::from_utf8_lossy(text).into_owned(),
+    })
+}
+
+/// Nero chapter list: start times in 100ns units and length prefixed UTF-8 titles.
+fn read_chpl(body: &[u8]) -> anyhow::Result<Vec<(u32, String)>> {
+    let mut at = match body.first() {
+        Some(0) => 4,
+        Some(_) => 8,
+        None => bail!("empty chpl atom"),
+    };
+
+    let Some(count) = body.get(at) else {
+        bail!("chpl atom too short");
+    };
+    at += 1;
+
+    let mut chapters = Vec::new();
+
+    for _ in 0..*count {
+        let start = be_u64(body, at)?;
+        let Some(len) = body.get(at + 8).map(|len| *len as usize) else {
+            bail!("chpl atom too short");
+        };
+        let Some(title) = body.get(at + 9..at + 9 + len) else {
+            bail!("chpl atom too short");
+        };
+
+        chapters.push((
+            (start / 10_000) as u32,
+            String::from_utf8_lossy(title).into_owned(),
+        ));
+        at += 9 + len;
+    }
+
+    Ok(chapters)
+}
+
+/// Body of the first `kind` atom inside an ilst item.
+fn item_child<'a>(item: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut at = 0;
+
+    while at + 8 <= item.len() {
+        let len = u32::from_be_bytes(item[at..at + 4].try_into().unwrap()) as usize;
+        if len < 8 || at + len > item.len() {
+            return None;
+        }
+        if &item[at + 4..at + 8] == kind {
+            return item.get(at + 8..at + len);
+        }
+        at += len;
+    }
+
+    None
+}
+
+/// Value of the `data` atom inside an ilst item, after its type and locale words.
+fn item_data(item: &[u8]) -> Option<&[u8]> {
+    item_child(item, b"data")?.get(8..)
+}
+
+/// Parses iTunSMPB: hex words of which the second and third are the encoder delay
+/// and padding, and the fourth the sample count without them.
+fn parse_itunsmpb(text: &str) -> Option<Gapless> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    Some(Gapless {
+        pregap: u32::from_str_radix(words.get(1)?, 16).ok()?,
+        postgap: u32::from_str_radix(words.get(2)?, 16).ok()?,
+        sample_count: u64::from_str_radix(words.get(3)?, 16).ok()?,
+        gapless_data: 0,
+    })
+}
+
+/// Reads the iTunes metadata items of moov/udta/meta/ilst.
+/// iTunSMPB is returned separately, it describes the stream rather than the content.
+fn read_ilst<R: Read + Seek>(reader: &mut R) -> anyhow::Result<(Tags, Option<Gapless>)> {
+    let mut tags = Tags::default();
+    let mut gapless = None;
+
+    let Some(ilst) = find(reader, &[b"moov", b"udta", b"meta", b"ilst"])? else {
+        return Ok((tags, gapless));
+    };
+
+    for item in children(reader, &ilst)? {
+        let body = read_body(reader, &item)?;
+        let Some(value) = item_data(&body) else {
+            continue;
+        };
+
+        let text = || {
+            Some(String::from_utf8_lossy(value).trim().to_string()).filter(|text| !text.is_empty())
+        };
+        // trkn and disk: a reserved u16, the position and the count
+        let position = || {
+            let number = be_u16(value, 2).ok().filter(|n| *n != 0).map(u32::from);
+            let count = be_u16(value, 4).ok().filter(|n| *n != 0).map(u32::from);
+            (number, count)
+        };
+
+        match &item.kind {
+            b"\xA9nam" => tags.title = text(),
+            b"\xA9ART" => tags.artist = text(),
+            b"\xA9alb" => tags.album = text(),
+            b"aART" => tags.album_artist = text(),
+            b"\xA9gen" => tags.genre = text(),
+            b"gnre" => {
+                tags.genre = tags.genre.take().or_else(|| {
+                    let index = be_u16(value, 0).ok()?.checked_sub(1)?;
+                    tags::id3v1_genre(index as usize)
+                })
+            }
+            b"\xA9wrt" => tags.composer = text(),
+            b"\xA9grp" => tags.grouping = text(),
+            b"\xA9cmt" => tags.comment = text(),
+            b"\xA9day" => tags.year = text().and_then(|text| tags::parse_year(&text)),
+            b"sonm" => tags.title_sort = text(),
+            b"soar" => tags.artist_sort = text(),
+            b"soal" => tags.album_sort = text(),
+            b"soaa" => tags.album_artist_sort = text(),
+            b"soco" => tags.composer_sort = text(),
+            b"trkn" => (tags.track_number, tags.track_count) = position(),
+            b"disk" => (tags.disc_number, tags.disc_count) = position(),
+            b"tmpo" => tags.bpm = be_u16(value, 0).ok().filter(|bpm| *bpm != 0),
+            b"cpil" => tags.compilation = value.first().is_some_and(|flag| *flag != 0),
+            b"pgap" => tags.gapless_album = value.first().is_some_and(|flag| *flag != 0),
+            // Freeform items, named by a `name` atom after the version and flags
+            b"----" => {
+                let name = item_child(&body, b"name")
+                    .and_then(|name| name.get(4..))
+                    .map(|name| String::from_utf8_lossy(name).to_ascii_uppercase());
+                let text = text().unwrap_or_default();
+
+                match name.as_deref() {
+                    Some("ITUNSMPB") => gapless = parse_itunsmpb(&text),
+                    Some("ITUNNORM") => tags.itunnorm = soundcheck::parse_itunnorm(&text),
+                    Some("REPLAYGAIN_TRACK_GAIN") => {
+                        tags.track_gain = soundcheck::parse_gain(&text)
+                    }
+                    Some("REPLAYGAIN_ALBUM_GAIN") => {
+                        tags.album_gain = soundcheck::parse_gain(&text)
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok((tags, gapless))
+}
+
+/// Timescale and duration of an mvhd or mdhd body.
+fn timescale_and_duration(body: &[u8]) -> anyhow::Result<(u64, u64)> {
+    Ok(match body.first() {
+        Some(1) => (be_u32(body, 20)? as u64, be_u64(body, 24)?),
+        _ => (be_u32(body, 12)? as u64, be_u32(body, 16)? as u64),
+    })
+}
+
+/// The first track whose handler is `soun`.
+fn audio_track<R: Read + Seek>(reader: &mut R, moov: &Atom) -> anyhow::Result<Option<Atom>> {
+    for trak in children(reader, moov)? {
+        if &trak.kind != b"trak" {
+            continue;
+        }
+        let Some(mdia) = child(reader, &trak, b"mdia")? else {
+            continue;
+        };
+        if let Some(hdlr) = child(reader, &mdia, b"hdlr")? {
+            if read_body(reader, &hdlr)?.get(8..12) == Some(b"soun") {
+                return Ok(Some(trak));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the iTunes tags and audio stream properties of an M4A/M4B file.
+pub(crate) fn read_audio<R: Read + Seek>(
+    reader: &mut R,
+) -> anyhow::Result<(Tags, AudioProperties)> {
+    let Some(moov) = find(reader, &[b"moov"])? else {
+        bail!("no moov atom");
+    };
+    let Some(trak) = audio_track(reader, &moov)? else {
+        bail!("no audio track");
+    };
+
+    let Some(mdia) = child(reader, &trak, b"mdia")? else {
+        bail!("audio track has no mdia atom");
+    };
+    let Some(mdhd) = child(reader, &mdia, b"mdhd")? else {
+        bail!("audio track has no mdhd atom");
+    };
+    let Some(minf) = child(reader, &mdia, b"minf")? else {
+        bail!("audio track has no minf atom");
+    };
+    let Some(stbl) = child(reader, &minf, b"stbl")? else {
+        bail!("audio track has no stbl atom");
+    };
+
+    let (timescale, duration) = timescale_and_duration(&read_body(reader, &mdhd)?)?;
+    ensure!(timescale != 0, "audio track has a zero timescale");
+    let duration_ms = duration * 1000 / timescale;
+
+    // First sample entry: size, format, 6 reserved bytes, data reference index,
+    // 8 bytes of version and vendor, channels, sample size, 4 bytes, 16.16 sample rate
+    let Some(stsd) = child(reader, &stbl, b"stsd")? else {
+        bail!("audio track has no stsd atom");
+    };
+    let stsd = read_body(reader, &stsd)?;
+    let codec = match stsd.get(12..16) {
+        Some(b"mp4a") => Codec::Aac,
+        Some(b"alac") => Codec::Alac,
+        Some(format) => bail!(
+            "unsupported audio format {}",
+            String::from_utf8_lossy(format)
+        ),
+        None => bail!("stsd atom too short"),
+    };
+    let sample_rate = be_u32(&stsd, 40)? >> 16;
+
+    // Total size of the audio samples, for the average bitrate
+    let stsz = match child(reader, &stbl, b"stsz")? {
+        Some(stsz) => read_body(reader, &stsz)?,
+        None => bail!("audio track has no stsz atom"),
+    };
+    let uniform_size = be_u32(&stsz, 4)? as u64;
+    let sample_count = be_u32(&stsz, 8)? as usize;
+    let audio_bytes = match uniform_size {
+        0 => (0..sample_count)
+            .map(|i| be_u32(&stsz, 12 + i * 4).map(u64::from))
+            .sum::<anyhow::Result<u64>>()?,
+        size => size * sample_count as u64,
+    };
+    let bitrate_kbps = match duration_ms {
+        0 => 0,
+        duration_ms => audio_bytes * 8 / duration_ms,
+    };
+
+    let (tags, gapless) = read_ilst(reader)?;
+
+    Ok((
+        tags,
+        AudioProperties {
+            codec,
+            bitrate_kbps: bitrate_kbps as u32,
+            sample_rate,
+            duration_ms: duration_ms as u32,
+            vbr: false,
+            gapless,
+        },
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use crate::media::tags::Codec;
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut atom = (8 + body.len() as u32).to_be_bytes().to_vec();
+        atom.extend(kind);
+        atom.extend(body);
+        atom
+    }
+
+    fn full_atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        atom(kind, &[&[0; 4], body].concat())
+    }
+
+    fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    /// An ilst item holding a `data` atom of the given type.
+    fn item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+        atom(
+            kind,
+            &atom(b"data", &[words(&[data_type, 0]), value.to_vec()].concat()),
+        )
+    }
+
+    /// A freeform item as written by foobar2000 and others.
+    fn freeform(name: &[u8], value: &[u8]) -> Vec<u8> {
+        atom(
+            b"----",
+            &[
+                full_atom(b"mean", b"com.apple.iTunes"),
+                full_atom(b"name", name),
+                atom(b"data", &[words(&[1, 0]), value.to_vec()].concat()),
+            ]
+            .concat(),
+        )
+    }
+
+    /// A three second 44.1kHz M4A with 48000 bytes of samples and a few tags. The
+    /// samples themselves aren't in the file, only their sizes.
+    pub(crate) fn m4a(format: &[u8; 4]) -> Vec<u8> {
+        let mut entry = words(&[36]);
+        entry.extend(format);
+        entry.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        entry.extend([0; 8]);
+        entry.extend([0, 2, 0, 16, 0, 0, 0, 0]);
+        entry.extend(words(&[44100 << 16]));
+
+        let stbl = [
+            full_atom(b"stsd", &[words(&[1]), entry].concat()),
+            full_atom(b"stsz", &words(&[0, 3, 16000, 16000, 16000])),
+        ]
+        .concat();
+
+        let hdlr = full_atom(
+            b"hdlr",
+            &[words(&[0]), b"soun".to_vec(), vec![0; 13]].concat(),
+        );
+        let mdia = [
+            full_atom(b"mdhd", &words(&[0, 0, 44100, 3 * 44100, 0])),
+            hdlr,
+            atom(b"minf", &atom(b"stbl", &stbl)),
+        ]
+        .concat();
+
+        let ilst = [
+            item(b"\xA9nam", 1, "Été".as_bytes()),
+            item(b"\xA9ART", 1, b"Artist"),
+            item(b"aART", 1, b"Album Artist"),
+            item(b"\xA9alb", 1, b"Album"),
+            item(b"gnre", 0, &[0, 18]),
+            item(b"\xA9day", 1, b"2009-05-01T07:00:00Z"),
+            item(b"trkn", 0, &[0, 0, 0, 3, 0, 10, 0, 0]),
+            item(b"disk", 0, &[0, 0, 0, 2, 0, 2]),
+            item(b"tmpo", 21, &[0, 120]),
+            item(b"cpil", 21, &[1]),
+            item(b"soar", 1, b"Artist, The"),
+            item(b"pgap", 21, &[1]),
+            freeform(b"replaygain_album_gain", b"-3.20 dB"),
+            freeform(b"iTunSMPB", b" 00000000 00000840 000001C8 000000000001FBD0"),
+        ]
+        .concat();
+        let meta = full_atom(b"meta", &atom(b"ilst", &ilst));
+
+        let moov = atom(
+            b"moov",
+            &[atom(b"trak", &atom(b"mdia", &mdia)), atom(b"udta", &meta)].concat(),
+        );
+
+        [atom(b"ftyp", b"M4A \0\0\0\0"), moov, atom(b"mdat", &[])].concat()
+    }
+
+    #[test]
+    fn read_audio() {
+        let (tags, properties) = super::read_audio(&mut Cursor::new(m4a(b"mp4a"))).unwrap();
+
+        assert_eq!(tags.title.as_deref(), Some("Été"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album_artist.as_deref(), Some("Album Artist"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.genre.as_deref(), Some("Rock"));
+        assert_eq!(tags.year, Some(2009));
+        assert_eq!((tags.track_number, tags.track_count), (Some(3), Some(10)));
+        assert_eq!((tags.disc_number, tags.disc_count), (Some(2), Some(2)));
+        assert_eq!(tags.bpm, Some(120));
+        assert!(tags.compilation);
+        assert_eq!(tags.artist_sort.as_deref(), Some("Artist, The"));
+
+        assert_eq!(properties.codec, Codec::Aac);
+        assert_eq!(properties.sample_rate, 44100);
+        assert_eq!(properties.duration_ms, 3000);
+        assert_eq!(properties.bitrate_kbps, 128);
+        assert!(tags.gapless_album);
+        assert_eq!(tags.album_gain, Some(-3.2));
+
+        let gapless = properties.gapless.unwrap();
+        assert_eq!((gapless.pregap, gapless.postgap), (2112, 456));
+        assert_eq!(gapless.sample_count, 130_000);
+
+        let (_, properties) = super::read_audio(&mut Cursor::new(m4a(b"alac"))).unwrap();
+        assert_eq!(properties.codec, Codec::Alac);
+    }
+}
@@ -0,0 +1,103 @@
+use super::tags::Tags;
+
+/// Which ReplayGain value becomes the Sound Check adjustment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayGain {
+    #[default]
+    Track,
+    Album,
+}
+
+/// Sound Check value for a gain in dB: `1000 * 10^(-0.1 * dB)`.
+pub fn soundcheck_from_gain(gain_db: f32) -> u32 {
+    (1000.0 * 10f64.powf(-0.1 * gain_db as f64))
+        .round()
+        .clamp(0.0, u32::MAX as f64) as u32
+}
+
+/// Gain in dB for a Sound Check value, the inverse of [`soundcheck_from_gain`].
+pub fn gain_from_soundcheck(soundcheck: u32) -> f32 {
+    match soundcheck {
+        0 => 0.0,
+        soundcheck => (-10.0 * (soundcheck as f64 / 1000.0).log10()) as f32,
+    }
+}
+
+/// ReplayGain values look like "-6.54 dB".
+pub(crate) fn parse_gain(text: &str) -> Option<f32> {
+    let text = text.trim();
+    let number = text
+        .strip_suffix("dB")
+        .or_else(|| text.strip_suffix("db"))
+        .unwrap_or(text);
+
+    number
+        .trim()
+        .parse()
+        .ok()
+        .filter(|gain: &f32| gain.is_finite())
+}
+
+/// iTunNORM is ten hex words, the first two the Sound Check values of the left and
+/// right channel. iTunes uses the louder one.
+pub(crate) fn parse_itunnorm(text: &str) -> Option<u32> {
+    let mut words = text
+        .split_whitespace()
+        .map(|word| u32::from_str_radix(word, 16).ok());
+
+    let left = words.next()??;
+    let right = words.next()??;
+    Some(left.max(right))
+}
+
+/// Sound Check value from the tags: the preferred ReplayGain, the other one, then
+/// iTunNORM.
+pub(crate) fn soundcheck(tags: &Tags, replay_gain: ReplayGain) -> Option<u32> {
+    let gain = match replay_gain {
+        ReplayGain::Track => tags.track_gain.or(tags.album_gain),
+        ReplayGain::Album => tags.album_gain.or(tags.track_gain),
+    };
+
+    gain.map(soundcheck_from_gain).or(tags.itunnorm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayGain;
+    use crate::media::tags::Tags;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(super::soundcheck_from_gain(0.0), 1000);
+        assert_eq!(super::soundcheck_from_gain(-6.0), 3981);
+        assert_eq!(super::soundcheck_from_gain(10.0), 100);
+        assert!((super::gain_from_soundcheck(3981) + 6.0).abs() < 0.01);
+        assert_eq!(super::gain_from_soundcheck(1000), 0.0);
+
+        assert_eq!(super::parse_gain("-6.54 dB"), Some(-6.54));
+        assert_eq!(super::parse_gain("+2.10 dB"), Some(2.1));
+        assert_eq!(super::parse_gain("loud"), None);
+
+        let itunnorm = " 0000044E 00000512 00002D7F 00003E34 00024CA8 00024CA8 00007FFF 00007FFF 00024CA8 00024CA8";
+        assert_eq!(super::parse_itunnorm(itunnorm), Some(0x512));
+    }
+
+    #[test]
+    fn soundcheck_from_tags() {
+        let mut tags = Tags {
+            track_gain: Some(-6.0),
+            album_gain: Some(0.0),
+            itunnorm: Some(2000),
+            ..Default::default()
+        };
+
+        assert_eq!(super::soundcheck(&tags, ReplayGain::Track), Some(3981));
+        assert_eq!(super::soundcheck(&tags, ReplayGain::Album), Some(1000));
+
+        tags.album_gain = None;
+        assert_eq!(super::soundcheck(&tags, ReplayGain::Album), Some(3981));
+
+        tags.track_gain = None;
+        assert_eq!(super::soundcheck(&tags, ReplayGain::Album), Some(2000));
+    }
+}
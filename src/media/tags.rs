@@ -0,0 +1,246 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::bail;
+
+use super::{mp3, mp4};
+
+/// Metadata read from the tags of an audio file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub grouping: Option<String>,
+    pub comment: Option<String>,
+    pub title_sort: Option<String>,
+    pub artist_sort: Option<String>,
+    pub album_sort: Option<String>,
+    pub album_artist_sort: Option<String>,
+    pub composer_sort: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_count: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_count: Option<u32>,
+    pub year: Option<u32>,
+    pub bpm: Option<u16>,
+    pub compilation: bool,
+    pub gapless_album: bool,
+    pub track_gain: Option<f32>, // ReplayGain in dB
+    pub album_gain: Option<f32>,
+    pub itunnorm: Option<u32>, // Sound Check value iTunes computed
+}
+
+impl Tags {
+    /// Fills the fields missing here from `other`, used to fall back to ID3v1.
+    pub fn merge(&mut self, other: Tags) {
+        macro_rules! fill {
+            ($($field:ident),*) => {
+                $(if self.$field.is_none() {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+
+        fill!(
+            title,
+            artist,
+            album,
+            album_artist,
+            genre,
+            composer,
+            grouping,
+            comment,
+            title_sort,
+            artist_sort,
+            album_sort,
+            album_artist_sort,
+            composer_sort,
+            track_number,
+            track_count,
+            disc_number,
+            disc_count,
+            year,
+            bpm,
+            track_gain,
+            album_gain,
+            itunnorm
+        );
+        self.compilation |= other.compilation;
+        self.gapless_album |= other.gapless_album;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Codec {
+    Mp3,
+    Aac,
+    Alac,
+}
+
+/// Samples the encoder added around the audio, for gapless playback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Gapless {
+    pub pregap: u32,
+    pub postgap: u32,
+    pub sample_count: u64, // samples of actual audio, without the gaps
+    pub gapless_data: u32, // MP3 only, see mp3::read_audio
+}
+
+/// Stream properties of an audio file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AudioProperties {
+    pub codec: Codec,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub duration_ms: u32,
+    pub vbr: bool,
+    pub gapless: Option<Gapless>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AudioFile {
+    pub tags: Tags,
+    pub properties: AudioProperties,
+    pub file_size: u64,
+}
+
+/// Reads the tags and stream properties of an MP3 or MP4 (AAC or ALAC) file.
+pub(crate) fn read_audio_file(path: impl AsRef<Path>) -> anyhow::Result<AudioFile> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file_size = reader.seek(SeekFrom::End(0))?;
+
+    let mut magic = [0u8; 8];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut magic)?;
+
+    let (tags, properties) = match magic {
+        [_, _, _, _, b'f', b't', b'y', b'p'] => mp4::read_audio(&mut reader)?,
+        [b'I', b'D', b'3', ..] | [0xFF, 0xE0..=0xFF, ..] => mp3::read_audio(&mut reader)?,
+        _ => bail!("not an MP3 or MP4 file"),
+    };
+
+    Ok(AudioFile {
+        tags,
+        properties,
+        file_size,
+    })
+}
+
+/// "3/12" style position and count, as used by ID3 TRCK and TPOS.
+pub(crate) fn parse_position(text: &str) -> (Option<u32>, Option<u32>) {
+    let mut parts = text.splitn(2, '/');
+    let number = parts.next().and_then(|part| part.trim().parse().ok());
+    let count = parts.next().and_then(|part| part.trim().parse().ok());
+    (number.filter(|n| *n != 0), count.filter(|n| *n != 0))
+}
+
+/// The year at the start of a date like "2004" or "2004-03-01T00:00:00Z".
+pub(crate) fn parse_year(text: &str) -> Option<u32> {
+    text.trim().get(..4)?.parse().ok().filter(|year| *year != 0)
+}
+
+pub(crate) const ID3V1_GENRES: [&str; 80] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "AlternRock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychadelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+pub(crate) fn id3v1_genre(index: usize) -> Option<String> {
+    ID3V1_GENRES.get(index).map(|genre| genre.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_position_and_year() {
+        assert_eq!(super::parse_position("3/12"), (Some(3), Some(12)));
+        assert_eq!(super::parse_position("7"), (Some(7), None));
+        assert_eq!(super::parse_position("0/0"), (None, None));
+        assert_eq!(super::parse_year("2004-03-01T00:00:00Z"), Some(2004));
+        assert_eq!(super::parse_year("04"), None);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::HashingScheme;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Regular, // 1G to 4G with the scroll or click wheel
+    Mini,
+    Photo,
+    Video,
+    Classic,
+    Nano,
+    Shuffle,
+    Touch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    White,
+    Black,
+    Silver,
+    Blue,
+    Pink,
+    Green,
+    Gold,
+    Orange,
+    Purple,
+    Red,
+}
+
+/// What a device is, as far as its serial number or SysInfo tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelInfo {
+    pub model: Model,
+    pub generation: u8,
+    pub model_number: Option<&'static str>, // eg. B147, without the region prefix
+    pub capacity_mb: Option<u32>,
+    pub color: Option<Color>,
+}
+
+use Color::*;
+use Model::*;
+
+// Model number, model, generation, capacity in MB, colour
+#[rustfmt::skip]
+const MODELS: &[(&str, Model, u8, u32, Color)] = &[
+    ("8513", Regular, 1, 5_000, White),
+    ("8541", Regular, 1, 5_000, White),
+    ("8697", Regular, 1, 5_000, White),
+    ("8709", Regular, 1, 10_000, White),
+    ("8737", Regular, 2, 10_000, White),
+    ("8740", Regular, 2, 10_000, White),
+    ("8738", Regular, 2, 20_000, White),
+    ("8741", Regular, 2, 20_000, White),
+    ("8976", Regular, 3, 10_000, White),
+    ("8946", Regular, 3, 15_000, White),
+    ("9460", Regular, 3, 15_000, White),
+    ("9244", Regular, 3, 20_000, White),
+    ("8948", Regular, 3, 30_000, White),
+    ("9245", Regular, 3, 40_000, White),
+    ("9282", Regular, 4, 20_000, White),
+    ("9787", Regular, 4, 25_000, Black), // U2
+    ("9268", Regular, 4, 40_000, White),
+    ("A079", Photo, 4, 20_000, White),
+    ("A127", Photo, 4, 20_000, Black), // U2
+    ("9829", Photo, 4, 30_000, White),
+    ("9585", Photo, 4, 40_000, White),
+    ("9830", Photo, 4, 60_000, White),
+    ("9586", Photo, 4, 60_000, White),
+    ("9160", Mini, 1, 4_000, Silver),
+    ("9436", Mini, 1, 4_000, Blue),
+    ("9435", Mini, 1, 4_000, Pink),
+    ("9434", Mini, 1, 4_000, Green),
+    ("9437", Mini, 1, 4_000, Gold),
+    ("9800", Mini, 2, 4_000, Silver),
+    ("9802", Mini, 2, 4_000, Blue),
+    ("9804", Mini, 2, 4_000, Pink),
+    ("9806", Mini, 2, 4_000, Green),
+    ("9801", Mini, 2, 6_000, Silver),
+    ("9803", Mini, 2, 6_000, Blue),
+    ("9805", Mini, 2, 6_000, Pink),
+    ("9807", Mini, 2, 6_000, Green),
+    ("9724", Shuffle, 1, 512, White),
+    ("9725", Shuffle, 1, 1_000, White),
+    ("A546", Shuffle, 2, 1_000, Silver),
+    ("A947", Shuffle, 2, 1_000, Pink),
+    ("A949", Shuffle, 2, 1_000, Blue),
+    ("A951", Shuffle, 2, 1_000, Green),
+    ("A953", Shuffle, 2, 1_000, Orange),
+    ("B225", Shuffle, 2, 1_000, Silver),
+    ("B233", Shuffle, 2, 1_000, Purple),
+    ("B867", Shuffle, 3, 4_000, Silver),
+    ("C164", Shuffle, 3, 4_000, Black),
+    ("C306", Shuffle, 3, 2_000, Silver),
+    ("C584", Shuffle, 4, 2_000, Silver),
+    ("C585", Shuffle, 4, 2_000, Pink),
+    ("A350", Nano, 1, 1_000, White),
+    ("A352", Nano, 1, 1_000, Black),
+    ("A004", Nano, 1, 2_000, White),
+    ("A099", Nano, 1, 2_000, Black),
+    ("A005", Nano, 1, 4_000, White),
+    ("A107", Nano, 1, 4_000, Black),
+    ("A477", Nano, 2, 2_000, Silver),
+    ("A426", Nano, 2, 4_000, Silver),
+    ("A428", Nano, 2, 4_000, Blue),
+    ("A487", Nano, 2, 4_000, Green),
+    ("A489", Nano, 2, 4_000, Pink),
+    ("A725", Nano, 2, 4_000, Red),
+    ("A726", Nano, 2, 8_000, Red),
+    ("A497", Nano, 2, 8_000, Black),
+    ("A978", Nano, 3, 4_000, Silver),
+    ("A980", Nano, 3, 8_000, Silver),
+    ("B261", Nano, 3, 8_000, Black),
+    ("B249", Nano, 3, 8_000, Blue),
+    ("B253", Nano, 3, 8_000, Green),
+    ("B257", Nano, 3, 8_000, Red),
+    ("B480", Nano, 4, 4_000, Silver),
+    ("B598", Nano, 4, 8_000, Silver),
+    ("B903", Nano, 4, 16_000, Silver),
+    ("C027", Nano, 5, 8_000, Silver),
+    ("C031", Nano, 5, 8_000, Black),
+    ("C034", Nano, 5, 8_000, Purple),
+    ("C060", Nano, 5, 16_000, Silver),
+    ("C525", Nano, 6, 8_000, Silver),
+    ("C688", Nano, 6, 16_000, Silver),
+    ("A002", Video, 1, 30_000, White),
+    ("A146", Video, 1, 30_000, Black),
+    ("A452", Video, 1, 30_000, Black), // U2
+    ("A003", Video, 1, 60_000, White),
+    ("A147", Video, 1, 60_000, Black),
+    ("A444", Video, 2, 30_000, White),
+    ("A446", Video, 2, 30_000, Black),
+    ("A664", Video, 2, 30_000, Black), // U2
+    ("A448", Video, 2, 80_000, White),
+    ("A450", Video, 2, 80_000, Black),
+    ("B029", Classic, 1, 80_000, Silver),
+    ("B147", Classic, 1, 80_000, Black),
+    ("B145", Classic, 1, 160_000, Silver),
+    ("B150", Classic, 1, 160_000, Black),
+    ("B562", Classic, 2, 120_000, Silver),
+    ("B565", Classic, 2, 120_000, Black),
+    ("C293", Classic, 3, 160_000, Silver),
+    ("C297", Classic, 3, 160_000, Black),
+];
+
+// Last three characters of the serial number, model number
+#[rustfmt::skip]
+const SERIAL_SUFFIXES: &[(&str, &str)] = &[
+    ("LG6", "8541"), ("NAM", "8541"), ("MJ2", "8541"), ("ML1", "8709"), ("MME", "8709"),
+    ("MMB", "8737"), ("MMC", "8738"), ("NGE", "8740"), ("NGH", "8740"), ("MMF", "8741"),
+    ("NLW", "8946"), ("NRH", "8976"), ("QQF", "9460"), ("PQ5", "9244"), ("PNT", "9244"),
+    ("NLY", "8948"), ("NM7", "8948"), ("PNU", "9245"), ("PS9", "9282"), ("Q8U", "9282"),
+    ("V9V", "9787"), ("S2X", "9787"), ("PQ7", "9268"), ("TDU", "A079"), ("TDS", "A079"),
+    ("TM2", "A127"), ("SAZ", "9830"), ("SB1", "9830"), ("SAY", "9829"), ("R5Q", "9585"),
+    ("R5R", "9586"), ("R5T", "9586"), ("PFW", "9160"), ("PRC", "9160"), ("QKL", "9436"),
+    ("QKQ", "9436"), ("QKK", "9435"), ("QKP", "9435"), ("QKJ", "9434"), ("QKN", "9434"),
+    ("QKM", "9437"), ("QKR", "9437"), ("S41", "9800"), ("S4C", "9800"), ("S43", "9802"),
+    ("S45", "9804"), ("S47", "9806"), ("S4J", "9806"), ("S42", "9801"), ("S44", "9803"),
+    ("S48", "9807"), ("RS9", "9724"), ("QGV", "9724"), ("TSX", "9724"), ("PFV", "9725"),
+    ("R80", "9725"), ("RSA", "9725"), ("TSY", "9725"), ("VTE", "A546"), ("VTF", "A546"),
+    ("XQ5", "A947"), ("XQS", "A947"), ("XQV", "A949"), ("XQX", "A949"), ("XQY", "A951"),
+    ("XR1", "A953"), ("YX6", "B225"), ("YX9", "B225"), ("YXA", "B233"), ("TJT", "A099"),
+    ("TJU", "A099"), ("TK2", "A107"), ("TK3", "A107"), ("VQ5", "A477"), ("VQ6", "A477"),
+    ("V8T", "A350"), ("V8U", "A350"), ("V8W", "A352"), ("V8X", "A352"), ("VQH", "A005"),
+    ("VQJ", "A005"), ("V9K", "A004"), ("V9L", "A004"), ("WL2", "A725"), ("WL3", "A725"),
+    ("X9A", "A726"), ("X9B", "A726"), ("SZ9", "A002"), ("WEC", "A444"), ("WEE", "A446"),
+    ("WEF", "A446"), ("WEG", "A448"), ("WEH", "A448"), ("WEL", "A450"), ("TXK", "A146"),
+    ("TXL", "A146"), ("TXM", "A146"), ("TXN", "A147"), ("TXP", "A147"), ("Y5N", "B029"),
+    ("YMV", "B147"), ("YMU", "B145"), ("YMX", "B150"), ("2C5", "B562"), ("2C7", "B565"),
+    ("9ZS", "C293"), ("9ZU", "C297"), ("Y0P", "A978"), ("Y0R", "A980"), ("YXR", "B249"),
+    ("YXV", "B257"), ("YXT", "B253"), ("YXX", "B261"), ("1P1", "B480"), ("1PK", "B598"),
+    ("71V", "C027"), ("71Y", "C031"), ("72Q", "C034"),
+];
+
+// SysInfoExtended FamilyID of the devices that report one, generation 0 when the
+// family spans several
+const FAMILIES: &[(&str, Model, u8)] = &[
+    ("11", Classic, 0),
+    ("12", Nano, 3),
+    ("15", Nano, 4),
+    ("16", Nano, 5),
+    ("17", Nano, 6),
+    ("18", Nano, 7),
+];
+
+impl ModelInfo {
+    /// Looks up a model number, with or without the region prefix SysInfo writes
+    /// (xB147, MA002).
+    pub fn from_model_number(model_number: &str) -> Option<Self> {
+        let model_number = match model_number.len() {
+            5 => model_number.get(1..)?,
+            _ => model_number,
+        };
+
+        MODELS
+            .iter()
+            .find(|(number, ..)| number.eq_ignore_ascii_case(model_number))
+            .map(
+                |&(number, model, generation, capacity_mb, color)| ModelInfo {
+                    model,
+                    generation,
+                    model_number: Some(number),
+                    capacity_mb: Some(capacity_mb),
+                    color: Some(color),
+                },
+            )
+    }
+
+    /// Looks up the model from the last three characters of the serial number.
+    pub fn from_serial(serial_num: &str) -> Option<Self> {
+        let suffix = serial_num.get(serial_num.len().checked_sub(3)?..)?;
+
+        SERIAL_SUFFIXES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(suffix))
+            .and_then(|(_, model_number)| Self::from_model_number(model_number))
+    }
+
+    /// Identifies a device from what its SysInfo files report, trying the most
+    /// specific first: model number, serial number, then FamilyID and ProductType
+    /// which only give the model and generation.
+    pub fn identify(
+        model_number: Option<&str>,
+        serial_num: Option<&str>,
+        family_id: Option<&str>,
+        product_type: Option<&str>,
+    ) -> Option<Self> {
+        let coarse = |model, generation| ModelInfo {
+            model,
+            generation,
+            model_number: None,
+            capacity_mb: None,
+            color: None,
+        };
+
+        model_number
+            .and_then(Self::from_model_number)
+            .or_else(|| serial_num.and_then(Self::from_serial))
+            .or_else(|| {
+                let family_id = family_id?;
+                FAMILIES
+                    .iter()
+                    .find(|(id, ..)| *id == family_id)
+                    .map(|&(_, model, generation)| coarse(model, generation))
+            })
+            .or_else(|| {
+                // iPod1,1 to iPod5,1 for the touch
+                let generation = product_type?.strip_prefix("iPod")?.split(',').next()?;
+                Some(coarse(Touch, generation.parse().ok()?))
+            })
+    }
+
+    pub fn supports_video(&self) -> bool {
+        match self.model {
+            Video | Classic | Touch => true,
+            Nano => self.generation >= 3,
+            _ => false,
+        }
+    }
+
+    pub fn supports_photos(&self) -> bool {
+        match self.model {
+            Photo | Video | Classic | Nano | Touch => true,
+            Regular | Mini | Shuffle => false,
+        }
+    }
+
+    pub fn supports_podcasts(&self) -> bool {
+        match self.model {
+            Regular => self.generation >= 3,
+            Shuffle => self.generation >= 2,
+            _ => true,
+        }
+    }
+
+    pub fn supports_gapless(&self) -> bool {
+        match self.model {
+            Video => self.generation >= 2,
+            Classic | Touch => true,
+            Nano => self.generation >= 2,
+            _ => false,
+        }
+    }
+
+    pub fn supports_chapters(&self) -> bool {
+        match self.model {
+            Regular => self.generation >= 3,
+            Shuffle => false,
+            _ => true,
+        }
+    }
+
+    /// The checksum the firmware checks before accepting an iTunesDB.
+    pub fn hashing_scheme(&self) -> HashingScheme {
+        match (self.model, self.generation) {
+            (Classic, _) | (Nano, 3 | 4) => HashingScheme::Hash58,
+            (Nano, 5) | (Touch, 1..=3) => HashingScheme::Hash72,
+            (Nano, 6..) | (Touch, 4..) => HashingScheme::HashAB,
+            _ => HashingScheme::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Model, ModelInfo};
+    use crate::HashingScheme;
+
+    #[test]
+    fn identify() {
+        let classic = ModelInfo::from_serial("8K9466E39ZU").unwrap();
+        assert_eq!(classic.model, Model::Classic);
+        assert_eq!(classic.generation, 3);
+        assert_eq!(classic.capacity_mb, Some(160_000));
+        assert_eq!(classic.color, Some(Color::Black));
+        assert_eq!(classic.hashing_scheme(), HashingScheme::Hash58);
+
+        let nano = ModelInfo::from_model_number("xB261").unwrap();
+        assert_eq!((nano.model, nano.generation), (Model::Nano, 3));
+        assert!(nano.supports_video());
+
+        let shuffle = ModelInfo::identify(Some("MA947"), None, None, None).unwrap();
+        assert_eq!(shuffle.model, Model::Shuffle);
+        assert!(!shuffle.supports_chapters());
+        assert_eq!(shuffle.hashing_scheme(), HashingScheme::None);
+
+        let nano = ModelInfo::identify(None, Some("unknown"), Some("16"), None).unwrap();
+        assert_eq!((nano.model, nano.generation), (Model::Nano, 5));
+        assert_eq!(nano.capacity_mb, None);
+        assert_eq!(nano.hashing_scheme(), HashingScheme::Hash72);
+
+        let touch = ModelInfo::identify(None, None, None, Some("iPod4,1")).unwrap();
+        assert_eq!((touch.model, touch.generation), (Model::Touch, 4));
+        assert_eq!(touch.hashing_scheme(), HashingScheme::HashAB);
+
+        assert_eq!(ModelInfo::identify(None, Some("XX"), None, None), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// One line of /proc/self/mountinfo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MountEntry {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String, // eg. /dev/sdb2
+}
+
+/// Undoes the octal escapes (`\040` for space) the kernel uses in mount paths.
+fn unescape(field: &str) -> String {
+    let mut out = Vec::new();
+    let bytes = field.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 4)) {
+            (b'\\', Some(octal)) if octal.iter().all(|b| (b'0'..=b'7').contains(b)) => {
+                out.push(
+                    octal
+                        .iter()
+                        .fold(0u8, |acc, b| acc.wrapping_mul(8) + (b - b'0')),
+                );
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub(crate) fn parse_mountinfo<R: BufRead>(reader: R) -> anyhow::Result<Vec<MountEntry>> {
+    let mut entries = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split(' ').collect();
+
+        // Optional fields end with a lone "-", followed by fs type, source and options
+        let Some(separator) = fields.iter().position(|field| *field == "-") else {
+            continue;
+        };
+        let (Some(mount_point), Some(fs_type), Some(source)) = (
+            fields.get(4),
+            fields.get(separator + 1),
+            fields.get(separator + 2),
+        ) else {
+            continue;
+        };
+
+        entries.push(MountEntry {
+            mount_point: PathBuf::from(unescape(mount_point)),
+            fs_type: fs_type.to_string(),
+            source: unescape(source),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// The mount `path` lives on: the entry with the longest mount point containing it.
+pub(crate) fn mount_of<'a>(entries: &'a [MountEntry], path: &Path) -> Option<&'a MountEntry> {
+    entries
+        .iter()
+        .filter(|entry| path.starts_with(&entry.mount_point))
+        .max_by_key(|entry| entry.mount_point.as_os_str().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    const MOUNTINFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+120 22 8:18 / /run/media/user/JOHN\\040S\\040IPOD rw,nosuid shared:70 - vfat /dev/sdb2 rw,fmask=0022
+121 22 8:34 / /mnt/classic rw,nosuid - hfsplus /dev/sdc2 rw
+";
+
+    #[test]
+    fn parse_mountinfo() {
+        let entries = super::parse_mountinfo(MOUNTINFO.as_bytes()).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[1].mount_point,
+            Path::new("/run/media/user/JOHN S IPOD")
+        );
+        assert_eq!(entries[1].fs_type, "vfat");
+        assert_eq!(entries[1].source, "/dev/sdb2");
+
+        let entry = super::mount_of(&entries, Path::new("/mnt/classic/iPod_Control")).unwrap();
+        assert_eq!(entry.source, "/dev/sdc2");
+        assert_eq!(
+            super::mount_of(&entries, Path::new("/home"))
+                .unwrap()
+                .source,
+            "/dev/nvme0n1p2"
+        );
+    }
+}
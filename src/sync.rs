@@ -0,0 +1,486 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Context;
+
+use crate::{
+    iPod,
+    media::{fingerprint, soundcheck::ReplayGain},
+};
+
+// Which source file each synced track came from, next to the iTunesDB
+const SYNC_STATE_PATH: &str = "iPod_Control/iTunes/SyncState";
+
+// Files with other extensions in the source folder are ignored
+const EXTENSIONS: [&str; 3] = ["mp3", "m4a", "m4b"];
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    pub dry_run: bool, // only compute the plan, don't touch the device
+    pub replay_gain: ReplayGain,
+    pub remove_unmanaged: bool, // also remove tracks no sync added, ie. ones iTunes added
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    /// A file no track was synced from yet
+    Add { source: PathBuf },
+    /// The tags of the file changed or it moved, the audio is the same
+    UpdateMetadata { track_id: u32, source: PathBuf },
+    /// The audio of the file changed
+    ReplaceFile { track_id: u32, source: PathBuf },
+    /// The file the track was synced from is gone
+    Remove { track_id: u32 },
+}
+
+/// What a sync does to make the device match the source folder.
+#[derive(Debug, Clone, Default)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    pub skipped: Vec<PathBuf>, // source files that couldn't be read, left out of the sync
+    root: PathBuf,
+    fingerprints: HashMap<PathBuf, [u8; 20]>, // computed while planning, reused when applying
+}
+
+impl SyncPlan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// A track added by a sync and the source file it was last synced from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SyncedTrack {
+    track_id: u32,
+    fingerprint: [u8; 20], // fingerprint::audio_hash of the file
+    size: u64,
+    modified: u64,   // nanoseconds since the Unix epoch
+    source: PathBuf, // relative to the source folder
+}
+
+/// One synced track per line: id, fingerprint, size, modification time and source
+/// path, separated by tabs.
+fn read_state(mount: &Path) -> anyhow::Result<Vec<SyncedTrack>> {
+    let text = match fs::read_to_string(mount.join(SYNC_STATE_PATH)) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("failed to read the sync state"),
+    };
+
+    let parse = |line: &str| -> Option<SyncedTrack> {
+        let mut fields = line.splitn(5, '\t');
+        Some(SyncedTrack {
+            track_id: fields.next()?.parse().ok()?,
+            fingerprint: hex::decode(fields.next()?).ok()?.try_into().ok()?,
+            size: fields.next()?.parse().ok()?,
+            modified: fields.next()?.parse().ok()?,
+            source: PathBuf::from(fields.next()?),
+        })
+    };
+
+    Ok(text.lines().filter_map(parse).collect())
+}
+
+/// The source file of each track a sync added, relative to the synced folder.
+pub(crate) fn synced_sources(mount: &Path) -> anyhow::Result<Vec<(u32, PathBuf)>> {
+    Ok(read_state(mount)?
+        .into_iter()
+        .map(|synced| (synced.track_id, synced.source))
+        .collect())
+}
+
+fn write_state(mount: &Path, state: &[SyncedTrack]) -> anyhow::Result<()> {
+    let text: String = state
+        .iter()
+        .map(|synced| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                synced.track_id,
+                hex::encode(synced.fingerprint),
+                synced.size,
+                synced.modified,
+                synced.source.display()
+            )
+        })
+        .collect();
+
+    let path = mount.join(SYNC_STATE_PATH);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, text)?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+/// Audio files under `root`, relative to it, skipping hidden files and folders.
+fn scan(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(root.join(&dir))
+            .with_context(|| format!("failed to read {}", root.join(&dir).display()))?;
+
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let relative = dir.join(&name);
+            if entry.file_type()?.is_dir() {
+                dirs.push(relative);
+            } else if relative
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+            {
+                found.push(relative);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Size and modification time, to skip hashing files that didn't change.
+fn stamp(path: &Path) -> anyhow::Result<(u64, u64)> {
+    let metadata =
+        fs::metadata(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or(0);
+
+    Ok((metadata.len(), modified))
+}
+
+/// Matches the files under `root` to the tracks synced from them, first by path, then
+/// by fingerprint for files that moved, and works out what changed. Files that can't
+/// be read are skipped, the tracks synced from them are kept as they are.
+pub(crate) fn plan(ipod: &iPod, root: &Path, options: &SyncOptions) -> anyhow::Result<SyncPlan> {
+    let master = ipod.master()?;
+    let track_ids: HashSet<u32> = master.tracks().map(|track| track.unique_id()).collect();
+
+    // Tracks removed from the device since the last sync are added again
+    let state: Vec<SyncedTrack> = read_state(&ipod.path)?
+        .into_iter()
+        .filter(|synced| track_ids.contains(&synced.track_id))
+        .collect();
+
+    let mut plan = SyncPlan {
+        root: root.to_path_buf(),
+        ..Default::default()
+    };
+    let mut matched = HashSet::new();
+    let mut moved = Vec::new();
+
+    let by_path: HashMap<&Path, &SyncedTrack> = state
+        .iter()
+        .map(|synced| (synced.source.as_path(), synced))
+        .collect();
+
+    for relative in scan(root)? {
+        let source = root.join(&relative);
+        let Some(synced) = by_path.get(relative.as_path()) else {
+            moved.push(source);
+            continue;
+        };
+        matched.insert(synced.track_id);
+
+        let changed = stamp(&source).map(|stamp| stamp != (synced.size, synced.modified));
+        if let Ok(false) = changed {
+            continue;
+        }
+        let Some(hash) = changed.and_then(|_| fingerprint::audio_hash(&source)).ok() else {
+            plan.skipped.push(source);
+            continue;
+        };
+        plan.fingerprints.insert(source.clone(), hash);
+
+        let track_id = synced.track_id;
+        plan.actions.push(match hash == synced.fingerprint {
+            true => SyncAction::UpdateMetadata { track_id, source },
+            false => SyncAction::ReplaceFile { track_id, source },
+        });
+    }
+
+    let mut by_fingerprint: HashMap<[u8; 20], &SyncedTrack> = state
+        .iter()
+        .filter(|synced| !matched.contains(&synced.track_id))
+        .map(|synced| (synced.fingerprint, synced))
+        .collect();
+
+    for source in moved {
+        let Ok(hash) = fingerprint::audio_hash(&source) else {
+            plan.skipped.push(source);
+            continue;
+        };
+        plan.fingerprints.insert(source.clone(), hash);
+
+        plan.actions.push(match by_fingerprint.remove(&hash) {
+            Some(synced) => {
+                matched.insert(synced.track_id);
+                SyncAction::UpdateMetadata {
+                    track_id: synced.track_id,
+                    source,
+                }
+            }
+            None => SyncAction::Add { source },
+        });
+    }
+
+    let managed: HashSet<u32> = state.iter().map(|synced| synced.track_id).collect();
+    for track in master.tracks() {
+        let track_id = track.unique_id();
+        let gone = managed.contains(&track_id) && !matched.contains(&track_id);
+        if gone || (options.remove_unmanaged && !managed.contains(&track_id)) {
+            plan.actions.push(SyncAction::Remove { track_id });
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Carries out the plan, saves the database and records where the tracks came from.
+/// The changes go through a transaction so a failure partway leaves the device as it
+/// was: copied files are deleted again, and the files of removed or replaced tracks are
+/// only deleted once the database and the sync state are written.
+pub(crate) fn apply(ipod: &mut iPod, plan: &SyncPlan, options: &SyncOptions) -> anyhow::Result<()> {
+    let mount = ipod.path.clone();
+    let mut state = read_state(&mount)?;
+    let mut transaction = ipod.transaction();
+
+    for action in &plan.actions {
+        let (track_id, source) = match action {
+            SyncAction::Add { source } => {
+                (transaction.add_track(source, options.replay_gain)?, source)
+            }
+            SyncAction::UpdateMetadata { track_id, source } => {
+                transaction.update_track(*track_id, source, options.replay_gain)?;
+                (*track_id, source)
+            }
+            SyncAction::ReplaceFile { track_id, source } => {
+                transaction.replace_file(*track_id, source, options.replay_gain)?;
+                (*track_id, source)
+            }
+            SyncAction::Remove { track_id } => {
+                transaction.remove_track(*track_id)?;
+                state.retain(|synced| synced.track_id != *track_id);
+                continue;
+            }
+        };
+
+        let fingerprint = match plan.fingerprints.get(source) {
+            Some(hash) => *hash,
+            None => fingerprint::audio_hash(source)?,
+        };
+        let (size, modified) = stamp(source)?;
+        let relative = source.strip_prefix(&plan.root).unwrap_or(source);
+
+        state.retain(|synced| synced.track_id != track_id && synced.source != relative);
+        state.push(SyncedTrack {
+            track_id,
+            fingerprint,
+            size,
+            modified,
+            source: relative.to_path_buf(),
+        });
+    }
+
+    transaction.commit_then(|| write_state(&mount, &state))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{SyncAction, SyncOptions};
+    use crate::{
+        iPod,
+        media::{mp3, mp4},
+    };
+
+    fn sync(mount: &Path, source: &Path, dry_run: bool) -> Vec<SyncAction> {
+        let options = SyncOptions {
+            dry_run,
+            ..Default::default()
+        };
+        let mut ipod = iPod::open(mount).unwrap();
+        ipod.sync(source, &options).unwrap().actions
+    }
+
+    fn track_count(mount: &Path) -> usize {
+        iPod::open(mount)
+            .unwrap()
+            .master()
+            .unwrap()
+            .tracks()
+            .count()
+    }
+
+    #[test]
+    fn sync_folder() {
+        let mount = crate::tests::device();
+        let source = tempfile::tempdir().unwrap();
+        let a = source.path().join("a.mp3");
+        let b = source.path().join("Album/b.m4a");
+        fs::create_dir(source.path().join("Album")).unwrap();
+        fs::write(&a, mp3::tests::tagged_mp3()).unwrap();
+        fs::write(&b, mp4::tests::m4a(b"mp4a")).unwrap();
+        fs::write(source.path().join("cover.jpg"), b"not audio").unwrap();
+
+        let adds = vec![
+            SyncAction::Add { source: b.clone() },
+            SyncAction::Add { source: a.clone() },
+        ];
+
+        // A dry run only plans
+        assert_eq!(sync(mount.path(), source.path(), true), adds);
+        assert_eq!(track_count(mount.path()), 3);
+        assert!(!mount.path().join(super::SYNC_STATE_PATH).exists());
+
+        assert_eq!(sync(mount.path(), source.path(), false), adds);
+        assert_eq!(track_count(mount.path()), 5);
+        assert!(sync(mount.path(), source.path(), true).is_empty());
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        let added = ipod
+            .master()
+            .unwrap()
+            .tracks()
+            .find(|track| track.title().as_deref() == Some("Ace of Spades"))
+            .unwrap();
+        let a_id = added.unique_id();
+        let a_path = added.location_path(mount.path()).unwrap();
+        assert_eq!(fs::read(&a_path).unwrap(), fs::read(&a).unwrap());
+
+        // Retagging updates the track, moving a file is found by its fingerprint
+        fs::write(&a, mp3::tests::mp3(&[], 100, &[])).unwrap();
+        let c = source.path().join("c.m4a");
+        fs::rename(&b, &c).unwrap();
+
+        let actions = sync(mount.path(), source.path(), false);
+        assert!(matches!(
+            &actions[..],
+            [
+                SyncAction::UpdateMetadata { track_id, source: first },
+                SyncAction::UpdateMetadata { source: second, .. },
+            ] if *track_id == a_id && first == &a && second == &c
+        ));
+        assert!(sync(mount.path(), source.path(), true).is_empty());
+
+        // New audio replaces the file, a deleted file removes its track
+        fs::write(&a, mp3::tests::mp3(&[], 99, &[])).unwrap();
+        fs::remove_file(&c).unwrap();
+
+        let actions = sync(mount.path(), source.path(), false);
+        assert!(matches!(
+            &actions[..],
+            [SyncAction::ReplaceFile { track_id, .. }, SyncAction::Remove { .. }]
+                if *track_id == a_id
+        ));
+        assert_eq!(track_count(mount.path()), 4);
+        assert!(!a_path.exists());
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        let replaced = ipod
+            .master()
+            .unwrap()
+            .tracks()
+            .find(|track| track.unique_id() == a_id)
+            .unwrap();
+        let replaced_path = replaced.location_path(mount.path()).unwrap();
+        assert_eq!(fs::read(replaced_path).unwrap(), fs::read(&a).unwrap());
+
+        // Tracks iTunes added are only removed when asked to
+        let options = SyncOptions {
+            remove_unmanaged: true,
+            ..Default::default()
+        };
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let plan = ipod.sync(source.path(), &options).unwrap();
+        assert_eq!(plan.actions.len(), 3);
+        assert_eq!(track_count(mount.path()), 1);
+    }
+
+    #[test]
+    fn unreadable_files_are_skipped() {
+        let mount = crate::tests::device();
+        let source = tempfile::tempdir().unwrap();
+        let a = source.path().join("a.mp3");
+        fs::write(&a, mp3::tests::tagged_mp3()).unwrap();
+        sync(mount.path(), source.path(), false);
+
+        // A synced file that got truncated keeps its track
+        fs::write(&a, b"ID3").unwrap();
+        let b = source.path().join("b.mp3");
+        fs::write(&b, b"").unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let plan = ipod
+            .sync(
+                source.path(),
+                &SyncOptions {
+                    dry_run: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(plan.is_empty());
+        assert_eq!(plan.skipped, vec![a, b]);
+    }
+
+    fn music_files(mount: &Path) -> Vec<std::path::PathBuf> {
+        let mut files = crate::files::music_files(mount);
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn failed_sync_changes_nothing() {
+        let mount = crate::tests::device();
+        let source = tempfile::tempdir().unwrap();
+        let a = source.path().join("a.mp3");
+        let b = source.path().join("b.m4a");
+        fs::write(&a, mp3::tests::tagged_mp3()).unwrap();
+        fs::write(&b, mp4::tests::m4a(b"mp4a")).unwrap();
+        sync(mount.path(), source.path(), false);
+
+        let itunesdb = fs::read(mount.path().join(crate::ITUNESDB_PATH)).unwrap();
+        let state = fs::read(mount.path().join(super::SYNC_STATE_PATH)).unwrap();
+        let files = music_files(mount.path());
+        assert_eq!(files.len(), 2);
+
+        // A replaced file and a removed track, then a file that is gone by the time
+        // it is added
+        fs::write(&a, mp3::tests::mp3(&[], 99, &[])).unwrap();
+        fs::remove_file(&b).unwrap();
+        let z = source.path().join("z.mp3");
+        fs::write(&z, mp3::tests::tagged_mp3()).unwrap();
+
+        let mut ipod = iPod::open(mount.path()).unwrap();
+        let options = SyncOptions::default();
+        let plan = super::plan(&ipod, source.path(), &options).unwrap();
+        assert_eq!(plan.actions.len(), 3);
+        fs::remove_file(&z).unwrap();
+        assert!(super::apply(&mut ipod, &plan, &options).is_err());
+
+        assert_eq!(
+            fs::read(mount.path().join(crate::ITUNESDB_PATH)).unwrap(),
+            itunesdb
+        );
+        assert_eq!(
+            fs::read(mount.path().join(super::SYNC_STATE_PATH)).unwrap(),
+            state
+        );
+        assert_eq!(music_files(mount.path()), files);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, io::BufRead};
+
+use quick_xml::{events::Event, Reader};
+
+/// Top level keys of the SysInfoExtended plist, with their string or integer values.
+/// Nested dictionaries and arrays are skipped.
+pub(crate) fn parse_extended<R: BufRead>(reader: R) -> anyhow::Result<HashMap<String, String>> {
+    let mut reader = Reader::from_reader(reader);
+    reader.config_mut().trim_text(true);
+
+    let mut buf = Vec::<u8>::new();
+    let mut values = HashMap::new();
+    let mut depth = 0usize;
+    let mut in_key = false;
+    let mut key = None::<String>;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(event) => match event.name().as_ref() {
+                b"dict" | b"array" => depth += 1,
+                b"key" => in_key = true,
+                _ => {}
+            },
+            Event::End(event) => match event.name().as_ref() {
+                b"dict" | b"array" => {
+                    depth = depth.saturating_sub(1);
+                    key = None;
+                }
+                b"key" => in_key = false,
+                _ => {}
+            },
+            Event::Text(event) if depth == 1 => {
+                let text = event.unescape()?.to_string();
+
+                if in_key {
+                    key = Some(text);
+                } else if let Some(key) = key.take() {
+                    values.insert(key, text);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(values)
+}
+
+/// Keys and values of the plain SysInfo file, one `Key: Value` pair per line.
+pub(crate) fn parse_sysinfo<R: BufRead>(reader: R) -> anyhow::Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+
+    for line in reader.lines() {
+        if let Some((key, value)) = line?.split_once(':') {
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::BufReader, path::PathBuf};
+
+    #[test]
+    fn parse_extended() {
+        let path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/db/itunesdb/sample/ExtendedSysInfoXml");
+
+        let values = super::parse_extended(BufReader::new(File::open(&path).unwrap())).unwrap();
+
+        assert_eq!(values["FireWireGUID"], "000A270013E10993");
+        assert_eq!(values["SerialNumber"], "8K9466E39ZU");
+        assert_eq!(values["FamilyID"], "11");
+    }
+
+    #[test]
+    fn parse_sysinfo() {
+        let sysinfo =
+            "BoardHwName: iPod Q98\nModelNumStr: xB147\nFirewireGuid: 0x000A270013E10993\n";
+
+        let values = super::parse_sysinfo(sysinfo.as_bytes()).unwrap();
+
+        assert_eq!(values["ModelNumStr"], "xB147");
+        assert_eq!(values["FirewireGuid"], "0x000A270013E10993");
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    db::itunesdb::{self, check, Master, Playlist, Record, Track},
+    files, iPod,
+    media::tags,
+    PodcastEpisode, ReplayGain,
+};
+
+/// Text fields of a track that can be edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackText {
+    Title,
+    Album,
+    Artist,
+    Genre,
+    Comment,
+    Composer,
+    Grouping,
+    AlbumArtist,
+}
+
+impl TrackText {
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            TrackText::Title => 1,
+            TrackText::Album => 3,
+            TrackText::Artist => 4,
+            TrackText::Genre => 5,
+            TrackText::Comment => 8,
+            TrackText::Composer => 12,
+            TrackText::Grouping => 13,
+            TrackText::AlbumArtist => 22,
+        }
+    }
+}
+
+/// Changes to the database staged on a copy of it, see [`iPod::transaction`]. Nothing
+/// is written until [`Transaction::commit`]; dropping the transaction rolls it back.
+pub struct Transaction<'a> {
+    ipod: &'a mut iPod,
+    itunesdb: Record,
+    copied: Vec<PathBuf>,  // files copied to the device, deleted on rollback
+    removed: Vec<PathBuf>, // files of removed tracks, deleted on commit
+    done: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(ipod: &'a mut iPod) -> Self {
+        Transaction {
+            itunesdb: ipod.itunesdb.clone(),
+            ipod,
+            copied: Vec::new(),
+            removed: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn master(&self) -> anyhow::Result<&Master> {
+        match &self.itunesdb {
+            Record::mhbd(master) => Ok(master),
+            _ => bail!("iTunesDB does not start with an mhbd record"),
+        }
+    }
+
+    fn master_mut(&mut self) -> anyhow::Result<&mut Master> {
+        match &mut self.itunesdb {
+            Record::mhbd(master) => Ok(master),
+            _ => bail!("iTunesDB does not start with an mhbd record"),
+        }
+    }
+
+    fn track_mut(&mut self, track_id: u32) -> anyhow::Result<&mut Track> {
+        match self.master_mut()?.track_mut(track_id) {
+            Some(track) => Ok(track),
+            None => bail!("no track with id {track_id}"),
+        }
+    }
+
+    fn playlist_mut(&mut self, playlist_id: u64) -> anyhow::Result<Vec<&mut Playlist>> {
+        let playlists = self.master_mut()?.playlist_mut(playlist_id);
+        if playlists.is_empty() {
+            bail!("no playlist with id {playlist_id:016X}");
+        }
+        Ok(playlists)
+    }
+
+    /// Copies the audio file at `source` to the device and stages a track for it.
+    /// Returns the unique id of the new track.
+    pub fn add_track(
+        &mut self,
+        source: impl AsRef<Path>,
+        replay_gain: ReplayGain,
+    ) -> anyhow::Result<u32> {
+        let source = source.as_ref();
+        let unique_id = self.master()?.next_track_id();
+
+        let mut track = Track::from_file(unique_id, source, replay_gain)
+            .with_context(|| format!("failed to read {}", source.display()))?;
+        let location = files::copy_to_device(&self.ipod.path, source)?;
+        self.copied
+            .push(files::location_path(&self.ipod.path, &location));
+        track.set_string(2, &location);
+
+        self.master_mut()?.add_track(track);
+        Ok(unique_id)
+    }
+
+    /// Copies the audio file at `source` to the device and stages it as an episode of
+    /// `episode.show`, see [`iPod::add_podcast`]. Returns the unique id of the new track.
+    pub fn add_podcast(
+        &mut self,
+        source: impl AsRef<Path>,
+        episode: &PodcastEpisode,
+    ) -> anyhow::Result<u32> {
+        let unique_id = self.add_track(source, ReplayGain::default())?;
+        self.track_mut(unique_id)?.set_podcast(episode);
+        Ok(unique_id)
+    }
+
+    /// Stages removing the track from the database and every playlist. Its file is
+    /// deleted when the transaction commits.
+    pub fn remove_track(&mut self, track_id: u32) -> anyhow::Result<()> {
+        let Some(track) = self.master_mut()?.remove_track(track_id) else {
+            bail!("no track with id {track_id}");
+        };
+
+        if let Some(path) = track.location_path(&self.ipod.path) {
+            self.removed.push(path);
+        }
+        Ok(())
+    }
+
+    /// Stages refilling the track from the tags of `source`, whose audio is the one
+    /// already on the device.
+    pub(crate) fn update_track(
+        &mut self,
+        track_id: u32,
+        source: &Path,
+        replay_gain: ReplayGain,
+    ) -> anyhow::Result<()> {
+        let file = tags::read_audio_file(source)
+            .with_context(|| format!("failed to read {}", source.display()))?;
+        self.track_mut(track_id)?.set_audio_file(&file, replay_gain);
+        Ok(())
+    }
+
+    /// Copies `source` to the device in place of the track's file. The track keeps its
+    /// play counts and ratings, the old file is deleted when the transaction commits.
+    pub(crate) fn replace_file(
+        &mut self,
+        track_id: u32,
+        source: &Path,
+        replay_gain: ReplayGain,
+    ) -> anyhow::Result<()> {
+        let file = tags::read_audio_file(source)
+            .with_context(|| format!("failed to read {}", source.display()))?;
+        let mount = self.ipod.path.clone();
+        let old_path = self.track_mut(track_id)?.location_path(&mount);
+
+        let location = files::copy_to_device(&mount, source)?;
+        self.copied.push(files::location_path(&mount, &location));
+
+        let track = self.track_mut(track_id)?;
+        track.set_audio_file(&file, replay_gain);
+        track.set_string(2, &location);
+        self.removed.extend(old_path);
+        Ok(())
+    }
+
+    pub fn set_text(&mut self, track_id: u32, field: TrackText, text: &str) -> anyhow::Result<()> {
+        self.track_mut(track_id)?.set_string(field.as_u32(), text);
+        Ok(())
+    }
+
+    /// Sets the rating in stars, 0 to 5.
+    pub fn set_rating(&mut self, track_id: u32, stars: u8) -> anyhow::Result<()> {
+        if stars > 5 {
+            bail!("ratings go from 0 to 5 stars, not {stars}");
+        }
+        self.track_mut(track_id)?.set_rating(stars * 20);
+        Ok(())
+    }
+
+    /// Unlinks the track from its artwork. Adding artwork needs an ArtworkDB writer,
+    /// which doesn't exist yet.
+    pub fn remove_artwork(&mut self, track_id: u32) -> anyhow::Result<()> {
+        self.track_mut(track_id)?.clear_artwork();
+        Ok(())
+    }
+
+    /// Stages a new, empty playlist. Returns its persistent id.
+    pub fn create_playlist(&mut self, name: &str) -> anyhow::Result<u64> {
+        let playlist = Playlist::new(name);
+        let playlist_id = playlist.persistent_id();
+
+        self.master_mut()?.add_playlist(playlist);
+        Ok(playlist_id)
+    }
+
+    pub fn delete_playlist(&mut self, playlist_id: u64) -> anyhow::Result<()> {
+        if self
+            .playlist_mut(playlist_id)?
+            .iter()
+            .any(|playlist| playlist.is_master())
+        {
+            bail!("the master playlist can't be deleted");
+        }
+
+        self.master_mut()?.remove_playlist(playlist_id);
+        Ok(())
+    }
+
+    pub fn add_to_playlist(&mut self, playlist_id: u64, track_id: u32) -> anyhow::Result<()> {
+        for playlist in self.playlist_mut(playlist_id)? {
+            playlist.push_track(track_id);
+        }
+        Ok(())
+    }
+
+    pub fn remove_from_playlist(&mut self, playlist_id: u64, track_id: u32) -> anyhow::Result<()> {
+        for playlist in self.playlist_mut(playlist_id)? {
+            playlist.remove_track(track_id);
+        }
+        Ok(())
+    }
+
+    /// Checks the staged database, writes it to the device and deletes the files of
+    /// removed tracks. On failure nothing is written and the transaction rolls back.
+    pub fn commit(self) -> anyhow::Result<()> {
+        self.commit_then(|| Ok(()))
+    }
+
+    /// Like [`Transaction::commit`], running `written` once the database is on the
+    /// device and before the files of removed tracks are deleted. When `written` fails
+    /// those files stay, the database that no longer points at them is kept.
+    pub(crate) fn commit_then(
+        mut self,
+        written: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        check::validate(&self.itunesdb).context("refusing to write an invalid database")?;
+
+        let buf = itunesdb::io::write_database(
+            &mut self.itunesdb,
+            self.ipod.fwid.as_deref(),
+            &self.ipod.signers,
+        )?;
+        self.ipod.write_itunesdb(buf)?;
+
+        self.done = true;
+        std::mem::swap(&mut self.ipod.itunesdb, &mut self.itunesdb);
+
+        written()?;
+
+        self.ipod.removed.append(&mut self.removed);
+        self.ipod.delete_removed();
+
+        Ok(())
+    }
+
+    /// Drops the staged changes and deletes the files copied to the device.
+    pub fn rollback(self) {}
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        for path in &self.copied {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::TrackText;
+    use crate::{iPod, media::mp3, tests::device, ReplayGain};
+
+    fn music_files(mount: &Path) -> usize {
+        fs::read_dir(mount.join(crate::files::MUSIC_DIR))
+            .map(|folders| {
+                folders
+                    .filter_map(Result::ok)
+                    .map(|folder| fs::read_dir(folder.path()).unwrap().count())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    fn source() -> tempfile::NamedTempFile {
+        let source = tempfile::Builder::new().suffix(".mp3").tempfile().unwrap();
+        fs::write(source.path(), mp3::tests::tagged_mp3()).unwrap();
+        source
+    }
+
+    #[test]
+    fn commit() {
+        let mount = device();
+        let source = source();
+        let mut ipod = iPod::open(mount.path()).unwrap();
+
+        let mut transaction = ipod.transaction();
+        let track_id = transaction
+            .add_track(source.path(), ReplayGain::Track)
+            .unwrap();
+        transaction
+            .set_text(track_id, TrackText::Title, "Renamed")
+            .unwrap();
+        transaction.set_rating(track_id, 4).unwrap();
+        let playlist_id = transaction.create_playlist("Loud").unwrap();
+        transaction.add_to_playlist(playlist_id, track_id).unwrap();
+        transaction.remove_track(101).unwrap();
+        transaction.commit().unwrap();
+
+        let ipod = iPod::open(mount.path()).unwrap();
+        let master = ipod.master().unwrap();
+        let ids: Vec<u32> = master.tracks().map(|track| track.unique_id()).collect();
+        assert_eq!(ids, vec![102, 103, track_id]);
+
+        let track = master.tracks().last().unwrap();
+        assert_eq!(track.title().as_deref(), Some("Renamed"));
+
+        let playlist = master
+            .playlists()
+            .find(|playlist| playlist.persistent_id() == playlist_id)
+            .unwrap();
+        assert_eq!(playlist.name().as_deref(), Some("Loud"));
+        assert_eq!(playlist.track_ids().collect::<Vec<_>>(), vec![track_id]);
+        assert!(master
+            .playlists()
+            .all(|playlist| !playlist.track_ids().any(|id| id == 101)));
+        assert_eq!(music_files(mount.path()), 1);
+    }
+
+    #[test]
+    fn rollback() {
+        let mount = device();
+        let source = source();
+        let before = fs::read(mount.path().join(crate::ITUNESDB_PATH)).unwrap();
+        let mut ipod = iPod::open(mount.path()).unwrap();
+
+        let mut transaction = ipod.transaction();
+        transaction
+            .add_track(source.path(), ReplayGain::Track)
+            .unwrap();
+        assert_eq!(music_files(mount.path()), 1);
+        drop(transaction);
+
+        assert_eq!(music_files(mount.path()), 0);
+        assert_eq!(ipod.master().unwrap().tracks().count(), 3);
+
+        // A playlist entry pointing at a missing track fails the commit
+        let mut transaction = ipod.transaction();
+        transaction
+            .add_track(source.path(), ReplayGain::Track)
+            .unwrap();
+        let playlist_id = transaction.create_playlist("Broken").unwrap();
+        transaction.add_to_playlist(playlist_id, 9999).unwrap();
+        assert!(transaction.commit().is_err());
+
+        assert_eq!(music_files(mount.path()), 0);
+        assert_eq!(
+            fs::read(mount.path().join(crate::ITUNESDB_PATH)).unwrap(),
+            before
+        );
+    }
+}
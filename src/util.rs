@@ -2,19 +2,6 @@
 
 use std::io::{Result, Seek, SeekFrom, Write};
 
-pub(crate) fn print_byte_diffs(a: &[u8], b: &[u8]) {
-    let min_len = a.len().min(b.len());
-
-    for i in 0..min_len {
-        if a[i] != b[i] {
-            println!("Byte 0x{:04X}: {:02X} != {:02X}", i, a[i], b[i]);
-        }
-    }
-    if a.len() != b.len() {
-        println!("Length differs: a.len()={} vs b.len()={}", a.len(), b.len());
-    }
-}
-
 pub(crate) struct ByteCounter {
     count: usize,
 }
@@ -58,3 +45,11 @@ impl Seek for ByteCounter {
         }
     }
 }
+
+/// Seconds between the HFS+ epoch (1904-01-01) and the Unix epoch.
+const HFS_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// The current time as the HFS+ timestamp the databases use.
+pub(crate) fn hfs_now() -> u32 {
+    (chrono::Utc::now().timestamp() + HFS_EPOCH_OFFSET) as u32
+}